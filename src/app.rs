@@ -1,9 +1,20 @@
 use std::error;
-use std::fs::{File, OpenOptions};
-use fs2::FileExt;
-use crate::model::Database;
+use std::fmt;
+use crate::model::{Billing, Category, Database};
+use crate::storage::Storage;
+use crate::backup;
+use crate::sync::PendingCommit;
+use crate::error::PtiError;
+use crate::merge;
+use crate::watcher::FileWatcher;
+use crate::keymap::{Key, Keymap, Mode};
+use crate::config::Config;
+use crate::theme::{Theme, parse_color};
+use crate::quickadd;
+use chrono::{DateTime, Local, Utc};
+use std::time::SystemTime;
 use crate::notification::NotificationManager;
-use crate::constants::{TASK_FILE, get_full_path};
+use crate::hooks::{HookEvent, Hooks};
 use ratatui::widgets::TableState;
 use tui_textarea::TextArea;
 
@@ -12,7 +23,7 @@ pub type AppResult<T> = std::result::Result<T, Box<dyn error::Error>>;
 
 /// Application.
 pub struct App<'a> {
-    pub database_file: File,
+    pub storage: Box<dyn Storage>,
 
     pub tablestate: TableState,
     pub textarea: TextArea<'a>,
@@ -20,6 +31,8 @@ pub struct App<'a> {
     pub running: bool,
     
     pub data: Database,
+    /// The database as last loaded or saved, which unsaved changes are merged from.
+    pub base: Database,
 
     pub data_changed: bool,
 
@@ -27,69 +40,400 @@ pub struct App<'a> {
     pub selected_category: Option<u32>,
 
     pub notification_manager: NotificationManager,
+    pub hooks: Hooks,
+
+    pub keymap: Keymap,
+    pub config: Config,
+    pub theme: Theme,
+    /// Keys of a chord which has not been completed yet.
+    pub pending_keys: Vec<Key>,
+
+    /// A one-line message shown below the input, e.g. keymap conflicts.
+    pub status_message: Option<String>,
+
+    /// Is the help overlay open?
+    pub show_help: bool,
+
+    /// Tasks marked for bulk operations.
+    pub marked_tasks: Vec<u32>,
+    /// Start of a visual range, which goes from here to the selected task.
+    pub visual_anchor: Option<u32>,
+
+    /// The text typed into the command line, if it is open.
+    pub command_line: Option<String>,
+    /// Title and lines of a popup with command output, e.g. a report.
+    pub popup: Option<(String, Vec<String>)>,
+
+    /// Modification time of the database when it was last loaded or saved.
+    pub last_modified: Option<SystemTime>,
+    /// Hints at changes of the database made outside of this instance.
+    pub watcher: FileWatcher,
+    /// Unsaved changes of ours clash with changes made outside of pti.
+    pub conflict: Option<Conflict>,
+
+    /// Asks what to do with the time of a pomodoro during which pti was not running.
+    pub interruption: Option<Interruption>,
+    /// Time of the last tick.
+    pub last_tick: DateTime<Utc>,
+    /// Saves which still have to be committed for syncing.
+    pub pending_commit: PendingCommit,
 }
 
-fn open_file() -> File {
-    let path = get_full_path(TASK_FILE);
-    let file = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .create(true)
-        .open(path.as_path())
-        .expect(format!("Failed to open task file {}.", path.display()).as_str());
-    
-    file.try_lock_exclusive()
-        .expect("Failed to lock task file. Check if another instance of pti is running.");
+/// A pause between two ticks longer than this means the computer was suspended.
+const SUSPEND_GAP: i64 = 60;
+/// Seconds after which the running instance saves again while a pomodoro runs,
+/// so that the closing time is known if pti gets killed.
+const HEARTBEAT: i64 = 30;
 
-    file
+/// A pomodoro was running while pti was closed or the computer was suspended.
+pub struct Interruption {
+    /// When pti stopped running.
+    pub since: DateTime<Utc>,
+    /// When it noticed.
+    pub until: DateTime<Utc>,
+    /// Descriptions of the tasks in the pomodoro.
+    pub tasks: Vec<String>,
+    /// pti was closed, as opposed to the computer being suspended.
+    pub closed: bool,
 }
 
-impl<'a> Default for App<'a> {
-    fn default() -> Self {
-        let mut db_file = open_file();
-        let db = Database::load_or_create(&mut db_file);
+/// The database was changed outside of pti, while there were unsaved changes
+/// here which clash with those changes.
+pub struct Conflict {
+    /// The database as changed outside of pti.
+    pub base: Database,
+    /// Both changes, with ours where they clash.
+    pub mine: Database,
+    /// Both changes, with theirs where they clash.
+    pub theirs: Database,
+    /// The clashes, see [`merge::merge`].
+    pub lines: Vec<String>,
+}
+
+pub const READ_ONLY_MESSAGE: &str = "Read-only, another instance of pti owns the database";
+
+impl<'a> App<'a> {
+    /// Constructs a new instance of [`App`] on the loaded database.
+    /// The warnings of loading the config are shown in the status line.
+    pub fn new(config: Config, mut warnings: Vec<String>, storage: Box<dyn Storage>, db: Database) -> Self {
+        let (keymap, keymap_warnings) = Keymap::load();
+        warnings.extend(keymap_warnings);
+        warnings.extend(keymap.validate(db.categories()));
+        let (theme, theme_warnings) = Theme::load(&config.theme);
+        warnings.extend(theme_warnings);
+        // A pomodoro which ended while pti was closed has not been recorded yet
+        let now = Utc::now();
+        let interruption = match db.pomodoro_end_time() {
+            Some(end) if end < now && storage.runs_timer() => Some(Interruption {
+                since: db.last_seen.unwrap_or(end).min(end),
+                until: now,
+                tasks: db.pomodoro_tasks(),
+                closed: true,
+            }),
+            _ => None,
+        };
+        let status_message = match warnings.len() {
+            0 if storage.read_only() => Some(format!("{}. Changes made there show up here.", READ_ONLY_MESSAGE)),
+            0 => None,
+            n => Some(format!("Configuration has {} problem(s): {}", n, warnings.join("; "))),
+        };
         Self {
-            database_file: db_file,
             tablestate: TableState::default(),
             textarea: TextArea::default(),
             running: true,
+            base: db.clone(),
             data: db,
             data_changed: false,
             selected_task: None,
             selected_category: None,
             notification_manager: NotificationManager::new(),
+            hooks: Hooks::new(&config),
+            keymap,
+            config,
+            theme,
+            pending_keys: Vec::new(),
+            status_message,
+            show_help: false,
+            marked_tasks: Vec::new(),
+            visual_anchor: None,
+            command_line: None,
+            popup: None,
+            last_modified: storage.modified(),
+            watcher: FileWatcher::new(storage.path()),
+            conflict: None,
+            interruption,
+            last_tick: now,
+            pending_commit: PendingCommit::default(),
+            storage,
         }
     }
-}
-
-impl<'a> App<'a> {
-    /// Constructs a new instance of [`App`].
-    pub fn new() -> Self {
-        Self::default()
-    }
 
     /// Handles the tick event of the terminal.
     pub fn tick(&mut self) {
+        // A long pause between ticks means the computer was suspended
+        let now = Utc::now();
+        if now - self.last_tick > chrono::Duration::seconds(SUSPEND_GAP) && self.interruption.is_none()
+            && self.data.pomodoro_end_time().is_some() && self.storage.runs_timer() {
+            self.interruption = Some(Interruption {
+                since: self.last_tick,
+                until: now,
+                tasks: self.data.pomodoro_tasks(),
+                closed: false,
+            });
+        }
+        self.last_tick = now;
+
+        // Changes made outside of pti come first, so that saving does not overwrite them
+        if self.watcher.changed() || self.data_changed {
+            self.check_external_change();
+        }
+        // Nothing is saved until a conflict with such changes is resolved
+        if self.conflict.is_some() {
+            return;
+        }
+
+        // Without other changes, last_seen would stay at the start of the pomodoro
+        if !self.data_changed && self.heartbeat_due(now) {
+            self.data_changed = true;
+        }
+
         // If some data has changed, save it.
+        // On failure the data stays marked as changed, so that the next tick tries again.
         if self.data_changed {
-            self.data.save(&mut self.database_file);
-            self.data_changed = false;
+            if let Err(error) = self.save() {
+                match error.downcast_ref::<PtiError>() {
+                    // Another client of the daemon saved first, the next tick merges its changes
+                    Some(PtiError::Stale) => self.last_modified = None,
+                    _ => self.status_message = Some(format!("Could not save: {}", error)),
+                }
+            }
+        }
+
+        if let Err(error) = self.pending_commit.tick(now) {
+            self.status_message = Some(format!("Could not commit the database: {}", error));
+        }
+
+        // Another instance or the daemon may end pomodoros and notify about it,
+        // and the time of an interrupted pomodoro has to be sorted out first
+        if !self.storage.runs_timer() || self.interruption.is_some() {
+            return;
         }
 
         // Check if a pomodoro is over
+        let task_ids = self.data.pomodoro_task_ids();
         match self.data.check_active_pomodoro_over() {
             Some(task_descriptions) => {
-                self.data.save(&mut self.database_file);
+                // Saved with the next tick
+                self.data_changed = true;
                 let body = format!("{} Tasks are over:\n{}", task_descriptions.len(), task_descriptions.join("\n"));
                 self.notification_manager.notify("Pomodoro over!", &body);
+                self.fire_hook(HookEvent::PomodoroFinished, &task_ids);
             }
             None => {}
         }
+        if let Err(error) = self.hooks.tick(&self.data) {
+            self.status_message = Some(error);
+        }
+    }
+
+    /// Whether `last_seen` should be refreshed, which only matters while a pomodoro runs.
+    fn heartbeat_due(&self, now: DateTime<Utc>) -> bool {
+        self.storage.runs_timer() && self.interruption.is_none() && self.data.pomodoro_end_time().is_some()
+            && self.data.last_seen.is_none_or(|seen| now - seen >= chrono::Duration::seconds(HEARTBEAT))
+    }
+
+    /// Runs the hook of an event. A read-only instance undoes its changes, so it has no events.
+    pub fn fire_hook(&mut self, event: HookEvent, task_ids: &[u32]) {
+        if self.storage.read_only() {
+            return;
+        }
+        if let Err(error) = self.hooks.fire(event, &self.data, task_ids) {
+            self.status_message = Some(error);
+        }
+    }
+
+    /// Reloads the database if it was changed outside of this instance. Unsaved
+    /// changes of our own are merged in, the user decides where they clash.
+    fn check_external_change(&mut self) {
+        if self.conflict.is_some() || self.storage.modified() == self.last_modified {
+            return;
+        }
+        if !self.data_changed {
+            match self.reload() {
+                Ok(()) if self.storage.read_only() => {}
+                Ok(()) => self.status_message = Some("Reloaded the database, it was changed outside of pti".to_string()),
+                Err(error) => self.keep_unreadable_change(error),
+            }
+            return;
+        }
+        self.last_modified = self.storage.modified();
+        match self.storage.load() {
+            Ok(Some(theirs)) => {
+                let mine = merge::merge(Some(&self.base), &self.data, &theirs);
+                if mine.conflicts.is_empty() {
+                    self.base = theirs;
+                    self.replace_data(mine.database);
+                    self.data_changed = true;
+                    self.status_message = Some("Merged changes made outside of pti".to_string());
+                } else {
+                    let merged_theirs = merge::merge(Some(&self.base), &theirs, &self.data);
+                    self.conflict = Some(Conflict {
+                        base: theirs,
+                        mine: mine.database,
+                        theirs: merged_theirs.database,
+                        lines: mine.conflicts,
+                    });
+                }
+            }
+            // An emptied file is simply written again
+            Ok(None) => {}
+            Err(error) => self.keep_unreadable_change(error),
+        }
+    }
+
+    /// The file was changed into something pti cannot read. It is kept in the
+    /// backups and will be overwritten with the current state.
+    fn keep_unreadable_change(&mut self, error: impl fmt::Display) {
+        self.data_changed = true;
+        self.status_message = Some(match backup::keep_damaged(self.config.storage) {
+            Ok(path) => format!("The database was changed outside of pti but cannot be read ({}), it is kept as {}", error, path.display()),
+            Err(_) => format!("The database was changed outside of pti but cannot be read ({})", error),
+        });
+    }
+
+    /// Resolves a conflict by keeping our side of the clashing changes.
+    pub fn keep_mine(&mut self) {
+        if let Some(conflict) = self.conflict.take() {
+            self.base = conflict.base;
+            self.replace_data(conflict.mine);
+            self.data_changed = true;
+        }
+    }
+
+    /// Resolves a conflict by taking their side of the clashing changes.
+    pub fn take_theirs(&mut self) {
+        if let Some(conflict) = self.conflict.take() {
+            self.base = conflict.base;
+            self.replace_data(conflict.theirs);
+            self.data_changed = true;
+        }
+    }
+
+    /// Counts the time pti was not running as worked.
+    pub fn keep_interrupted_pomodoro(&mut self) {
+        // The next tick ends the pomodoro if it is over by now
+        self.interruption = None;
+    }
+
+    /// Ends the pomodoro when pti stopped running.
+    pub fn truncate_interrupted_pomodoro(&mut self) {
+        if let Some(interruption) = self.interruption.take() {
+            let end = match self.data.pomodoro_end_time() {
+                Some(end) => end.min(interruption.since),
+                None => interruption.since,
+            };
+            let task_ids = self.data.pomodoro_task_ids();
+            self.data.end_pomodoro_at(end);
+            self.data_changed = true;
+            self.fire_hook(HookEvent::PomodoroFinished, &task_ids);
+        }
+    }
+
+    /// Drops the whole pomodoro.
+    pub fn discard_interrupted_pomodoro(&mut self) {
+        if self.interruption.take().is_some() {
+            self.data.discard_pomodoro();
+            self.data_changed = true;
+        }
+    }
+
+    /// Saves the database and keeps a backup of it.
+    pub fn save(&mut self) -> AppResult<()> {
+        // While an interruption is open, last_seen still tells when it started
+        if self.interruption.is_none() {
+            self.data.last_seen = Some(Utc::now());
+        }
+        self.storage.save(&self.data)?;
+        // A heartbeat would push the real changes out of the backups
+        let heartbeat = self.only_last_seen_changed();
+        self.base = self.data.clone();
+        self.data_changed = false;
+        self.last_modified = self.storage.modified();
+        if heartbeat {
+            return Ok(());
+        }
+        // The daemon backs up and commits its own saves
+        if self.storage.runs_timer() {
+            self.pending_commit.saved();
+            backup::write(&self.data)?;
+        }
+        Ok(())
+    }
+
+    /// Whether the data only differs from what was last loaded or saved in `last_seen`.
+    fn only_last_seen_changed(&self) -> bool {
+        let mut base = self.base.clone();
+        base.last_seen = self.data.last_seen;
+        base == self.data
+    }
+
+    /// Replaces the data with what is stored, dropping unsaved changes.
+    pub fn reload(&mut self) -> AppResult<()> {
+        self.last_modified = self.storage.modified();
+        match self.storage.load()? {
+            Some(data) => {
+                self.base = data.clone();
+                self.replace_data(data);
+            }
+            None => self.data_changed = false,
+        }
+        Ok(())
+    }
+
+    /// Switches to other data while keeping the cursor as close as possible.
+    fn replace_data(&mut self, data: Database) {
+        let previous_index = self.selected_index();
+        self.data = data;
+        self.data_changed = false;
+        if self.selected_task.is_some() {
+            self.reselect_near(previous_index);
+        }
+        if self.selected_category.is_some_and(|id| !self.data.categories().iter().any(|category| category.id == id)) {
+            self.select_first_category();
+        }
+        self.prune_marks();
+    }
+
+    /// The mode whose key bindings apply in the current state.
+    pub fn mode(&self) -> Mode {
+        if self.selected_category.is_none() {
+            if self.selected_task.is_none() {
+                Mode::TextInput
+            } else {
+                Mode::Todo
+            }
+        } else {
+            Mode::Category
+        }
     }
 
     /// Set running to false to quit the application.
     pub fn quit(&mut self) {
         self.running = false;
+        if self.storage.read_only() {
+            return;
+        }
+        match self.conflict {
+            // Remember when pti was closed, in case a pomodoro is running
+            None => {
+                let _ = self.save();
+                let _ = self.pending_commit.flush();
+            }
+            // The file keeps the other changes, ours can still be restored from the backups
+            Some(_) => {
+                let _ = backup::write(&self.data);
+            }
+        }
     }
 
     pub fn select_previous_task(&mut self) {
@@ -130,6 +474,14 @@ impl<'a> App<'a> {
         }
     }
 
+    pub fn select_first_task(&mut self) {
+        self.selected_task = self.data.tasks_printeable().first().map(|task| task.id);
+    }
+
+    pub fn select_last_task(&mut self) {
+        self.selected_task = self.data.tasks_printeable().last().map(|task| task.id);
+    }
+
     pub fn select_previous_category(&mut self) {
         let categorylist = self.data.categories_printeable();
         match self.selected_category {
@@ -168,6 +520,10 @@ impl<'a> App<'a> {
         }
     }
 
+    pub fn select_last_category(&mut self) {
+        self.selected_category = self.data.categories_printeable().last().map(|category| category.id);
+    }
+
     pub fn select_first_category(&mut self) {
         let categorylist = self.data.categories_printeable();
         if categorylist.len() > 0 {
@@ -184,6 +540,43 @@ impl<'a> App<'a> {
         self.data_changed = true;
     }
 
+    pub fn add_category(&mut self, name: String, hotkey: Option<char>) -> Result<u32, String> {
+        let id = self.data.add_category(name, hotkey)?;
+        // The new hotkey might collide with a key binding
+        let conflicts = self.keymap.validate(self.data.categories());
+        if !conflicts.is_empty() {
+            self.status_message = Some(conflicts.join("; "));
+        }
+        self.data_changed = true;
+        Ok(id)
+    }
+
+    pub fn set_category_color(&mut self, category: u32, color: Option<String>) -> Result<(), String> {
+        if let Some(color) = &color {
+            parse_color(color).ok_or(format!("Unknown colour '{}'", color))?;
+        }
+        self.data.set_category_color(category, color);
+        self.data_changed = true;
+        Ok(())
+    }
+
+    pub fn set_category_billing(&mut self, category: u32, billing: Option<Billing>) {
+        self.data.set_category_billing(category, billing);
+        self.data_changed = true;
+    }
+
+    pub fn move_done_tasks(&mut self, category: u32) -> usize {
+        let previous_index = self.selected_index();
+        let moved = self.data.move_done_tasks(category);
+        // The selected task might have gone invisible
+        if self.selected_task.is_some() {
+            self.reselect_near(previous_index);
+        }
+        self.prune_marks();
+        self.data_changed = true;
+        moved
+    }
+
     pub fn toggle_category_visible(&mut self, category: u32) {
         self.data.toggle_category_visible(category);
         // Our selected task might have gone invisible, so we need to check that.
@@ -191,6 +584,7 @@ impl<'a> App<'a> {
         if new_tasklist.iter().find(|task| Some(task.id) == self.selected_task).is_none() {
             self.selected_task = None;
         }
+        self.prune_marks();
         self.data_changed = true;
     }
 
@@ -198,86 +592,475 @@ impl<'a> App<'a> {
         self.selected_task = None;
     }
 
-    pub fn check_task(&mut self) {
-        match self.selected_task {
-            Some(selected) => {
-                self.data.check_task(selected);
-                self.data_changed = true;
+    /// The tasks an operation applies to: the marked ones and the visual range,
+    /// or just the selected task if nothing is marked.
+    pub fn targets(&self) -> Vec<u32> {
+        let tasklist = self.data.tasks_printeable();
+        let mut targets = self.marked_tasks.clone();
+        if let (Some(anchor), Some(selected)) = (self.visual_anchor, self.selected_task) {
+            let anchor_index = tasklist.iter().position(|task| task.id == anchor);
+            let selected_index = tasklist.iter().position(|task| task.id == selected);
+            if let (Some(anchor_index), Some(selected_index)) = (anchor_index, selected_index) {
+                let range = anchor_index.min(selected_index)..=anchor_index.max(selected_index);
+                targets.extend(tasklist[range].iter().map(|task| task.id).filter(|id| !self.marked_tasks.contains(id)));
             }
-            None => {}
         }
+        if targets.is_empty() {
+            targets.extend(self.selected_task);
+        }
+        // Keep the order of the list, so that moving several tasks keeps them in order
+        tasklist.iter().map(|task| task.id).filter(|id| targets.contains(id)).collect()
     }
 
-    pub fn add_task(&mut self, task: String) {
-        self.data.add_task_from_string(task);
-        self.data_changed = true;
+    /// The marked tasks including the visual range, empty if nothing is marked.
+    pub fn marked(&self) -> Vec<u32> {
+        if self.marked_tasks.is_empty() && self.visual_anchor.is_none() {
+            Vec::new()
+        } else {
+            self.targets()
+        }
     }
 
-    pub fn set_category(&mut self, category: u32) {
-        match self.selected_task {
-            Some(selected_task_id) => {
-                let mut previous_index = self.data.tasks_printeable().iter().position(|task| task.id == selected_task_id).expect("Task not found");
-                self.data.set_category(selected_task_id, category);
-                // Find the closest task to the previous one in case this one got invisible
-                let new_tasklist = self.data.tasks_printeable();
-                if new_tasklist.len() == 0 {
-                    self.selected_task = None;
-                } else {
-                    if previous_index >= new_tasklist.len() {
-                        previous_index = new_tasklist.len() - 1;
-                    }
-                    self.selected_task = Some(new_tasklist[previous_index].id);    
+    pub fn toggle_mark(&mut self) {
+        if let Some(selected) = self.selected_task {
+            match self.marked_tasks.iter().position(|id| *id == selected) {
+                Some(index) => {
+                    self.marked_tasks.remove(index);
                 }
+                None => self.marked_tasks.push(selected),
+            }
+        }
+    }
 
-                self.data_changed = true;
+    /// Starts a visual range at the cursor, or marks the range if one is started.
+    pub fn toggle_visual_mark(&mut self) {
+        match self.visual_anchor {
+            Some(_) => self.end_visual_mark(),
+            None => self.visual_anchor = self.selected_task,
+        }
+    }
+
+    fn end_visual_mark(&mut self) {
+        if self.visual_anchor.is_some() {
+            self.marked_tasks = self.targets();
+            self.visual_anchor = None;
+        }
+    }
+
+    pub fn clear_marks(&mut self) -> bool {
+        let had_marks = !self.marked_tasks.is_empty() || self.visual_anchor.is_some();
+        self.marked_tasks.clear();
+        self.visual_anchor = None;
+        had_marks
+    }
+
+    /// Forgets marks of tasks which got deleted or invisible.
+    fn prune_marks(&mut self) {
+        let tasklist = self.data.tasks_printeable();
+        self.marked_tasks.retain(|id| tasklist.iter().any(|task| task.id == *id));
+        if !tasklist.iter().any(|task| Some(task.id) == self.visual_anchor) {
+            self.visual_anchor = None;
+        }
+    }
+
+    /// Keeps the cursor close to where it was, in case the selected task got invisible.
+    fn reselect_near(&mut self, previous_index: usize) {
+        let new_tasklist = self.data.tasks_printeable();
+        if new_tasklist.iter().any(|task| Some(task.id) == self.selected_task) {
+            return;
+        }
+        self.selected_task = new_tasklist.get(previous_index.min(new_tasklist.len().saturating_sub(1))).map(|task| task.id);
+    }
+
+    fn selected_index(&self) -> usize {
+        self.data.tasks_printeable().iter().position(|task| Some(task.id) == self.selected_task).unwrap_or(0)
+    }
+
+    /// Checks all targets, or unchecks them if all are checked already.
+    pub fn check_task(&mut self) {
+        let targets = self.targets();
+        if targets.is_empty() {
+            return;
+        }
+        self.end_visual_mark();
+        let all_done = targets.iter().all(|id| self.data.task_done(*id));
+        for id in targets.iter() {
+            self.data.set_task_done(*id, !all_done);
+        }
+        self.data_changed = true;
+        if !all_done {
+            self.fire_hook(HookEvent::TaskChecked, &targets);
+        }
+    }
+
+    pub fn delete_task(&mut self) {
+        let targets = self.targets();
+        self.delete_tasks(&targets);
+    }
+
+    pub fn delete_tasks(&mut self, task_ids: &[u32]) {
+        if task_ids.is_empty() {
+            return;
+        }
+        let previous_index = self.selected_index();
+        for id in task_ids {
+            self.data.delete_task(*id);
+        }
+        // Select the task which moved into the place of the deleted one
+        self.reselect_near(previous_index);
+        self.prune_marks();
+        self.data_changed = true;
+    }
+
+    /// Time spent per category after `since`, including the running pomodoro.
+    pub fn time_per_category(&mut self, since: DateTime<Utc>) -> AppResult<Vec<(Category, chrono::Duration)>> {
+        // Pending changes have to reach the storage before it can be queried
+        if self.data_changed && self.conflict.is_none() {
+            self.save()?;
+        }
+        let mut segments = self.storage.segments_since(since)?;
+        segments.extend(self.data.active_segments(Utc::now()));
+        Ok(self.data.time_per_category(&segments, since))
+    }
+
+    /// Adds a task written in quick-add syntax.
+    pub fn add_task(&mut self, task: String) {
+        let parsed = quickadd::parse(&task, self.data.categories(), Local::now().date_naive());
+        if parsed.description.is_empty() {
+            return;
+        }
+        let id = self.data.add_parsed_task(&parsed, None);
+        self.data_changed = true;
+        self.fire_hook(HookEvent::TaskAdded, &[id]);
+    }
+
+    /// Adds one task per line. Indentation and `- ` bullets nest a line below
+    /// the closest previous line which is less indented, `- [x]` items are added
+    /// as done. Returns the number of tasks added.
+    pub fn add_tasks_from_lines(&mut self, text: &str) -> usize {
+        let today = Local::now().date_naive();
+        // Nesting levels and ids of the tasks which can still get subtasks
+        let mut parents: Vec<(usize, u32)> = Vec::new();
+        let mut added = Vec::new();
+        for line in text.lines() {
+            let item = quickadd::list_item(line);
+            let level = item.indent + usize::from(item.bullet);
+            let parsed = quickadd::parse(item.text, self.data.categories(), today);
+            if parsed.description.is_empty() {
+                continue;
             }
-            None => {}
+            while parents.last().is_some_and(|(parent_level, _)| *parent_level >= level) {
+                parents.pop();
+            }
+            let parent = parents.last().map(|(_, id)| *id);
+            let id = self.data.add_parsed_task(&parsed, parent);
+            if item.checkbox == Some(true) {
+                self.data.set_task_done(id, true);
+            }
+            parents.push((level, id));
+            added.push(id);
+        }
+        if !added.is_empty() {
+            self.data_changed = true;
+            self.fire_hook(HookEvent::TaskAdded, &added);
+        }
+        added.len()
+    }
+
+    /// What the task in the input would look like, if there is one.
+    pub fn quickadd_preview(&self) -> Option<String> {
+        let input = self.textarea.lines()[0].trim();
+        if input.is_empty() {
+            return None;
         }
+        let parsed = quickadd::parse(input, self.data.categories(), Local::now().date_naive());
+        Some(parsed.preview(self.data.categories()))
     }
 
+    pub fn set_category(&mut self, category: u32) {
+        let targets = self.targets();
+        if targets.is_empty() {
+            return;
+        }
+        self.end_visual_mark();
+        let previous_index = self.selected_index();
+        for id in targets {
+            self.data.set_category(id, category);
+        }
+        // Find the closest task to the previous one in case this one got invisible
+        self.reselect_near(previous_index);
+        self.prune_marks();
+        self.data_changed = true;
+    }
+
+    /// Joins all targets into the pomodoro, or lets them leave if all are in it already.
     pub fn toggle_pomodoro(&mut self) {
-        match self.selected_task {
-            Some(selected_task_id) => {
-                self.data.toggle_pomodoro(selected_task_id);
-                self.data_changed = true;
+        let targets = self.targets();
+        if targets.is_empty() {
+            return;
+        }
+        self.end_visual_mark();
+        let all_active = targets.iter().all(|id| self.data.task_in_pomodoro(*id));
+        let was_running = self.data.pomodoro_end_time().is_some();
+        for id in targets {
+            if self.data.task_in_pomodoro(id) == all_active {
+                self.data.toggle_pomodoro(id);
             }
-            None => {}
+        }
+        self.data_changed = true;
+        if !was_running && self.data.pomodoro_end_time().is_some() {
+            self.fire_hook(HookEvent::PomodoroStarted, &self.data.pomodoro_task_ids());
         }
     }
 
     pub fn move_task_up(&mut self) {
-        match self.selected_task {
-            Some(selected_task_id) => {
-                let tasklist = self.data.tasks_printeable();
-                let idx = self.data.tasks_printeable().iter().position(|task| task.id == selected_task_id);
-                match idx {
-                    Some(0) => {} // Already the first element, can't move up.
-                    Some(index) => {
-                        self.data.order_task_before_other(selected_task_id, tasklist[index - 1].id).expect("Could not reorder tasks");
-                        self.data_changed = true;
+        let targets = self.targets();
+        self.end_visual_mark();
+        // Top to bottom, so that the targets keep their order. If the first one can't move, none does.
+        for id in targets {
+            let tasklist = self.data.tasks_printeable();
+            let idx = tasklist.iter().position(|task| task.id == id);
+            match idx {
+                Some(0) => return, // Already the first element, can't move up.
+                Some(index) => {
+                    // The order can disagree with the tree, e.g. after a merge, then nothing moves
+                    if self.data.order_task_before_other(id, tasklist[index - 1].id).is_err() {
+                        return;
                     }
-                    None => {}
+                    self.data_changed = true;
                 }
+                None => {}
             }
-            None => {}
         }
     }
 
     pub fn move_task_down(&mut self) {
-        match self.selected_task {
-            Some(selected_task_id) => {
-                let tasklist = self.data.tasks_printeable();
-                let idx = self.data.tasks_printeable().iter().position(|task| task.id == selected_task_id);
-                match idx {
-                    Some(index) if index == tasklist.len() - 1 => {} // Already the last element, can't move down.
-                    Some(index) => {
-                        self.data.order_task_after_other(selected_task_id, tasklist[index + 1].id).expect("Could not reorder tasks");
-                        self.data_changed = true;
+        let targets = self.targets();
+        self.end_visual_mark();
+        // Bottom to top, so that the targets keep their order. If the last one can't move, none does.
+        for id in targets.into_iter().rev() {
+            let tasklist = self.data.tasks_printeable();
+            let idx = tasklist.iter().position(|task| task.id == id);
+            match idx {
+                Some(index) if index == tasklist.len() - 1 => return, // Already the last element, can't move down.
+                Some(index) => {
+                    if self.data.order_task_after_other(id, tasklist[index + 1].id).is_err() {
+                        return;
                     }
-                    None => {}
+                    self.data_changed = true;
                 }
+                None => {}
             }
-            None => {}
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::sync::{Mutex, MutexGuard};
+    use chrono::Duration;
+    use crate::error::PtiError;
+    use crate::model::Segment;
+    use super::*;
+
+    /// The storage directory is process-wide, so the tests take turns.
+    static STORAGE_DIR: Mutex<()> = Mutex::new(());
+
+    /// An empty storage directory for one test, so that backups, the keymap and
+    /// git of the real `~/.pti` stay out of it. Removed when dropped.
+    struct TestDir {
+        path: PathBuf,
+        _lock: MutexGuard<'static, ()>,
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.path);
+        }
+    }
+
+    fn storage_dir() -> TestDir {
+        let lock = STORAGE_DIR.lock().unwrap_or_else(|error| error.into_inner());
+        let path = env::temp_dir().join(format!("pti-app-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        env::set_var("PTI_STORAGE_DIR", &path);
+        TestDir { path, _lock: lock }
+    }
+
+    /// Keeps the last saved database in memory.
+    struct MemoryStorage {
+        path: PathBuf,
+        saved: Option<Database>,
+    }
+
+    impl Storage for MemoryStorage {
+        fn load(&mut self) -> Result<Option<Database>, PtiError> {
+            Ok(self.saved.clone())
+        }
+
+        fn save(&mut self, database: &Database) -> Result<(), PtiError> {
+            self.saved = Some(database.clone());
+            Ok(())
+        }
+
+        fn path(&self) -> &Path {
+            &self.path
+        }
+
+        fn read_only(&self) -> bool {
+            false
+        }
+
+        fn modified(&self) -> Option<SystemTime> {
+            None
+        }
+
+        fn segments_since(&mut self, _since: DateTime<Utc>) -> Result<Vec<Segment>, PtiError> {
+            Ok(Vec::new())
+        }
+    }
+
+    /// A database with one task in a pomodoro which started `minutes` ago and
+    /// was last saved `seen` minutes ago.
+    fn running_pomodoro(minutes: i64, seen: i64) -> Database {
+        let mut database = Database::example_db();
+        let parsed = quickadd::parse("Write the report", database.categories(), Local::now().date_naive());
+        let id = database.add_parsed_task(&parsed, None);
+        database.toggle_pomodoro(id);
+        let start = Utc::now() - Duration::minutes(minutes);
+        database.active_pomodoro_starttime = Some(start);
+        database.tasks[0].active_pomodoro_jointime = Some(start);
+        database.last_seen = Some(Utc::now() - Duration::minutes(seen));
+        database
+    }
+
+    fn app(database: Database) -> App<'static> {
+        let storage = MemoryStorage { path: std::env::temp_dir().join("pti-app-test.json"), saved: Some(database.clone()) };
+        App::new(Config::default(), Vec::new(), Box::new(storage), database)
+    }
+
+    #[test]
+    fn tick_refreshes_last_seen_while_a_pomodoro_runs() {
+        let dir = storage_dir();
+        let mut app = app(running_pomodoro(10, 10));
+        app.tick();
+        let saved = app.storage.load().unwrap().unwrap();
+        assert!(Utc::now() - saved.last_seen.unwrap() < Duration::seconds(HEARTBEAT));
+        // Heartbeats are not worth a backup
+        assert!(!dir.path.join(crate::constants::BACKUP_DIR).exists());
+    }
+
+    #[test]
+    fn stale_close_keeps_the_time_until_the_last_heartbeat() {
+        let _dir = storage_dir();
+        // Killed 20 minutes into a pomodoro which would have ended 15 minutes ago
+        let mut app = app(running_pomodoro(40, 20));
+        let since = app.interruption.as_ref().unwrap().since;
+        assert!(Utc::now() - since >= Duration::minutes(20));
+        assert!(Utc::now() - since < Duration::minutes(21));
+        app.truncate_interrupted_pomodoro();
+        let worked = app.data.tasks[0].past_pomodoros.iter().fold(Duration::zero(), |sum, pomodoro| sum + (pomodoro.end_time - pomodoro.start_time));
+        assert_eq!(worked.num_minutes(), 20);
+    }
+
+    fn add_task(database: &mut Database, text: &str) {
+        let parsed = quickadd::parse(text, database.categories(), Local::now().date_naive());
+        database.add_parsed_task(&parsed, None);
+    }
+
+    fn descriptions(database: &Database) -> Vec<String> {
+        let mut descriptions = database.tasks_printeable().iter().map(|task| task.description.clone()).collect::<Vec<String>>();
+        descriptions.sort();
+        descriptions
+    }
+
+    /// Saves `theirs` as another client would and lets the app notice it.
+    fn change_outside(app: &mut App, theirs: &Database) {
+        app.storage.save(theirs).unwrap();
+        app.last_modified = Some(SystemTime::UNIX_EPOCH);
+        app.tick();
+    }
+
+    #[test]
+    fn unsaved_changes_are_merged_with_changes_made_outside() {
+        let _dir = storage_dir();
+        let mut database = Database::example_db();
+        add_task(&mut database, "Write the report");
+        let mut app = app(database.clone());
+        let mut theirs = database;
+        add_task(&mut theirs, "Added by pti add");
+        add_task(&mut app.data, "Added here");
+        app.data_changed = true;
+
+        change_outside(&mut app, &theirs);
+        assert!(app.conflict.is_none());
+        let saved = app.storage.load().unwrap().unwrap();
+        assert_eq!(descriptions(&saved), vec!["Added by pti add", "Added here", "Write the report"]);
+    }
+
+    #[test]
+    fn keeping_mine_in_a_conflict_keeps_their_other_changes() {
+        let _dir = storage_dir();
+        let mut database = Database::example_db();
+        add_task(&mut database, "Write the report");
+        let mut app = app(database.clone());
+        let mut theirs = database;
+        theirs.set_pomodoro_duration(30);
+        add_task(&mut theirs, "Added by pti add");
+        app.data.set_pomodoro_duration(45);
+        app.data_changed = true;
+
+        change_outside(&mut app, &theirs);
+        assert_eq!(app.conflict.as_ref().unwrap().lines, vec!["settings: pomodoro duration changed on both sides"]);
+        app.keep_mine();
+        app.tick();
+        let saved = app.storage.load().unwrap().unwrap();
+        assert_eq!(saved.pomodoro_duration_minutes(), 45);
+        assert_eq!(descriptions(&saved), vec!["Added by pti add", "Write the report"]);
+    }
+
+    #[test]
+    fn moving_a_task_whose_order_disagrees_with_the_tree_does_not_panic() {
+        let _dir = storage_dir();
+        let mut database = Database::example_db();
+        let today = Local::now().date_naive();
+        let parent = database.add_parsed_task(&quickadd::parse("Write the report", database.categories(), today), None);
+        let child = database.add_parsed_task(&quickadd::parse("Collect the numbers", database.categories(), today), Some(parent));
+        // Listed below its parent, but ordered before it
+        database.tasks[1].order = 0;
+        database.tasks[0].order = 1;
+        let mut app = app(database);
+        app.selected_task = Some(child);
+        app.move_task_up();
+        app.selected_task = Some(parent);
+        app.move_task_down();
+        let order = app.data.tasks_printeable().iter().map(|task| task.id).collect::<Vec<u32>>();
+        assert_eq!(order, vec![parent, child]);
+    }
+
+    #[test]
+    fn pasted_checklists_keep_their_checkboxes_out_of_the_text() {
+        let _dir = storage_dir();
+        let mut app = app(Database::example_db());
+        assert_eq!(app.add_tasks_from_lines("- [x] Book the flight\n* [ ] Pack\n  - [X] Buy a charger\n"), 3);
+        let tasks = &app.data.tasks;
+        let added = tasks.iter().map(|task| (task.description.as_str(), task.done)).collect::<Vec<_>>();
+        assert_eq!(added, vec![("Book the flight", true), ("Pack", false), ("Buy a charger", true)]);
+        assert_eq!(tasks[2].parent.as_ref(), Some(&tasks[1].uid));
+    }
+
+    #[test]
+    fn quitting_keeps_the_interruption_for_the_next_start() {
+        let _dir = storage_dir();
+        let mut app = app(running_pomodoro(40, 20));
+        let since = app.interruption.as_ref().unwrap().since;
+        app.quit();
+        let saved = app.storage.load().unwrap().unwrap();
+        assert_eq!(saved.last_seen, Some(since));
+        assert!(saved.pomodoro_end_time().is_some());
+    }
+}
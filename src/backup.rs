@@ -0,0 +1,117 @@
+use std::fs::{self, DirEntry};
+use std::path::PathBuf;
+use chrono::Local;
+use serde_json::Value;
+use crate::app::AppResult;
+use crate::constants::{BACKUP_DIR, get_full_path};
+use crate::model::Database;
+use crate::error::PtiError;
+use crate::storage::{self, write_atomic, StorageKind};
+
+/// Number of backups kept of the most recent saves.
+const RECENT_BACKUPS: usize = 10;
+/// Number of days for which the first save of the day is kept.
+const DAILY_BACKUPS: usize = 14;
+
+const RECENT_PREFIX: &str = "recent-";
+const DAILY_PREFIX: &str = "daily-";
+const DAMAGED_PREFIX: &str = "damaged-";
+const MIGRATION_PREFIX: &str = "before-migration-";
+
+fn backup_dir() -> PathBuf {
+    get_full_path(BACKUP_DIR)
+}
+
+/// Backup files starting with `prefix`, oldest first. The timestamps in the names sort chronologically.
+fn backups_with_prefix(prefix: &str) -> AppResult<Vec<DirEntry>> {
+    let mut entries = match fs::read_dir(backup_dir()) {
+        Ok(entries) => entries.collect::<Result<Vec<DirEntry>, _>>()?,
+        Err(_) => return Ok(Vec::new()),
+    };
+    entries.retain(|entry| entry.file_name().to_string_lossy().starts_with(prefix));
+    entries.sort_by_key(|entry| entry.file_name());
+    Ok(entries)
+}
+
+fn prune(prefix: &str, keep: usize) -> AppResult<()> {
+    let entries = backups_with_prefix(prefix)?;
+    for entry in entries.iter().take(entries.len().saturating_sub(keep)) {
+        fs::remove_file(entry.path())?;
+    }
+    Ok(())
+}
+
+/// Writes a backup of the saved database and removes the ones which are no longer needed.
+pub fn write(database: &Database) -> AppResult<()> {
+    fs::create_dir_all(backup_dir())?;
+    let serialized = serde_json::to_string_pretty(database)?;
+    let now = Local::now();
+    let recent = backup_dir().join(format!("{}{}.json", RECENT_PREFIX, now.format("%Y-%m-%dT%H-%M-%S%.3f")));
+    write_atomic(&recent, serialized.as_bytes())?;
+    let daily = backup_dir().join(format!("{}{}.json", DAILY_PREFIX, now.format("%Y-%m-%d")));
+    if !daily.exists() {
+        write_atomic(&daily, serialized.as_bytes())?;
+    }
+    prune(RECENT_PREFIX, RECENT_BACKUPS)?;
+    prune(DAILY_PREFIX, DAILY_BACKUPS)?;
+    Ok(())
+}
+
+/// Keeps the database as it was before upgrading it from an older schema version.
+pub fn keep_before_migration(version: u32, database: &Value) -> Result<PathBuf, PtiError> {
+    let dir = backup_dir();
+    fs::create_dir_all(&dir).map_err(|error| PtiError::io(&dir, error))?;
+    let serialized = serde_json::to_string_pretty(database).map_err(|error| PtiError::Storage(error.to_string()))?;
+    let target = dir.join(format!("{}v{}-{}.json", MIGRATION_PREFIX, version, Local::now().format("%Y-%m-%dT%H-%M-%S")));
+    write_atomic(&target, serialized.as_bytes())?;
+    Ok(target)
+}
+
+/// Names of all backups, newest first.
+pub fn list() -> AppResult<Vec<String>> {
+    let mut names = backups_with_prefix(RECENT_PREFIX)?
+        .into_iter()
+        .chain(backups_with_prefix(DAILY_PREFIX)?)
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .collect::<Vec<String>>();
+    // Sort by the timestamp, daily backups count as the start of their day
+    names.sort_by_key(|name| {
+        let stamp = name.trim_start_matches(RECENT_PREFIX).trim_start_matches(DAILY_PREFIX).to_string();
+        std::cmp::Reverse(stamp)
+    });
+    Ok(names)
+}
+
+/// Reads a backup by its name. Backups of an older schema version are upgraded.
+pub fn read(name: &str) -> AppResult<Database> {
+    let path = backup_dir().join(name);
+    let data = fs::read_to_string(&path).map_err(|error| PtiError::io(&path, error))?;
+    let value: Value = serde_json::from_str(&data).map_err(|error| PtiError::parse(&path, error))?;
+    Ok(storage::upgrade_value(&path, value)?)
+}
+
+/// Copies a database file which could not be loaded into the backup directory
+/// before it gets overwritten, so that it can still be repaired by hand.
+pub fn keep_damaged(kind: StorageKind) -> AppResult<PathBuf> {
+    let path = storage::database_path(kind);
+    let file = path.file_name().unwrap_or_default().to_string_lossy();
+    fs::create_dir_all(backup_dir())?;
+    let target = backup_dir().join(format!("{}{}-{}", DAMAGED_PREFIX, Local::now().format("%Y-%m-%dT%H-%M-%S"), file));
+    fs::copy(&path, &target)?;
+    Ok(target)
+}
+
+/// Replaces the database with a backup. The current state is backed up first, so this can be undone.
+pub fn restore(name: &str, kind: StorageKind) -> AppResult<()> {
+    let backup = read(name)?;
+    let mut storage = storage::open(kind)?;
+    match storage.load() {
+        Ok(Some(current)) => write(&current)?,
+        Ok(None) => {}
+        Err(_) => {
+            keep_damaged(kind)?;
+        }
+    }
+    storage.save(&backup)?;
+    Ok(())
+}
@@ -0,0 +1,337 @@
+use crate::app::AppResult;
+use crate::backup;
+use crate::sync;
+use crate::config::Config;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use chrono::{DateTime, Local, NaiveDate, TimeZone, Utc};
+use crate::command::period_start;
+use crate::export::{self, Filter, Rows};
+use crate::import;
+use crate::merge;
+use crate::timesheet;
+use crate::model::Database;
+use crate::storage::{self, StorageKind};
+#[cfg(unix)]
+use crate::daemon::{client::Client, server};
+#[cfg(feature = "http")]
+use crate::http;
+#[cfg(unix)]
+use serde_json::{json, Value};
+
+const USAGE: &str = "Usage:
+    pti                          Start the todo list
+    pti migrate <from> <to>      Copy the database between storages, json or sqlite
+    pti restore                  List the backups, newest first
+    pti restore <backup>         Replace the database with a backup
+    pti daemon                   Run the timer in the background, pti then talks to it
+    pti list                     List the tasks kept by the daemon
+    pti add <task>               Add a task, with quick-add syntax
+    pti start <id>               Put a task into the pomodoro, starting one if needed
+    pti stop [id]                Take a task out of the pomodoro, or end the pomodoro
+    pti events                   Print the events of the daemon as they happen
+    pti serve [--port <port>]    Serve the HTTP API on localhost
+    pti export --format <format> [--since <when>] [--category <name>] [--segments] [--output <file>]
+                                 Print the tasks, or with --segments the times worked on them,
+                                 as csv, jsonl, md, ics or org. <when> is today, week, month, all or a date
+    pti import --format <format> <file>
+                                 Add the tasks of a todotxt, md, taskwarrior or org file, - reads stdin
+    pti timesheet [--month <yyyy-mm>] [--client <name>] [--format text|csv]
+                                 Time per day and project with billing, this month by default
+    pti sync init [<url>]        Keep the database in a git repository, committed when pti quits
+                                 or has not saved for five minutes
+    pti sync [--remote <name>]   Pull, merge task by task and push, origin by default
+    pti merge <a.json> <b.json> [-o <out.json>]
+                                 Combine two database files, where both changed a task a wins";
+
+/// Reads `--name value` options, `flags` stand alone without a value.
+fn parse_options<'a>(args: &'a [String], flags: &[&str]) -> AppResult<HashMap<&'a str, &'a str>> {
+    let mut options = HashMap::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            flag if flags.contains(&flag) => {
+                options.insert(flag, "");
+            }
+            name if name.starts_with("--") => {
+                let value = args.next().ok_or_else(|| format!("{} needs a value", name))?;
+                options.insert(name, value.as_str());
+            }
+            _ => return Err(USAGE.into()),
+        }
+    }
+    Ok(options)
+}
+
+/// A period like in the `:report` command, or a date.
+fn parse_since(since: &str) -> AppResult<DateTime<Utc>> {
+    match NaiveDate::parse_from_str(since, "%Y-%m-%d") {
+        Ok(date) => {
+            let start = date.and_hms_opt(0, 0, 0).expect("Midnight is a valid time");
+            Ok(Local.from_local_datetime(&start).earliest().ok_or("Invalid date")?.with_timezone(&Utc))
+        }
+        Err(_) => Ok(period_start(since)?),
+    }
+}
+
+fn category_id(data: &Database, name: &str) -> AppResult<u32> {
+    data.get_category_by_name(name).map(|category| category.id).ok_or_else(|| format!("There is no category '{}'", name).into())
+}
+
+fn parse_kind(name: Option<&String>) -> AppResult<StorageKind> {
+    let name = name.ok_or(USAGE)?;
+    StorageKind::parse(name).ok_or_else(|| format!("Unknown storage '{}', use json or sqlite", name).into())
+}
+
+/// Runs the subcommand given on the command line instead of the user interface.
+pub fn run(args: &[String]) -> AppResult<()> {
+    match args.first().map(|arg| arg.as_str()) {
+        Some("migrate") => {
+            let from = parse_kind(args.get(1))?;
+            let to = parse_kind(args.get(2))?;
+            storage::migrate(from, to)?;
+            println!("Database migrated. Set \"storage\": \"{}\" in the config file to use it.", args[2]);
+            Ok(())
+        }
+        Some("restore") => match args.get(1) {
+            Some(name) => {
+                let (config, _) = Config::load();
+                backup::restore(name, config.storage)?;
+                println!("Restored {}. The previous state was backed up as well.", name);
+                Ok(())
+            }
+            None => {
+                let names = backup::list()?;
+                if names.is_empty() {
+                    println!("There are no backups yet.");
+                }
+                for name in names {
+                    println!("{}", name);
+                }
+                Ok(())
+            }
+        },
+        Some(command @ ("daemon" | "list" | "add" | "start" | "stop" | "events")) => run_daemon_command(command, &args[1..]),
+        Some("serve") => serve(&args[1..]),
+        Some("export") => {
+            let options = parse_options(&args[1..], &["--segments"])?;
+            let format = options.get("--format").ok_or(USAGE)?;
+            let format = export::Format::parse(format).ok_or_else(|| format!("Unknown format '{}', use csv, jsonl, md, ics or org", format))?;
+            let (config, _) = Config::load();
+            let mut storage = storage::open_read_only(config.storage)?;
+            let data = storage.load()?.ok_or("There is no database yet")?;
+            let filter = Filter {
+                since: options.get("--since").map(|since| parse_since(since)).transpose()?.unwrap_or(DateTime::<Utc>::MIN_UTC),
+                category: options.get("--category").map(|name| category_id(&data, name)).transpose()?,
+            };
+            let mut segments = storage.segments_since(filter.since)?;
+            segments.extend(data.active_segments(Utc::now()));
+            let rows = match options.contains_key("--segments") {
+                true => Rows::Segments,
+                false => Rows::Tasks,
+            };
+            let output = export::render(&data, &segments, &filter, format, rows);
+            match options.get("--output") {
+                // Replaced at once, so that a calendar subscribed to the file never reads half of it
+                Some(path) => storage::write_atomic(Path::new(path), output.as_bytes())?,
+                None => print!("{}", output),
+            }
+            Ok(())
+        }
+        Some("import") => {
+            let (file, options) = match args.get(1..).unwrap_or_default() {
+                [options @ .., file] if !file.starts_with("--") || file == "-" => (file, parse_options(options, &[])?),
+                _ => return Err(USAGE.into()),
+            };
+            let format = options.get("--format").ok_or(USAGE)?;
+            let format = import::Format::parse(format).ok_or_else(|| format!("Unknown format '{}', use todotxt, md, taskwarrior or org", format))?;
+            let text = match file.as_str() {
+                "-" => {
+                    let mut text = String::new();
+                    io::stdin().read_to_string(&mut text)?;
+                    text
+                }
+                path => fs::read_to_string(path).map_err(|error| format!("Cannot read {}: {}", path, error))?,
+            };
+            let (config, _) = Config::load();
+            let mut storage = storage::open_writable(config.storage)?;
+            let mut data = storage.load()?.unwrap_or_else(Database::example_db);
+            let tasks = match format {
+                import::Format::TodoTxt => import::todo_txt(&text),
+                import::Format::Markdown => import::markdown(&text, &data),
+                import::Format::Taskwarrior => import::taskwarrior(&text)?,
+                import::Format::Org => import::org(&text),
+            };
+            let summary = import::apply(&mut data, tasks);
+            storage.save(&data)?;
+            // The daemon backs up and commits its own saves
+            if storage.runs_timer() {
+                backup::write(&data)?;
+                sync::commit_database()?;
+            }
+            println!("Imported {} task(s)", summary.tasks);
+            if summary.updated > 0 {
+                println!("Updated {} task(s) which were imported or exported before", summary.updated);
+            }
+            if !summary.categories.is_empty() {
+                println!("New categories: {}", summary.categories.join(", "));
+            }
+            Ok(())
+        }
+        Some("timesheet") => {
+            let options = parse_options(&args[1..], &[])?;
+            let month = options.get("--month").map(|month| month.to_string()).unwrap_or_else(|| Local::now().format("%Y-%m").to_string());
+            let (start, end) = timesheet::parse_month(&month).ok_or_else(|| format!("'{}' is not a month like 2023-06", month))?;
+            let (config, _) = Config::load();
+            let mut storage = storage::open_read_only(config.storage)?;
+            let data = storage.load()?.ok_or("There is no database yet")?;
+            let mut segments = storage.segments_since(timesheet::day_start(start))?;
+            segments.extend(data.active_segments(Utc::now()));
+            let client = options.get("--client").copied();
+            let entries = timesheet::entries(&data, &segments, start, end, client, config.rounding);
+            match options.get("--format").copied().unwrap_or("text") {
+                "text" => {
+                    let title = match client {
+                        Some(client) => format!("Timesheet {}, {}", month, client),
+                        None => format!("Timesheet {}", month),
+                    };
+                    print!("{}", timesheet::text(&entries, &title));
+                }
+                "csv" => print!("{}", timesheet::csv(&entries)),
+                format => return Err(format!("Unknown format '{}', use text or csv", format).into()),
+            }
+            Ok(())
+        }
+        Some("sync") => {
+            let (config, _) = Config::load();
+            if config.storage != StorageKind::Json {
+                return Err("Syncing needs the json storage".into());
+            }
+            if args.get(1).map(String::as_str) == Some("init") {
+                sync::init(args.get(2).map(String::as_str))?;
+                println!("Syncing {} through git", storage::database_path(config.storage).display());
+                return Ok(());
+            }
+            let options = parse_options(&args[1..], &[])?;
+            let remote = options.get("--remote").copied().unwrap_or("origin");
+            let mut storage = storage::open(config.storage)?;
+            match sync::sync(storage.as_mut(), remote)? {
+                sync::Outcome::UpToDate => println!("Already up to date"),
+                sync::Outcome::Pushed => println!("Pushed the local changes to {}", remote),
+                sync::Outcome::FastForwarded => println!("Pulled the changes from {}", remote),
+                sync::Outcome::Merged(conflicts) => {
+                    println!("Merged the changes of both sides and pushed them to {}", remote);
+                    if !conflicts.is_empty() {
+                        println!("Changed differently on both sides, kept as on this machine:");
+                    }
+                    for conflict in conflicts {
+                        println!("  {}", conflict);
+                    }
+                }
+            }
+            Ok(())
+        }
+        Some("merge") => {
+            let (a, b, output) = match args.get(1..).unwrap_or_default() {
+                [a, b] => (a, b, None),
+                [a, b, option, output] if option == "-o" || option == "--output" => (a, b, Some(output)),
+                _ => return Err(USAGE.into()),
+            };
+            let read = |path: &str| -> AppResult<Database> {
+                let text = fs::read_to_string(path).map_err(|error| format!("Cannot read {}: {}", path, error))?;
+                merge::parse(&text).map_err(|error| format!("Cannot load {}: {}", path, error).into())
+            };
+            let (a_data, b_data) = (read(a)?, read(b)?);
+            let merged = merge::merge(None, &a_data, &b_data);
+            if !merged.conflicts.is_empty() {
+                eprintln!("Changed differently in both files, kept as in {}:", a);
+            }
+            for conflict in merged.conflicts.iter() {
+                eprintln!("  {}", conflict);
+            }
+            let serialized = serde_json::to_string_pretty(&merged.database)?;
+            match output {
+                Some(path) => {
+                    storage::write_atomic(Path::new(path), serialized.as_bytes())?;
+                    println!("Wrote {} with {} task(s), {} conflict(s)", path, merged.database.task_ids().len(), merged.conflicts.len());
+                }
+                None => println!("{}", serialized),
+            }
+            Ok(())
+        }
+        Some("help") | Some("--help") | Some("-h") => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        _ => Err(USAGE.into()),
+    }
+}
+
+#[cfg(unix)]
+fn parse_task_id(arg: &String) -> AppResult<u32> {
+    arg.parse().map_err(|_| format!("'{}' is not a task id", arg).into())
+}
+
+/// Commands which run or talk to the daemon.
+#[cfg(unix)]
+fn run_daemon_command(command: &str, args: &[String]) -> AppResult<()> {
+    if command == "daemon" {
+        return server::run();
+    }
+    let mut client = Client::connect()?;
+    match command {
+        "list" => {
+            let database: Database = serde_json::from_value(client.call("list", Value::Null)?)?;
+            for task in database.tasks_printeable() {
+                println!("{} {:>4} {}", task.get_checkbox_string(), task.id, task.get_description_string());
+            }
+        }
+        "add" => {
+            if args.is_empty() {
+                return Err(USAGE.into());
+            }
+            let result = client.call("add", json!({ "text": args.join(" ") }))?;
+            println!("Added task {}", result["id"]);
+        }
+        "start" => {
+            let task = parse_task_id(args.first().ok_or(USAGE)?)?;
+            client.call("start", json!({ "task": task }))?;
+        }
+        "stop" => {
+            let task = args.first().map(parse_task_id).transpose()?;
+            if client.call("stop", json!({ "task": task }))? == Value::Bool(false) {
+                println!("Nothing to stop");
+            }
+        }
+        _ => {
+            client.subscribe()?;
+            loop {
+                let event = client.next_event()?;
+                println!("{}", serde_json::to_string(&event)?);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn run_daemon_command(_command: &str, _args: &[String]) -> AppResult<()> {
+    Err("The daemon needs Unix domain sockets, which this platform lacks".into())
+}
+
+#[cfg(feature = "http")]
+fn serve(args: &[String]) -> AppResult<()> {
+    let port = match args {
+        [] => http::DEFAULT_PORT,
+        [flag, port] if flag == "--port" => port.parse().map_err(|_| format!("'{}' is not a port", port))?,
+        _ => return Err(USAGE.into()),
+    };
+    http::serve(port)
+}
+
+#[cfg(not(feature = "http"))]
+fn serve(_args: &[String]) -> AppResult<()> {
+    Err("pti was built without the HTTP API. Reinstall with --features=http.".into())
+}
@@ -0,0 +1,290 @@
+use chrono::{DateTime, Datelike, Duration, Local, TimeZone, Utc};
+use crate::app::App;
+use crate::hooks::HookEvent;
+use crate::model::Billing;
+
+/// What an argument of a command expects, used for tab completion.
+#[derive(Clone, Copy)]
+#[derive(Debug)]
+enum Arg {
+    Keyword(&'static [&'static str]),
+    Category,
+    Task,
+    Free,
+    /// Free text up to the end of the line, which may have spaces.
+    Rest,
+}
+
+struct CommandSpec {
+    name: &'static str,
+    usage: &'static str,
+    description: &'static str,
+    args: &'static [Arg],
+}
+
+const REPORT_PERIODS: &[&str] = &["today", "week", "month", "all"];
+
+const COMMANDS: &[CommandSpec] = &[
+    CommandSpec { name: "cat new", usage: "cat new <name> [hotkey]", description: "Create a category", args: &[Arg::Free, Arg::Free] },
+    CommandSpec { name: "cat default", usage: "cat default <category>", description: "Make a category the default", args: &[Arg::Category] },
+    CommandSpec { name: "cat toggle", usage: "cat toggle <category>", description: "Toggle visibility of a category", args: &[Arg::Category] },
+    CommandSpec { name: "cat color", usage: "cat color <category> [color]", description: "Set or clear the colour of a category", args: &[Arg::Category, Arg::Free] },
+    CommandSpec { name: "cat billing", usage: "cat billing <category> [client] [rate] [currency] [project]", description: "Set or clear who a category is billed to", args: &[Arg::Category, Arg::Free, Arg::Free, Arg::Free, Arg::Rest] },
+    CommandSpec { name: "archive done", usage: "archive done", description: "Move all checked tasks to the archive", args: &[] },
+    CommandSpec { name: "archive", usage: "archive <task>", description: "Move a task to the archive", args: &[Arg::Task] },
+    CommandSpec { name: "duration", usage: "duration [minutes]", description: "Show or set the pomodoro duration", args: &[Arg::Free] },
+    CommandSpec { name: "report", usage: "report [today|week|month|all]", description: "Time spent per category", args: &[Arg::Keyword(REPORT_PERIODS)] },
+    CommandSpec { name: "check", usage: "check <task>", description: "Check a task", args: &[Arg::Task] },
+    CommandSpec { name: "delete", usage: "delete <task>", description: "Delete a task", args: &[Arg::Task] },
+    CommandSpec { name: "select", usage: "select <task>", description: "Move the cursor to a task", args: &[Arg::Task] },
+    CommandSpec { name: "pomodoro", usage: "pomodoro <task>", description: "Toggle a task in the pomodoro", args: &[Arg::Task] },
+    CommandSpec { name: "help", usage: "help", description: "List all commands", args: &[] },
+    CommandSpec { name: "quit", usage: "quit", description: "Quit pti", args: &[] },
+];
+
+/// Finds the command with the longest name matching the start of the words.
+fn find_command(words: &[&str]) -> Option<&'static CommandSpec> {
+    COMMANDS
+        .iter()
+        .filter(|spec| {
+            let name_words = spec.name.split(' ').collect::<Vec<&str>>();
+            words.len() >= name_words.len() && name_words.iter().zip(words).all(|(a, b)| a == b)
+        })
+        .max_by_key(|spec| spec.name.len())
+}
+
+pub(crate) fn format_duration(duration: Duration) -> String {
+    format!("{}h{:02}m", duration.num_hours(), duration.num_minutes() % 60)
+}
+
+/// Start of the reporting period in local time.
+pub(crate) fn period_start(period: &str) -> Result<DateTime<Utc>, String> {
+    let today = Local::now().date_naive();
+    let start = match period {
+        "today" => today,
+        "week" => today - Duration::days(today.weekday().num_days_from_monday().into()),
+        "month" => today.with_day(1).expect("Every month has a first day"),
+        "all" => return Ok(DateTime::<Utc>::MIN_UTC),
+        _ => return Err(format!("Unknown period '{}', use one of {}", period, REPORT_PERIODS.join(", "))),
+    };
+    let start = start.and_hms_opt(0, 0, 0).expect("Midnight is a valid time");
+    Ok(Local.from_local_datetime(&start).earliest().unwrap_or_else(Local::now).with_timezone(&Utc))
+}
+
+fn parse_task(app: &App, arg: Option<&&str>) -> Result<u32, String> {
+    let arg = arg.ok_or("Missing task id")?;
+    let id = arg.parse::<u32>().map_err(|_| format!("'{}' is not a task id", arg))?;
+    match app.data.has_task(id) {
+        true => Ok(id),
+        false => Err(format!("There is no task {}", id)),
+    }
+}
+
+fn parse_category(app: &App, arg: Option<&&str>) -> Result<u32, String> {
+    let arg = arg.ok_or("Missing category name")?;
+    app.data.get_category_by_name(arg)
+        .map(|category| category.id)
+        .ok_or(format!("There is no category {}", arg))
+}
+
+fn archive_category(app: &App) -> Result<u32, String> {
+    app.data.get_category_by_name("archive")
+        .map(|category| category.id)
+        .ok_or("There is no category named archive, create it with 'cat new archive'".to_string())
+}
+
+/// Executes a command typed into the command line. Returns a message for the status line.
+pub fn execute(input: &str, app: &mut App) -> Result<Option<String>, String> {
+    let words = input.split_whitespace().collect::<Vec<&str>>();
+    if words.is_empty() {
+        return Ok(None);
+    }
+    let spec = find_command(&words).ok_or(format!("Unknown command '{}', see 'help'", words[0]))?;
+    let args = &words[spec.name.split(' ').count()..];
+    if args.len() > spec.args.len() && !matches!(spec.args.last(), Some(Arg::Rest)) {
+        return Err(format!("Usage: {}", spec.usage));
+    }
+
+    match spec.name {
+        "cat new" => {
+            let name = args.first().ok_or(format!("Usage: {}", spec.usage))?;
+            let hotkey = match args.get(1) {
+                Some(hotkey) if hotkey.chars().count() == 1 => hotkey.chars().next(),
+                Some(hotkey) => return Err(format!("Hotkey '{}' must be a single character", hotkey)),
+                None => None,
+            };
+            app.add_category(name.to_string(), hotkey)?;
+            Ok(Some(format!("Created category {}", name)))
+        }
+        "cat default" => {
+            let category = parse_category(app, args.first())?;
+            app.make_default_category(category);
+            Ok(None)
+        }
+        "cat toggle" => {
+            let category = parse_category(app, args.first())?;
+            app.toggle_category_visible(category);
+            Ok(None)
+        }
+        "cat color" => {
+            let category = parse_category(app, args.first())?;
+            app.set_category_color(category, args.get(1).map(|color| color.to_string()))?;
+            Ok(None)
+        }
+        "cat billing" => {
+            let category = parse_category(app, args.first())?;
+            let billing = match args.get(1) {
+                Some(client) => Some(Billing {
+                    client: client.to_string(),
+                    project: args.get(4..).filter(|project| !project.is_empty()).map(|project| project.join(" ")),
+                    rate: args.get(2).map(|rate| rate.parse::<f64>().map_err(|_| format!("'{}' is not a rate per hour", rate))).transpose()?,
+                    currency: args.get(3).map(|currency| currency.to_string()),
+                }),
+                None => None,
+            };
+            let message = match &billing {
+                Some(billing) => format!("Billing {} to {}", args[0], billing.client),
+                None => format!("{} is not billed anymore", args[0]),
+            };
+            app.set_category_billing(category, billing);
+            Ok(Some(message))
+        }
+        "archive done" => {
+            let archive = archive_category(app)?;
+            let moved = app.move_done_tasks(archive);
+            Ok(Some(format!("Archived {} task(s)", moved)))
+        }
+        "archive" => {
+            let task = parse_task(app, args.first())?;
+            let archive = archive_category(app)?;
+            app.data.set_category(task, archive);
+            app.data_changed = true;
+            Ok(None)
+        }
+        "duration" => match args.first() {
+            Some(minutes) => {
+                let minutes = minutes.parse::<u32>().ok().filter(|minutes| *minutes > 0).ok_or(format!("'{}' is not a number of minutes", minutes))?;
+                app.data.set_pomodoro_duration(minutes);
+                app.data_changed = true;
+                Ok(Some(format!("Pomodoros now take {} minutes", minutes)))
+            }
+            None => Ok(Some(format!("Pomodoros take {} minutes", app.data.pomodoro_duration_minutes()))),
+        },
+        "report" => {
+            let period = args.first().copied().unwrap_or("today");
+            let since = period_start(period)?;
+            let times = app.time_per_category(since).map_err(|error| format!("Could not query the database: {}", error))?;
+            let total = times.iter().fold(Duration::zero(), |acc, (_, time)| acc + *time);
+            let mut lines = times.iter()
+                .map(|(category, time)| format!("{:>8}  {}", format_duration(*time), category.name))
+                .collect::<Vec<String>>();
+            lines.push(format!("{:>8}  total", format_duration(total)));
+            app.popup = Some((format!("Report: {}", period), lines));
+            Ok(None)
+        }
+        "check" => {
+            let task = parse_task(app, args.first())?;
+            app.data.check_task(task);
+            app.data_changed = true;
+            if app.data.task_done(task) {
+                app.fire_hook(HookEvent::TaskChecked, &[task]);
+            }
+            Ok(None)
+        }
+        "delete" => {
+            let task = parse_task(app, args.first())?;
+            app.delete_tasks(&[task]);
+            Ok(None)
+        }
+        "select" => {
+            let task = parse_task(app, args.first())?;
+            match app.data.tasks_printeable().iter().any(|printeable| printeable.id == task) {
+                true => {
+                    app.selected_task = Some(task);
+                    Ok(None)
+                }
+                false => Err(format!("Task {} is in a hidden category", task)),
+            }
+        }
+        "pomodoro" => {
+            let task = parse_task(app, args.first())?;
+            let was_running = app.data.pomodoro_end_time().is_some();
+            app.data.toggle_pomodoro(task);
+            app.data_changed = true;
+            if !was_running {
+                app.fire_hook(HookEvent::PomodoroStarted, &[task]);
+            }
+            Ok(None)
+        }
+        "help" => {
+            let lines = COMMANDS.iter()
+                .map(|spec| format!("{:<30}  {}", spec.usage, spec.description))
+                .collect::<Vec<String>>();
+            app.popup = Some(("Commands".to_string(), lines));
+            Ok(None)
+        }
+        "quit" => {
+            app.quit();
+            Ok(None)
+        }
+        _ => unreachable!("Command without implementation"),
+    }
+}
+
+/// Candidates for the word under the cursor, which is the last one of the input.
+fn candidates(input: &str, app: &App) -> Vec<String> {
+    let mut words = input.split_whitespace().collect::<Vec<&str>>();
+    if input.is_empty() || input.ends_with(' ') {
+        words.push("");
+    }
+    let (current, previous) = words.split_last().expect("There is always a current word");
+
+    let mut candidates: Vec<String> = Vec::new();
+    // Further words of command names
+    for spec in COMMANDS {
+        let name_words = spec.name.split(' ').collect::<Vec<&str>>();
+        if name_words.len() > previous.len() && name_words.iter().zip(previous.iter()).all(|(a, b)| a == b) {
+            candidates.push(name_words[previous.len()].to_string());
+        }
+    }
+    // Arguments of the command typed so far
+    if let Some(spec) = find_command(previous) {
+        let position = previous.len() - spec.name.split(' ').count();
+        match spec.args.get(position) {
+            Some(Arg::Keyword(keywords)) => candidates.extend(keywords.iter().map(|keyword| keyword.to_string())),
+            Some(Arg::Category) => candidates.extend(app.data.categories().iter().map(|category| category.name.clone())),
+            Some(Arg::Task) => candidates.extend(app.data.task_ids().iter().map(|id| id.to_string())),
+            Some(Arg::Free) | Some(Arg::Rest) | None => {}
+        }
+    }
+    candidates.retain(|candidate| candidate.starts_with(current));
+    candidates.dedup();
+    candidates
+}
+
+/// Completes the last word of the input. With several candidates it completes
+/// as far as they agree and returns them to be shown.
+pub fn complete(input: &str, app: &App) -> (String, Vec<String>) {
+    let candidates = candidates(input, app);
+    let head = match input.rfind(' ') {
+        Some(index) => &input[..index + 1],
+        None => "",
+    };
+    match candidates.len() {
+        0 => (input.to_string(), candidates),
+        1 => (format!("{}{} ", head, candidates[0]), Vec::new()),
+        _ => {
+            let mut prefix = candidates[0].clone();
+            for candidate in candidates.iter() {
+                while !candidate.starts_with(&prefix) {
+                    prefix.pop();
+                }
+            }
+            let current = &input[head.len()..];
+            if prefix.len() < current.len() {
+                prefix = current.to_string();
+            }
+            (format!("{}{}", head, prefix), candidates)
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use serde::{Serialize, Deserialize};
+use crate::constants::{CONFIG_FILE, get_full_path};
+use crate::hooks::HookEvent;
+use crate::storage::StorageKind;
+
+/// A column of the todo table.
+#[derive(Clone, Copy)]
+#[derive(Serialize)]
+#[derive(Deserialize)]
+#[derive(Eq)]
+#[derive(PartialEq)]
+#[derive(Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Column {
+    Checkbox,
+    Time,
+    Category,
+    Task,
+    Added,
+    Id,
+    Tags,
+    Priority,
+    Estimate,
+    Due,
+}
+
+impl Column {
+    pub fn title(&self) -> &'static str {
+        match self {
+            Column::Checkbox => "",
+            Column::Time => "Time",
+            Column::Category => "Cat",
+            Column::Task => "Task",
+            Column::Added => "Added",
+            Column::Id => "Id",
+            Column::Tags => "Tags",
+            Column::Priority => "Prio",
+            Column::Estimate => "Est",
+            Column::Due => "Due",
+        }
+    }
+}
+
+#[derive(Clone)]
+#[derive(Serialize)]
+#[derive(Deserialize)]
+#[derive(Debug)]
+pub struct ColumnConfig {
+    pub column: Column,
+    /// Width in characters, the column takes up the remaining space if not set.
+    #[serde(default)]
+    pub width: Option<u16>,
+}
+
+impl ColumnConfig {
+    fn new(column: Column, width: Option<u16>) -> ColumnConfig {
+        ColumnConfig { column, width }
+    }
+}
+
+/// Which way worked time is rounded on timesheets.
+#[derive(Clone, Copy)]
+#[derive(Serialize)]
+#[derive(Deserialize)]
+#[derive(Eq)]
+#[derive(PartialEq)]
+#[derive(Default)]
+#[derive(Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum RoundingMode {
+    #[default]
+    Up,
+    Nearest,
+    Down,
+}
+
+/// Rounding of the time per day and project on timesheets.
+#[derive(Clone, Copy)]
+#[derive(Serialize)]
+#[derive(Deserialize)]
+#[derive(Default)]
+#[derive(Debug)]
+pub struct Rounding {
+    /// Rounded to multiples of this, not rounded if 0.
+    #[serde(default)]
+    pub minutes: u32,
+    #[serde(default)]
+    pub mode: RoundingMode,
+}
+
+fn default_theme() -> String {
+    "dark".to_string()
+}
+
+fn default_break_minutes() -> u32 {
+    5
+}
+
+fn default_columns() -> Vec<ColumnConfig> {
+    vec![
+        ColumnConfig::new(Column::Checkbox, Some(3)),
+        ColumnConfig::new(Column::Time, Some(8)),
+        ColumnConfig::new(Column::Category, Some(10)),
+        ColumnConfig::new(Column::Task, None),
+    ]
+}
+
+/// Settings read from the config file in the storage directory.
+#[derive(Clone)]
+#[derive(Serialize)]
+#[derive(Deserialize)]
+#[derive(Debug)]
+pub struct Config {
+    /// One of the builtin themes `dark`, `light` and `monochrome`, or the name
+    /// of a theme file in the storage directory.
+    #[serde(default = "default_theme")]
+    pub theme: String,
+    /// Columns of the todo table, in the order they are shown.
+    #[serde(default = "default_columns")]
+    pub columns: Vec<ColumnConfig>,
+    /// Where the database is kept, `json` or `sqlite`.
+    #[serde(default)]
+    pub storage: StorageKind,
+    /// Executables run on events, e.g. `"pomodoro_finished": "~/bin/dnd-off"`.
+    #[serde(default)]
+    pub hooks: HashMap<HookEvent, String>,
+    /// Length of the break after a pomodoro, for the `break_over` hook.
+    #[serde(default = "default_break_minutes")]
+    pub break_minutes: u32,
+    /// Rounding of timesheets, e.g. `{"minutes": 15, "mode": "up"}`.
+    #[serde(default)]
+    pub rounding: Rounding,
+    /// Origins of web pages and extensions which may call `pti serve`,
+    /// e.g. `moz-extension://<uuid>`. Requests of other origins are refused.
+    #[serde(default)]
+    pub http_origins: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            theme: default_theme(),
+            columns: default_columns(),
+            storage: StorageKind::default(),
+            hooks: HashMap::new(),
+            break_minutes: default_break_minutes(),
+            rounding: Rounding::default(),
+            http_origins: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Loads the config file, falling back to the defaults if there is none.
+    pub fn load() -> (Config, Vec<String>) {
+        let path = get_full_path(CONFIG_FILE);
+        match read_to_string(path.as_path()) {
+            Ok(data) => match serde_json::from_str::<Config>(&data) {
+                Ok(config) => (config, Vec::new()),
+                Err(error) => (Config::default(), vec![format!("Could not parse {}: {}", path.display(), error)]),
+            },
+            Err(_) => (Config::default(), Vec::new()),
+        }
+    }
+}
@@ -3,7 +3,13 @@ use std::env;
 use dirs::home_dir;
 
 pub const TASK_FILE: &str = "database.json";
+pub const SQLITE_FILE: &str = "database.sqlite";
+pub const LOCK_FILE: &str = "database.lock";
+pub const BACKUP_DIR: &str = "backups";
+pub const SOCKET_FILE: &str = "pti.sock";
 pub const ALARM_FILE: &str = "alarm.mp3";
+pub const KEYMAP_FILE: &str = "keymap.json";
+pub const CONFIG_FILE: &str = "config.json";
 
 pub fn get_full_path(file: &str) -> PathBuf {
     let mut dir = match env::var("PTI_STORAGE_DIR") {
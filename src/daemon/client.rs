@@ -0,0 +1,145 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use crate::error::PtiError;
+use crate::model::{Database, Segment};
+use crate::storage::{self, Storage, StorageKind};
+use super::{STALE, Notification, Request, Response, socket_path};
+
+/// How long the user interface waits for an answer before giving up.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A connection to the daemon.
+pub struct Client {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+    next_id: u64,
+}
+
+impl Client {
+    /// Connects to the running daemon, fails if there is none.
+    pub fn connect() -> Result<Client, PtiError> {
+        let writer = UnixStream::connect(socket_path())
+            .map_err(|_| PtiError::Daemon("no daemon is running, start one with `pti daemon`".to_string()))?;
+        let reader = BufReader::new(writer.try_clone().map_err(|error| PtiError::io(&socket_path(), error))?);
+        Ok(Client { reader, writer, next_id: 1 })
+    }
+
+    /// Calls a method and waits for its result. Events arriving in the meantime are skipped.
+    pub fn call(&mut self, method: &str, params: Value) -> Result<Value, PtiError> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let request = Request { jsonrpc: "2.0".to_string(), id: Some(json!(id)), method: method.to_string(), params };
+        let mut line = serde_json::to_vec(&request).map_err(|error| PtiError::Daemon(error.to_string()))?;
+        line.push(b'\n');
+        self.writer.write_all(&line).map_err(|error| PtiError::Daemon(error.to_string()))?;
+        loop {
+            let line = self.read_line()?;
+            // Responses have an id, events don't
+            let response: Response = match serde_json::from_str::<Value>(&line) {
+                Ok(value) if value.get("id").is_some() => serde_json::from_value(value).map_err(|error| PtiError::Daemon(error.to_string()))?,
+                Ok(_) => continue,
+                Err(error) => return Err(PtiError::Daemon(error.to_string())),
+            };
+            if response.id != json!(id) {
+                continue;
+            }
+            return match (response.result, response.error) {
+                (_, Some(error)) if error.code == STALE => Err(PtiError::Stale),
+                (_, Some(error)) => Err(PtiError::Daemon(error.message)),
+                (Some(result), None) => Ok(result),
+                (None, None) => Ok(Value::Null),
+            };
+        }
+    }
+
+    /// Asks for events, which are then read with [`Client::next_event`].
+    pub fn subscribe(&mut self) -> Result<(), PtiError> {
+        self.call("subscribe", Value::Null).map(|_| ())
+    }
+
+    /// Waits for the next event.
+    pub fn next_event(&mut self) -> Result<Notification, PtiError> {
+        loop {
+            let line = self.read_line()?;
+            if let Ok(notification) = serde_json::from_str::<Notification>(&line) {
+                return Ok(notification);
+            }
+        }
+    }
+
+    fn read_line(&mut self) -> Result<String, PtiError> {
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Ok(0) => Err(PtiError::Daemon("the daemon closed the connection".to_string())),
+            Ok(_) => Ok(line),
+            Err(error) => Err(PtiError::Daemon(error.to_string())),
+        }
+    }
+
+    fn set_timeout(&self, timeout: Option<Duration>) -> Result<(), PtiError> {
+        self.writer.set_read_timeout(timeout).map_err(|error| PtiError::Daemon(error.to_string()))
+    }
+}
+
+/// The database as kept by the daemon. The daemon saves to the storage file,
+/// so watching that file is how the user interface notices changes.
+pub struct DaemonStorage {
+    client: Client,
+    path: PathBuf,
+    /// What was last loaded or saved, to answer queries.
+    snapshot: Option<Database>,
+    /// Revision of the snapshot, saving is refused once the daemon has a newer one.
+    revision: Option<u64>,
+}
+
+impl DaemonStorage {
+    pub fn connect(kind: StorageKind) -> Result<DaemonStorage, PtiError> {
+        let client = Client::connect()?;
+        client.set_timeout(Some(TIMEOUT))?;
+        Ok(DaemonStorage { client, path: storage::database_path(kind), snapshot: None, revision: None })
+    }
+}
+
+impl Storage for DaemonStorage {
+    fn load(&mut self) -> Result<Option<Database>, PtiError> {
+        let mut loaded = self.client.call("load", Value::Null)?;
+        let database: Database = serde_json::from_value(loaded["database"].take())
+            .map_err(|error| PtiError::Daemon(error.to_string()))?;
+        self.snapshot = Some(database.clone());
+        self.revision = loaded["revision"].as_u64();
+        Ok(Some(database))
+    }
+
+    /// Fails with [`PtiError::Stale`] if another client saved since the last load or save.
+    fn save(&mut self, database: &Database) -> Result<(), PtiError> {
+        let saved = self.client.call("update", json!({ "database": database, "revision": self.revision }))?;
+        self.snapshot = Some(database.clone());
+        self.revision = saved["revision"].as_u64();
+        Ok(())
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn read_only(&self) -> bool {
+        false
+    }
+
+    fn runs_timer(&self) -> bool {
+        false
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        fs::metadata(&self.path).and_then(|metadata| metadata.modified()).ok()
+    }
+
+    fn segments_since(&mut self, since: DateTime<Utc>) -> Result<Vec<Segment>, PtiError> {
+        Ok(self.snapshot.as_ref().map(|database| database.segments_since(since)).unwrap_or_default())
+    }
+}
@@ -0,0 +1,84 @@
+use std::path::PathBuf;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use crate::constants::{SOCKET_FILE, get_full_path};
+
+/// `pti daemon`, which owns the database and runs the timer.
+pub mod server;
+
+/// Talking to the daemon, from the user interface or the command line.
+pub mod client;
+
+/// JSON-RPC error codes.
+pub const PARSE_ERROR: i64 = -32700;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+/// The request was understood but could not be carried out, e.g. an unknown task.
+pub const FAILED: i64 = -32000;
+/// `update` was based on a revision which is not the latest anymore.
+pub const STALE: i64 = -32001;
+
+/// The socket the daemon listens on, in the storage directory.
+pub fn socket_path() -> PathBuf {
+    get_full_path(SOCKET_FILE)
+}
+
+/// A JSON-RPC 2.0 request, one per line. Without an id no response is sent.
+#[derive(Clone)]
+#[derive(Serialize)]
+#[derive(Deserialize)]
+#[derive(Debug)]
+pub struct Request {
+    pub jsonrpc: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<Value>,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+#[derive(Clone)]
+#[derive(Serialize)]
+#[derive(Deserialize)]
+#[derive(Debug)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+/// The answer to a request, with either a result or an error.
+#[derive(Clone)]
+#[derive(Serialize)]
+#[derive(Deserialize)]
+#[derive(Debug)]
+pub struct Response {
+    pub jsonrpc: String,
+    pub id: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+impl Response {
+    pub fn result(id: Value, result: Value) -> Response {
+        Response { jsonrpc: "2.0".to_string(), id, result: Some(result), error: None }
+    }
+
+    pub fn error(id: Value, code: i64, message: String) -> Response {
+        Response { jsonrpc: "2.0".to_string(), id, result: None, error: Some(RpcError { code, message }) }
+    }
+}
+
+/// An event sent to subscribed clients: `changed` after every save and
+/// `pomodoro_over` with the `tasks` of a pomodoro which just ended.
+#[derive(Clone)]
+#[derive(Serialize)]
+#[derive(Deserialize)]
+#[derive(Debug)]
+pub struct Notification {
+    pub jsonrpc: String,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
@@ -0,0 +1,281 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+use chrono::{Local, Utc};
+use serde::Serialize;
+use serde_json::{json, Value};
+use crate::app::AppResult;
+use crate::backup;
+use crate::sync::PendingCommit;
+use crate::config::Config;
+use crate::error::PtiError;
+use crate::model::Database;
+use crate::hooks::{HookEvent, Hooks};
+use crate::notification::NotificationManager;
+use crate::quickadd;
+use crate::storage::{self, Storage};
+use super::{FAILED, INVALID_PARAMS, METHOD_NOT_FOUND, PARSE_ERROR, STALE, Notification, Request, Response, socket_path};
+
+/// How often the timer is checked when no requests come in.
+const TICK: Duration = Duration::from_secs(1);
+
+/// What the connection threads hand to the main thread, which owns the database.
+enum Message {
+    Call(Request, Sender<Response>),
+    Subscribe(UnixStream),
+}
+
+struct Daemon {
+    storage: Box<dyn Storage>,
+    data: Database,
+    /// Counts the saves, so that `update` can tell if it is based on the latest data.
+    revision: u64,
+    notification_manager: NotificationManager,
+    hooks: Hooks,
+    /// Saves which still have to be committed for syncing.
+    pending_commit: PendingCommit,
+    /// Connections which asked for events, dropped once writing to them fails.
+    subscribers: Vec<UnixStream>,
+}
+
+/// Runs the daemon until it is killed.
+pub fn run() -> AppResult<()> {
+    let (config, _) = Config::load();
+    let mut storage = match storage::open(config.storage) {
+        Ok(storage) => storage,
+        Err(PtiError::Locked(_)) => return Err(PtiError::Daemon("another instance of pti owns the database, close it first".to_string()).into()),
+        Err(error) => return Err(error.into()),
+    };
+    let data = storage.load()?.unwrap_or_else(Database::example_db);
+
+    // Holding the lock means no other daemon is running, so a socket left
+    // behind by one that was killed can go
+    let path = socket_path();
+    if path.exists() {
+        fs::remove_file(&path).map_err(|error| PtiError::io(&path, error))?;
+    }
+    let listener = UnixListener::bind(&path).map_err(|error| PtiError::io(&path, error))?;
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || accept(listener, sender));
+    println!("pti daemon listening on {}", path.display());
+
+    let mut daemon = Daemon {
+        storage,
+        data,
+        revision: 0,
+        notification_manager: NotificationManager::new(),
+        hooks: Hooks::new(&config),
+        pending_commit: PendingCommit::default(),
+        subscribers: Vec::new(),
+    };
+    loop {
+        match receiver.recv_timeout(TICK) {
+            Ok(Message::Call(request, reply)) => {
+                let _ = reply.send(daemon.handle(request));
+            }
+            Ok(Message::Subscribe(stream)) => {
+                // A client which stops reading must not hold up the timer
+                let _ = stream.set_write_timeout(Some(TICK));
+                daemon.subscribers.push(stream);
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+        daemon.tick();
+    }
+}
+
+fn accept(listener: UnixListener, sender: Sender<Message>) {
+    for stream in listener.incoming().flatten() {
+        let sender = sender.clone();
+        thread::spawn(move || serve(stream, sender));
+    }
+}
+
+/// Answers the requests of one connection, one JSON object per line.
+fn serve(stream: UnixStream, sender: Sender<Message>) -> io::Result<()> {
+    let reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let request = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => request,
+            Err(error) => {
+                write_line(&mut writer, &Response::error(Value::Null, PARSE_ERROR, error.to_string()))?;
+                continue;
+            }
+        };
+        let id = request.id.clone();
+        if request.method == "subscribe" {
+            if let Some(id) = id {
+                write_line(&mut writer, &Response::result(id, Value::Bool(true)))?;
+            }
+            // Events go out after the answer, so the client sees them in order
+            if sender.send(Message::Subscribe(writer.try_clone()?)).is_err() {
+                return Ok(());
+            }
+            continue;
+        }
+        let (reply, response) = mpsc::channel();
+        if sender.send(Message::Call(request, reply)).is_err() {
+            return Ok(());
+        }
+        match (id, response.recv()) {
+            (Some(_), Ok(response)) => write_line(&mut writer, &response)?,
+            // Requests without an id are notifications and get no answer
+            (None, _) => {}
+            (Some(_), Err(_)) => return Ok(()),
+        }
+    }
+    Ok(())
+}
+
+fn write_line(stream: &mut UnixStream, message: &impl Serialize) -> io::Result<()> {
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    stream.write_all(&line)
+}
+
+fn task_param(params: &Value) -> Result<Option<u32>, String> {
+    match params.get("task") {
+        None | Some(Value::Null) => Ok(None),
+        Some(task) => task.as_u64().and_then(|task| u32::try_from(task).ok()).map(Some).ok_or_else(|| "task has to be a task id".to_string()),
+    }
+}
+
+impl Daemon {
+    fn handle(&mut self, request: Request) -> Response {
+        let id = request.id.unwrap_or(Value::Null);
+        let params = request.params;
+        let result = match request.method.as_str() {
+            "list" => serde_json::to_value(&self.data).map_err(|error| (FAILED, error.to_string())),
+            "load" => Ok(json!({ "database": self.data, "revision": self.revision })),
+            "add" => self.add(&params),
+            "start" => self.start(&params),
+            "stop" => self.stop(&params),
+            "update" => self.update(params),
+            method => Err((METHOD_NOT_FOUND, format!("Unknown method '{}'", method))),
+        };
+        match result {
+            Ok(result) => Response::result(id, result),
+            Err((code, message)) => Response::error(id, code, message),
+        }
+    }
+
+    /// Adds a task written in quick-add syntax.
+    fn add(&mut self, params: &Value) -> Result<Value, (i64, String)> {
+        let text = params.get("text").and_then(Value::as_str).ok_or((INVALID_PARAMS, "text is missing".to_string()))?;
+        let parsed = quickadd::parse(text, self.data.categories(), Local::now().date_naive());
+        if parsed.description.is_empty() {
+            return Err((INVALID_PARAMS, "The task has no description".to_string()));
+        }
+        let task = self.data.add_parsed_task(&parsed, None);
+        self.save()?;
+        self.fire_hook(HookEvent::TaskAdded, &[task]);
+        Ok(json!({ "id": task }))
+    }
+
+    /// Puts a task into the running pomodoro, starting one if needed.
+    fn start(&mut self, params: &Value) -> Result<Value, (i64, String)> {
+        let task = task_param(params).map_err(|message| (INVALID_PARAMS, message))?.ok_or((INVALID_PARAMS, "task is missing".to_string()))?;
+        if !self.data.has_task(task) {
+            return Err((FAILED, format!("There is no task {}", task)));
+        }
+        if !self.data.task_in_pomodoro(task) {
+            let was_running = self.data.pomodoro_end_time().is_some();
+            self.data.toggle_pomodoro(task);
+            self.save()?;
+            if !was_running {
+                self.fire_hook(HookEvent::PomodoroStarted, &[task]);
+            }
+        }
+        Ok(Value::Bool(true))
+    }
+
+    /// Takes a task out of the pomodoro, or ends the pomodoro without a task.
+    fn stop(&mut self, params: &Value) -> Result<Value, (i64, String)> {
+        match task_param(params).map_err(|message| (INVALID_PARAMS, message))? {
+            Some(task) if !self.data.has_task(task) => return Err((FAILED, format!("There is no task {}", task))),
+            Some(task) if self.data.task_in_pomodoro(task) => {
+                self.data.toggle_pomodoro(task);
+                self.save()?;
+                // Taking out the last task ends the pomodoro
+                if self.data.pomodoro_end_time().is_none() {
+                    self.fire_hook(HookEvent::PomodoroFinished, &[task]);
+                }
+            }
+            Some(_) => return Ok(Value::Bool(false)),
+            None if self.data.pomodoro_end_time().is_some() => {
+                let task_ids = self.data.pomodoro_task_ids();
+                self.data.end_pomodoro_at(Utc::now());
+                self.save()?;
+                self.fire_hook(HookEvent::PomodoroFinished, &task_ids);
+            }
+            None => return Ok(Value::Bool(false)),
+        }
+        Ok(Value::Bool(true))
+    }
+
+    /// Replaces the whole database, this is how the user interface saves. It has
+    /// to send the `revision` it was loaded at and is refused if someone else saved since.
+    fn update(&mut self, params: Value) -> Result<Value, (i64, String)> {
+        let database = params.get("database").cloned().ok_or((INVALID_PARAMS, "database is missing".to_string()))?;
+        let revision = params.get("revision").and_then(Value::as_u64).ok_or((INVALID_PARAMS, "revision is missing".to_string()))?;
+        if revision != self.revision {
+            return Err((STALE, format!("The database changed since revision {}, load it again", revision)));
+        }
+        // A client of an older version may still send an older schema
+        self.data = storage::upgrade_value(self.storage.path(), database).map_err(|error| (INVALID_PARAMS, error.to_string()))?;
+        self.save()?;
+        Ok(json!({ "revision": self.revision }))
+    }
+
+    /// Ends the pomodoro once it is over.
+    fn tick(&mut self) {
+        let task_ids = self.data.pomodoro_task_ids();
+        if let Some(tasks) = self.data.check_active_pomodoro_over() {
+            let body = format!("{} Tasks are over:\n{}", tasks.len(), tasks.join("\n"));
+            self.notification_manager.notify("Pomodoro over!", &body);
+            if let Err((_, message)) = self.save() {
+                eprintln!("Could not save: {}", message);
+            }
+            self.broadcast("pomodoro_over", json!({ "tasks": tasks }));
+            self.fire_hook(HookEvent::PomodoroFinished, &task_ids);
+        }
+        if let Err(error) = self.hooks.tick(&self.data) {
+            eprintln!("{}", error);
+        }
+        if let Err(error) = self.pending_commit.tick(Utc::now()) {
+            eprintln!("Could not commit the database: {}", error);
+        }
+    }
+
+    fn save(&mut self) -> Result<(), (i64, String)> {
+        self.data.last_seen = Some(Utc::now());
+        self.storage.save(&self.data).map_err(|error| (FAILED, error.to_string()))?;
+        self.revision += 1;
+        if let Err(error) = backup::write(&self.data) {
+            eprintln!("Could not write a backup: {}", error);
+        }
+        self.pending_commit.saved();
+        self.broadcast("changed", json!({ "revision": self.revision }));
+        Ok(())
+    }
+
+    fn fire_hook(&mut self, event: HookEvent, task_ids: &[u32]) {
+        if let Err(error) = self.hooks.fire(event, &self.data, task_ids) {
+            eprintln!("{}", error);
+        }
+    }
+
+    fn broadcast(&mut self, method: &str, params: Value) {
+        let notification = Notification { jsonrpc: "2.0".to_string(), method: method.to_string(), params };
+        self.subscribers.retain_mut(|stream| write_line(stream, &notification).is_ok());
+    }
+}
@@ -0,0 +1,78 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::migration::SCHEMA_VERSION;
+
+/// Things that can go wrong with the files pti keeps in the storage directory.
+#[derive(Debug)]
+pub enum PtiError {
+    /// Another instance of pti holds the lock on the database.
+    Locked(PathBuf),
+    /// A file could not be read or written.
+    Io { path: PathBuf, source: io::Error },
+    /// A file is not valid json, or does not fit the database format.
+    Parse { path: PathBuf, line: usize, column: usize, message: String },
+    /// The database was written by a newer version of pti.
+    NewerSchema { path: PathBuf, version: u32 },
+    /// Another instance owns the database, this one may only look at it.
+    ReadOnly,
+    /// Talking to the pti daemon failed.
+    Daemon(String),
+    /// Someone else saved since the database was loaded, saving would drop their changes.
+    Stale,
+    /// The storage backend failed, e.g. with an SQLite error.
+    Storage(String),
+    /// The storage needs a feature pti was built without.
+    Unsupported(String),
+}
+
+impl PtiError {
+    pub fn io(path: &Path, source: io::Error) -> PtiError {
+        PtiError::Io { path: path.to_path_buf(), source }
+    }
+
+    pub fn parse(path: &Path, error: serde_json::Error) -> PtiError {
+        PtiError::Parse {
+            path: path.to_path_buf(),
+            line: error.line(),
+            column: error.column(),
+            message: error.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for PtiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PtiError::Locked(path) => write!(f, "Failed to lock {}. Check if another instance of pti is running.", path.display()),
+            PtiError::Io { path, source } => write!(f, "Could not access {}: {}", path.display(), source),
+            PtiError::Parse { path, message, .. } => write!(f, "Could not parse {}: {}", path.display(), message),
+            PtiError::NewerSchema { path, version } => write!(
+                f,
+                "{} has schema version {}, but this version of pti only understands up to {}. Please update pti.",
+                path.display(), version, SCHEMA_VERSION,
+            ),
+            PtiError::ReadOnly => write!(f, "The database is read-only, another instance of pti owns it."),
+            PtiError::Daemon(message) => write!(f, "pti daemon: {}", message),
+            PtiError::Stale => write!(f, "The database was changed elsewhere since it was loaded, load it again."),
+            PtiError::Storage(message) => write!(f, "Storage error: {}", message),
+            PtiError::Unsupported(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for PtiError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PtiError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for PtiError {
+    fn from(error: rusqlite::Error) -> Self {
+        PtiError::Storage(error.to_string())
+    }
+}
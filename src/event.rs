@@ -5,7 +5,7 @@ use std::thread;
 use std::time::{Duration, Instant};
 
 /// Terminal events.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 pub enum Event {
     /// Terminal tick.
     Tick,
@@ -15,6 +15,8 @@ pub enum Event {
     Mouse(MouseEvent),
     /// Terminal resize.
     Resize(u16, u16),
+    /// Text pasted into the terminal.
+    Paste(String),
 }
 
 /// Terminal event handler.
@@ -48,7 +50,8 @@ impl EventHandler {
                             CrosstermEvent::Key(e) => sender.send(Event::Key(e)),
                             CrosstermEvent::Mouse(e) => sender.send(Event::Mouse(e)),
                             CrosstermEvent::Resize(w, h) => sender.send(Event::Resize(w, h)),
-                            _ => unimplemented!(),
+                            CrosstermEvent::Paste(text) => sender.send(Event::Paste(text)),
+                            CrosstermEvent::FocusGained | CrosstermEvent::FocusLost => Ok(()),
                         }
                         .expect("failed to send terminal event")
                     }
@@ -0,0 +1,469 @@
+use std::collections::HashMap;
+use chrono::{DateTime, Duration, Local, Utc};
+use serde::Serialize;
+use crate::command::format_duration;
+use crate::model::{Database, Priority, PrinteableTask, Segment, get_category_by_id};
+
+#[derive(Clone, Copy)]
+#[derive(Eq)]
+#[derive(PartialEq)]
+#[derive(Debug)]
+pub enum Format {
+    Csv,
+    Jsonl,
+    Markdown,
+    /// iCalendar, with the segments as events and the tasks as todos.
+    Ics,
+    /// Org-mode headings with the segments as clock entries.
+    Org,
+}
+
+/// The formats with a row per task or segment, iCalendar and org have a layout of their own.
+#[derive(Clone, Copy)]
+#[derive(Eq)]
+#[derive(PartialEq)]
+#[derive(Debug)]
+enum Table {
+    Csv,
+    Jsonl,
+    Markdown,
+}
+
+/// What a row of the export stands for.
+#[derive(Clone, Copy)]
+#[derive(Eq)]
+#[derive(PartialEq)]
+#[derive(Debug)]
+pub enum Rows {
+    Tasks,
+    Segments,
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Option<Format> {
+        match name {
+            "csv" => Some(Format::Csv),
+            "jsonl" => Some(Format::Jsonl),
+            "md" | "markdown" => Some(Format::Markdown),
+            "ics" => Some(Format::Ics),
+            "org" => Some(Format::Org),
+            _ => None,
+        }
+    }
+}
+
+/// Which part of the database is exported.
+pub struct Filter {
+    /// Only tasks added or worked on after this, and only the time after it.
+    pub since: DateTime<Utc>,
+    pub category: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct TaskRow {
+    id: u32,
+    parent: Option<u32>,
+    description: String,
+    category: String,
+    done: bool,
+    added: DateTime<Utc>,
+    time_spent_seconds: i64,
+    tags: Vec<String>,
+    priority: Option<Priority>,
+    estimate: Option<u32>,
+    due: Option<String>,
+}
+
+#[derive(Serialize)]
+struct SegmentRow {
+    task_id: u32,
+    task: String,
+    category: String,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    duration_seconds: i64,
+}
+
+fn local_time(time: DateTime<Utc>) -> String {
+    time.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+fn clock(duration: Duration) -> String {
+    format!("{}:{:02}:{:02}", duration.num_hours(), duration.num_minutes() % 60, duration.num_seconds() % 60)
+}
+
+/// Quotes a CSV field if it needs to.
+pub(crate) fn csv_field(field: &str) -> String {
+    match field.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", field.replace('"', "\"\"")),
+        false => field.to_string(),
+    }
+}
+
+fn csv_line(fields: &[String]) -> String {
+    fields.iter().map(|field| csv_field(field)).collect::<Vec<String>>().join(",") + "\n"
+}
+
+fn category_name(data: &Database, id: u32) -> String {
+    match get_category_by_id(data.categories(), id) {
+        Some(category) if category.name != "nocat" => category.name.clone(),
+        _ => String::new(),
+    }
+}
+
+fn jsonl<T: Serialize>(rows: &[T]) -> String {
+    rows.iter().map(|row| serde_json::to_string(row).expect("Rows serialize") + "\n").collect()
+}
+
+/// Renders the export. `segments` are the times worked, only the part after
+/// `filter.since` counts. iCalendar and org always contain both tasks and segments.
+pub fn render(data: &Database, segments: &[Segment], filter: &Filter, format: Format, rows: Rows) -> String {
+    let table = match format {
+        Format::Ics => return ics(data, segments, filter),
+        Format::Org => return org(data, segments, filter),
+        Format::Csv => Table::Csv,
+        Format::Jsonl => Table::Jsonl,
+        Format::Markdown => Table::Markdown,
+    };
+    match rows {
+        Rows::Tasks => tasks(data, segments, filter, table),
+        Rows::Segments => self::segments(data, segments, filter, table),
+    }
+}
+
+/// Time worked per task after `since`.
+fn time_per_task(segments: &[Segment], since: DateTime<Utc>) -> HashMap<u32, Duration> {
+    let mut time_spent: HashMap<u32, Duration> = HashMap::new();
+    for segment in segments {
+        let total = time_spent.get(&segment.task_id).copied().unwrap_or_else(Duration::zero) + segment.time_spent_since(since);
+        time_spent.insert(segment.task_id, total);
+    }
+    time_spent
+}
+
+/// The tasks which were added or worked on after `filter.since`, in tree order.
+fn filtered_tasks(data: &Database, time_spent: &HashMap<u32, Duration>, filter: &Filter) -> Vec<PrinteableTask> {
+    data.task_tree(|task| {
+        (filter.category.is_none() || filter.category == Some(task.category))
+            && (task.date_added >= filter.since || time_spent.get(&task.id).is_some_and(|time| *time > Duration::zero()))
+    })
+}
+
+/// One row per task, or the task tree with checkboxes in Markdown.
+fn tasks(data: &Database, segments: &[Segment], filter: &Filter, table: Table) -> String {
+    let time_spent = time_per_task(segments, filter.since);
+    let time_of = |id: u32| time_spent.get(&id).copied().unwrap_or_else(Duration::zero);
+    let tree = filtered_tasks(data, &time_spent, filter);
+    let rows = || tree.iter().map(|task| TaskRow {
+        id: task.id,
+        parent: task.parent,
+        description: task.description.clone(),
+        category: task.get_category_string(),
+        done: task.done,
+        added: task.date_added,
+        time_spent_seconds: time_of(task.id).num_seconds(),
+        tags: task.tags.clone(),
+        priority: task.priority,
+        estimate: task.estimate,
+        due: task.due.map(|due| due.format("%Y-%m-%d").to_string()),
+    }).collect::<Vec<TaskRow>>();
+
+    match table {
+        Table::Jsonl => jsonl(&rows()),
+        Table::Csv => {
+            let mut csv = csv_line(&["id", "parent", "description", "category", "done", "added", "time_spent", "tags", "priority", "estimate", "due"].map(String::from));
+            for row in rows() {
+                csv.push_str(&csv_line(&[
+                    row.id.to_string(),
+                    row.parent.map(|parent| parent.to_string()).unwrap_or_default(),
+                    row.description,
+                    row.category,
+                    row.done.to_string(),
+                    local_time(row.added),
+                    clock(Duration::seconds(row.time_spent_seconds)),
+                    row.tags.join(" "),
+                    row.priority.map(|priority| priority.to_string()).unwrap_or_default(),
+                    row.estimate.map(|estimate| estimate.to_string()).unwrap_or_default(),
+                    row.due.unwrap_or_default(),
+                ]));
+            }
+            csv
+        }
+        Table::Markdown => tree.iter().map(|task| {
+            let checkbox = if task.done { "[x]" } else { "[ ]" };
+            let details = [task.get_category_string(), match time_of(task.id) {
+                time if time.num_minutes() > 0 => format_duration(time),
+                _ => String::new(),
+            }].into_iter().filter(|detail| !detail.is_empty()).collect::<Vec<String>>();
+            let details = match details.is_empty() {
+                true => String::new(),
+                false => format!(" ({})", details.join(", ")),
+            };
+            format!("{}- {} {}{}\n", "  ".repeat(task.indent as usize), checkbox, task.description, details)
+        }).collect(),
+    }
+}
+
+fn segment_included(segment: &Segment, filter: &Filter) -> bool {
+    (filter.category.is_none() || filter.category == Some(segment.category_id)) && segment.end_time > filter.since
+}
+
+fn task_description(data: &Database, task_id: u32) -> String {
+    data.task_printeable(task_id).map(|task| task.description).unwrap_or_default()
+}
+
+fn segment_rows(data: &Database, segments: &[Segment], filter: &Filter) -> Vec<SegmentRow> {
+    segments.iter()
+        .filter(|segment| segment_included(segment, filter))
+        .map(|segment| SegmentRow {
+            task_id: segment.task_id,
+            task: task_description(data, segment.task_id),
+            category: category_name(data, segment.category_id),
+            start: segment.start_time.max(filter.since),
+            end: segment.end_time,
+            duration_seconds: segment.time_spent_since(filter.since).num_seconds(),
+        })
+        .collect()
+}
+
+/// One row per stretch of time worked on a task, cut off at `filter.since`.
+fn segments(data: &Database, segments: &[Segment], filter: &Filter, table: Table) -> String {
+    let rows = segment_rows(data, segments, filter);
+    match table {
+        Table::Jsonl => jsonl(&rows),
+        Table::Csv => {
+            let mut csv = csv_line(&["task_id", "task", "category", "start", "end", "duration"].map(String::from));
+            for row in rows {
+                csv.push_str(&csv_line(&[
+                    row.task_id.to_string(),
+                    row.task,
+                    row.category,
+                    local_time(row.start),
+                    local_time(row.end),
+                    clock(Duration::seconds(row.duration_seconds)),
+                ]));
+            }
+            csv
+        }
+        Table::Markdown => {
+            let mut markdown = "| Task | Category | Start | End | Duration |\n|---|---|---|---|---|\n".to_string();
+            for row in rows {
+                markdown.push_str(&format!("| {} | {} | {} | {} | {} |\n",
+                    row.task.replace('|', "\\|"),
+                    row.category,
+                    local_time(row.start),
+                    local_time(row.end),
+                    format_duration(Duration::seconds(row.duration_seconds))));
+            }
+            markdown
+        }
+    }
+}
+
+/// Escapes text for an iCalendar property value.
+fn ics_text(text: &str) -> String {
+    text.replace('\\', "\\\\").replace(';', "\\;").replace(',', "\\,").replace('\n', "\\n")
+}
+
+fn ics_time(time: DateTime<Utc>) -> String {
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// Lines longer than 75 bytes are folded onto continuation lines starting with a space.
+fn ics_line(ics: &mut String, line: &str) {
+    let mut rest = line;
+    let mut limit = 75;
+    while rest.len() > limit {
+        let mut split = limit;
+        while !rest.is_char_boundary(split) {
+            split -= 1;
+        }
+        ics.push_str(&rest[..split]);
+        ics.push_str("\r\n ");
+        rest = &rest[split..];
+        // The leading space counts as well
+        limit = 74;
+    }
+    ics.push_str(rest);
+    ics.push_str("\r\n");
+}
+
+/// Built from the uid of the task rather than its number, which changes when tasks are merged.
+fn task_uid(data: &Database, task_id: u32) -> String {
+    format!("{}@pti", data.task_printeable(task_id).map(|task| task.uid).unwrap_or_default())
+}
+
+/// Segments become events and tasks todos. UIDs derive from the uid of the task and
+/// the start of the segment, so a regenerated file updates subscribed calendars in place.
+fn ics(data: &Database, segments: &[Segment], filter: &Filter) -> String {
+    let mut ics = String::new();
+    for line in ["BEGIN:VCALENDAR", "VERSION:2.0", "PRODID:-//pti//pti//EN", "CALSCALE:GREGORIAN", "X-WR-CALNAME:pti"] {
+        ics_line(&mut ics, line);
+    }
+    // Events keep their whole length, also when they started before `since`
+    for segment in segments.iter().filter(|segment| segment_included(segment, filter)) {
+        ics_line(&mut ics, "BEGIN:VEVENT");
+        ics_line(&mut ics, &format!("UID:pomodoro-{}-{}", ics_time(segment.start_time), task_uid(data, segment.task_id)));
+        ics_line(&mut ics, &format!("DTSTAMP:{}", ics_time(segment.end_time)));
+        ics_line(&mut ics, &format!("DTSTART:{}", ics_time(segment.start_time)));
+        ics_line(&mut ics, &format!("DTEND:{}", ics_time(segment.end_time)));
+        ics_line(&mut ics, &format!("SUMMARY:{}", ics_text(&task_description(data, segment.task_id))));
+        let category = category_name(data, segment.category_id);
+        if !category.is_empty() {
+            ics_line(&mut ics, &format!("CATEGORIES:{}", ics_text(&category)));
+        }
+        ics_line(&mut ics, &format!("RELATED-TO:{}", task_uid(data, segment.task_id)));
+        ics_line(&mut ics, "END:VEVENT");
+    }
+    let time_spent = time_per_task(segments, filter.since);
+    for task in filtered_tasks(data, &time_spent, filter) {
+        ics_line(&mut ics, "BEGIN:VTODO");
+        ics_line(&mut ics, &format!("UID:{}@pti", task.uid));
+        ics_line(&mut ics, &format!("DTSTAMP:{}", ics_time(task.date_added)));
+        ics_line(&mut ics, &format!("CREATED:{}", ics_time(task.date_added)));
+        ics_line(&mut ics, &format!("SUMMARY:{}", ics_text(&task.description)));
+        ics_line(&mut ics, &format!("STATUS:{}", if task.done { "COMPLETED" } else { "NEEDS-ACTION" }));
+        if let Some(due) = task.due {
+            ics_line(&mut ics, &format!("DUE;VALUE=DATE:{}", due.format("%Y%m%d")));
+        }
+        if let Some(priority) = task.priority {
+            let level = match priority {
+                Priority::High => 1,
+                Priority::Medium => 5,
+                Priority::Low => 9,
+            };
+            ics_line(&mut ics, &format!("PRIORITY:{}", level));
+        }
+        if !task.get_category_string().is_empty() {
+            ics_line(&mut ics, &format!("CATEGORIES:{}", ics_text(&task.get_category_string())));
+        }
+        if let Some(parent) = task.parent {
+            ics_line(&mut ics, &format!("RELATED-TO;RELTYPE=PARENT:{}", task_uid(data, parent)));
+        }
+        ics_line(&mut ics, "END:VTODO");
+    }
+    ics_line(&mut ics, "END:VCALENDAR");
+    ics
+}
+
+/// An inactive org timestamp like `[2023-06-01 Thu 09:25]`, in local time.
+fn org_time(time: DateTime<Utc>) -> String {
+    time.with_timezone(&Local).format("[%Y-%m-%d %a %H:%M]").to_string()
+}
+
+/// The task tree as org headings, each with its uid as `:ID:` and its `:LOGBOOK:` of `CLOCK:` entries,
+/// newest first as Emacs writes them. Segments keep their whole length.
+fn org(data: &Database, segments: &[Segment], filter: &Filter) -> String {
+    let time_spent = time_per_task(segments, filter.since);
+    let mut org = String::new();
+    for task in filtered_tasks(data, &time_spent, filter) {
+        let mut heading = format!("{} {}", "*".repeat(task.indent as usize + 1), if task.done { "DONE" } else { "TODO" });
+        if let Some(priority) = task.priority {
+            heading.push_str(match priority {
+                Priority::High => " [#A]",
+                Priority::Medium => " [#B]",
+                Priority::Low => " [#C]",
+            });
+        }
+        heading.push(' ');
+        heading.push_str(&task.description);
+        if !task.tags.is_empty() {
+            heading.push_str(&format!(" :{}:", task.tags.join(":")));
+        }
+        org.push_str(&heading);
+        org.push('\n');
+        if let Some(due) = task.due {
+            org.push_str(&format!("DEADLINE: <{}>\n", due.format("%Y-%m-%d %a")));
+        }
+        org.push_str(":PROPERTIES:\n");
+        // Lets an import of the file update the task instead of adding it again
+        org.push_str(&format!(":ID:       {}\n", task.uid));
+        if !task.get_category_string().is_empty() {
+            org.push_str(&format!(":CATEGORY: {}\n", task.get_category_string()));
+        }
+        org.push_str(&format!(":CREATED:  {}\n", org_time(task.date_added)));
+        if let Some(estimate) = task.estimate {
+            org.push_str(&format!(":ESTIMATE: {}\n", estimate));
+        }
+        org.push_str(":END:\n");
+
+        let mut clocks = segments.iter()
+            .filter(|segment| segment.task_id == task.id && segment_included(segment, filter))
+            .collect::<Vec<&Segment>>();
+        if clocks.is_empty() {
+            continue;
+        }
+        clocks.sort_by_key(|segment| std::cmp::Reverse(segment.start_time));
+        org.push_str(":LOGBOOK:\n");
+        for segment in clocks {
+            let duration = segment.end_time - segment.start_time;
+            org.push_str(&format!("CLOCK: {}--{} => {:>2}:{:02}\n",
+                org_time(segment.start_time),
+                org_time(segment.end_time),
+                duration.num_hours(),
+                duration.num_minutes() % 60));
+        }
+        org.push_str(":END:\n");
+    }
+    org
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The lines of a folded iCalendar line, without the line breaks.
+    fn folded(line: &str) -> Vec<String> {
+        let mut ics = String::new();
+        ics_line(&mut ics, line);
+        ics.strip_suffix("\r\n").unwrap().split("\r\n").map(str::to_string).collect()
+    }
+
+    #[test]
+    fn csv_fields_are_quoted_when_needed() {
+        assert_eq!(csv_field("Write the report"), "Write the report");
+        assert_eq!(csv_field("Numbers, graphs"), "\"Numbers, graphs\"");
+        assert_eq!(csv_field("The \"final\" one"), "\"The \"\"final\"\" one\"");
+        assert_eq!(csv_field("Two\nlines"), "\"Two\nlines\"");
+    }
+
+    #[test]
+    fn ics_text_is_escaped() {
+        assert_eq!(ics_text("a;b,c\\d\ne"), "a\\;b\\,c\\\\d\\ne");
+    }
+
+    #[test]
+    fn long_ics_lines_are_folded_at_75_bytes() {
+        let line = format!("SUMMARY:{}", "x".repeat(200));
+        let lines = folded(&line);
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].len(), 75);
+        assert!(lines[1..].iter().all(|line| line.starts_with(' ') && line.len() <= 75));
+        assert_eq!(lines[0].clone() + &lines[1][1..] + &lines[2][1..], line);
+        assert_eq!(folded("SUMMARY:short"), vec!["SUMMARY:short"]);
+    }
+
+    #[test]
+    fn ics_lines_are_not_folded_inside_a_character() {
+        // The two bytes of é would be split by the fold after 75 bytes
+        let line = format!("{}é{}", "x".repeat(74), "y".repeat(10));
+        let lines = folded(&line);
+        assert_eq!(lines[0], "x".repeat(74));
+        assert_eq!(lines[1], format!(" é{}", "y".repeat(10)));
+    }
+
+    #[test]
+    fn ics_uids_follow_the_task_uid() {
+        let mut data = Database::example_db();
+        let today = Local::now().date_naive();
+        let parent = data.add_parsed_task(&crate::quickadd::parse("Write the report", data.categories(), today), None);
+        data.set_task_uid(parent, "01H8XGJWBWBAQ4Z4G5K2Y7N1QF".to_string());
+        let child = data.add_parsed_task(&crate::quickadd::parse("Collect the numbers", data.categories(), today), Some(parent));
+        data.set_task_uid(child, "01H8XGK3T1R6QG0V9C2M5D8E4A".to_string());
+        let filter = Filter { since: Utc::now() - Duration::days(1), category: None };
+        let text = ics(&data, &[], &filter);
+        assert!(text.contains("UID:01H8XGJWBWBAQ4Z4G5K2Y7N1QF@pti\r\n"));
+        assert!(text.contains("UID:01H8XGK3T1R6QG0V9C2M5D8E4A@pti\r\n"));
+        assert!(text.contains("RELATED-TO;RELTYPE=PARENT:01H8XGJWBWBAQ4Z4G5K2Y7N1QF@pti\r\n"));
+    }
+}
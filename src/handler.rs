@@ -1,133 +1,258 @@
-use crate::app::{App, AppResult};
+use crate::app::{App, AppResult, READ_ONLY_MESSAGE};
+use crate::command;
+use crate::keymap::{Action, Key, Lookup, Mode};
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 use tui_textarea::{CursorMove};
 
-fn handle_todo_view_events(key_event: KeyEvent, app: &mut App) -> AppResult<()> {
-    match key_event.code {
-        KeyCode::Esc | KeyCode::Enter => {
-            app.select_no_task();
+/// Executes an action bound in the keymap.
+pub fn dispatch_action(action: Action, app: &mut App) -> AppResult<()> {
+    let mode = app.mode();
+    match action {
+        Action::Quit => {
+            app.quit();
         }
-        KeyCode::Char('x') | KeyCode::Char('X') | KeyCode::Char(' ') => {
-            app.check_task();
+        Action::KeepMine => {
+            app.keep_mine();
         }
-        KeyCode::Char('c') | KeyCode::Char('C') | KeyCode::Char('h') | KeyCode::Char('H') => {
-            app.select_first_category();
+        Action::TakeTheirs => {
+            app.take_theirs();
+        }
+        Action::KeepInterruptedTime => {
+            app.keep_interrupted_pomodoro();
+        }
+        Action::TruncateInterruptedTime => {
+            app.truncate_interrupted_pomodoro();
+        }
+        Action::DiscardInterruptedPomodoro => {
+            app.discard_interrupted_pomodoro();
+        }
+        Action::SelectPrevious => match mode {
+            Mode::Category => app.select_previous_category(),
+            _ => app.select_previous_task(),
+        },
+        Action::SelectNext => match mode {
+            Mode::Category => app.select_next_category(),
+            _ => app.select_next_task(),
+        },
+        Action::SelectFirst => match mode {
+            Mode::Category => app.select_first_category(),
+            _ => app.select_first_task(),
+        },
+        Action::SelectLast => match mode {
+            Mode::Category => app.select_last_category(),
+            _ => app.select_last_task(),
+        },
+        Action::EnterTextInput => {
+            if !app.clear_marks() {
+                app.select_no_task();
+            }
         }
-        KeyCode::Up => {
-            app.select_previous_task();
+        Action::CheckTask => {
+            app.check_task();
         }
-        KeyCode::Down => {
-            app.select_next_task();
+        Action::DeleteTask => {
+            app.delete_task();
         }
-        KeyCode::PageUp => {
+        Action::MoveTaskUp => {
             app.move_task_up();
         }
-        KeyCode::PageDown => {
+        Action::MoveTaskDown => {
             app.move_task_down();
         }
-        KeyCode::Char('p') | KeyCode::Char('P') => {
+        Action::TogglePomodoro => {
             app.toggle_pomodoro();
         }
-        KeyCode::Char('n') | KeyCode::Char('N') => {
+        Action::CategoryView => {
+            app.select_first_category();
+        }
+        Action::TestNotification => {
             app.notification_manager.notify("Test notification", "This is a test notification");
         }
-        // Check for category hotkeys
-        KeyCode::Char(character) => {
-            match app.data.get_category_by_hotkey(character) {
-                Some(category) => {
-                    // Without ctrl, assigns the current task to the category. With crtl, toggles the category visibility
-                    // The with-ctrl case is handled globally
-                    app.set_category(category.id);
-                }
-                None => {}
-            }
+        Action::AddTask => {
+            app.add_task(app.textarea.lines()[0].to_string());
+            app.textarea.move_cursor(CursorMove::Jump(0, 0));
+            app.textarea.delete_line_by_end();
+        }
+        Action::LeaveCategoryView => {
+            app.select_no_category();
+        }
+        Action::LeaveToTextInput => {
+            app.select_no_category();
+            app.select_no_task();
+        }
+        Action::MakeDefaultCategory => {
+            app.make_default_category(app.selected_category.expect("Category handler called without a selected category"));
+        }
+        Action::ToggleCategoryVisible => {
+            app.toggle_category_visible(app.selected_category.expect("Category handler called without a selected category"));
+        }
+        Action::ShowHelp => {
+            app.show_help = true;
+        }
+        Action::OpenCommandLine => {
+            app.command_line = Some(String::new());
+        }
+        Action::ToggleMark => {
+            app.toggle_mark();
+        }
+        Action::VisualMark => {
+            app.toggle_visual_mark();
         }
-        _ => {}
     }
     Ok(())
 }
 
-fn handle_text_view_events(key_event: KeyEvent, app: &mut App) -> AppResult<()> {
-    match key_event.code {
-        // On arrow keys, select a task
-        KeyCode::Up => {
-            app.select_previous_task();
-        }
-        KeyCode::Down => {
-            app.select_next_task();
-        }
-        // On enter, add a task
-        KeyCode::Enter => {
-            app.add_task(app.textarea.lines()[0].to_string());
-            app.textarea.move_cursor(CursorMove::Jump(0, 0));
-            app.textarea.delete_line_by_end();
-        }
-        _ => {
-            app.textarea.input(key_event);
+/// Handles a key which is not bound to any action, i.e. category hotkeys and typing.
+fn handle_unbound_key(key_event: KeyEvent, mode: Mode, app: &mut App) -> AppResult<()> {
+    if let KeyCode::Char(character) = key_event.code {
+        if let Some(category) = app.data.get_category_by_hotkey(character) {
+            // Without ctrl, assigns the current task to the category. With ctrl, toggles the category visibility
+            if key_event.modifiers == KeyModifiers::CONTROL {
+                app.toggle_category_visible(category.id);
+                return Ok(());
+            }
+            if mode == Mode::Todo {
+                app.set_category(category.id);
+                return Ok(());
+            }
         }
     }
+    if mode == Mode::TextInput {
+        app.textarea.input(key_event);
+    }
     Ok(())
 }
 
-fn handle_category_view_events(key_event: KeyEvent, app: &mut App) -> AppResult<()> {
+/// Line editing in the command line.
+fn handle_command_line_events(key_event: KeyEvent, mut input: String, app: &mut App) -> AppResult<()> {
     match key_event.code {
         KeyCode::Esc => {
-            app.select_no_category();
-            app.select_no_task();
+            return Ok(());
         }
-        KeyCode::Char('c') | KeyCode::Char('C') => {
-            app.select_no_category();
+        KeyCode::Char('c') if key_event.modifiers == KeyModifiers::CONTROL => {
+            return Ok(());
         }
-        KeyCode::Up => {
-            app.select_previous_category();
+        KeyCode::Enter => {
+            app.status_message = match command::execute(&input, app) {
+                Ok(message) => message,
+                Err(error) => Some(error),
+            };
+            return Ok(());
         }
-        KeyCode::Down => {
-            app.select_next_category();
+        KeyCode::Tab => {
+            let (completed, candidates) = command::complete(&input, app);
+            input = completed;
+            if candidates.len() > 1 {
+                app.status_message = Some(candidates.join("  "));
+            }
         }
-        KeyCode::Char('d') | KeyCode::Char('D') => {
-            app.make_default_category(app.selected_category.expect("Category handler called without a selected category"));
+        // Like in vim, deleting past the start closes the command line
+        KeyCode::Backspace if input.pop().is_none() => {
+            return Ok(());
         }
-        KeyCode::Char('x') | KeyCode::Char('X') | KeyCode::Char(' ') => {
-            app.toggle_category_visible(app.selected_category.expect("Category handler called without a selected category"));
+        KeyCode::Backspace => {}
+        KeyCode::Char(character) => {
+            input.push(character);
         }
         _ => {}
     }
+    app.command_line = Some(input);
     Ok(())
 }
 
-fn handle_scope_local_key_events(key_event: KeyEvent, app: &mut App) -> AppResult<()> {
-    if app.selected_category == None {
-        if app.selected_task == None {
-            handle_text_view_events(key_event, app)
-        } else {
-            handle_todo_view_events(key_event, app)
-        }
+/// Handles the key events and updates the state of [`App`].
+pub fn handle_key_events(key_event: KeyEvent, app: &mut App) -> AppResult<()> {
+    handle_key(key_event, app)?;
+    // A read-only instance cannot save, so whatever the key changed is undone right away
+    if app.data_changed && app.storage.read_only() {
+        app.reload()?;
+        app.status_message = Some(READ_ONLY_MESSAGE.to_string());
+    }
+    Ok(())
+}
+
+/// The question pti waits for an answer to, before anything else.
+fn prompt_mode(app: &App) -> Option<Mode> {
+    // A conflict with changes made outside of pti has to be resolved first, or left for later
+    if app.conflict.is_some() {
+        Some(Mode::Conflict)
+    } else if app.interruption.is_some() {
+        Some(Mode::Interruption)
     } else {
-        handle_category_view_events(key_event, app)
+        None
     }
 }
 
-/// Handles the key events and updates the state of [`App`].
-pub fn handle_key_events(key_event: KeyEvent, app: &mut App) -> AppResult<()> {
-    match key_event.code {
-        // Exit application on `Ctrl-C`
-        KeyCode::Char('c') | KeyCode::Char('C') | KeyCode::Char('q') | KeyCode::Char('Q') if key_event.modifiers == KeyModifiers::CONTROL => {
-            app.quit();
-            Ok(())
+fn handle_key(key_event: KeyEvent, app: &mut App) -> AppResult<()> {
+    if let Some(mode) = prompt_mode(app) {
+        let answers: &[Action] = match mode {
+            Mode::Conflict => &[Action::KeepMine, Action::TakeTheirs],
+            _ => &[Action::KeepInterruptedTime, Action::TruncateInterruptedTime, Action::DiscardInterruptedPomodoro],
+        };
+        // Quitting leaves the question for the next start
+        if let Lookup::Action(action) = app.keymap.lookup(mode, &[Key::from(key_event)]) {
+            if action == Action::Quit || answers.contains(&action) {
+                dispatch_action(action, app)?;
+            }
         }
-        KeyCode::Char(character) if key_event.modifiers == KeyModifiers::CONTROL => {
-            match app.data.get_category_by_hotkey(character) {
-                Some(category) => {
-                    app.toggle_category_visible(category.id);
-                    Ok(())
-                }
-                None => {
-                    handle_scope_local_key_events(key_event, app)
+        return Ok(());
+    }
+    // Any key closes the help overlay and popups
+    if app.show_help || app.popup.is_some() {
+        app.show_help = false;
+        app.popup = None;
+        return Ok(());
+    }
+    if let Some(input) = app.command_line.take() {
+        return handle_command_line_events(key_event, input, app);
+    }
+    let mode = app.mode();
+    app.pending_keys.push(Key::from(key_event));
+    match app.keymap.lookup(mode, &app.pending_keys) {
+        Lookup::Action(action) => {
+            app.pending_keys.clear();
+            dispatch_action(action, app)
+        }
+        Lookup::Pending => Ok(()),
+        Lookup::NoMatch => {
+            let mut keys = std::mem::take(&mut app.pending_keys);
+            keys.pop();
+            if keys.is_empty() {
+                return handle_unbound_key(key_event, mode, app);
+            }
+            // An unfinished chord was interrupted, so the earlier keys are just typed
+            // and the last key gets a fresh lookup.
+            if mode == Mode::TextInput {
+                for key in keys {
+                    app.textarea.input(KeyEvent::new(key.code, key.modifiers));
                 }
             }
+            handle_key(key_event, app)
         }
-        _ => {
-            handle_scope_local_key_events(key_event, app)
+    }
+}
+
+/// Handles pasted text. A single line goes into the input, several lines become one task each.
+pub fn handle_paste_event(text: String, app: &mut App) -> AppResult<()> {
+    if app.conflict.is_some() || app.interruption.is_some() || app.show_help || app.popup.is_some() || app.mode() == Mode::Category {
+        return Ok(());
+    }
+    if let Some(input) = app.command_line.as_mut() {
+        input.push_str(text.lines().next().unwrap_or_default());
+        return Ok(());
+    }
+    let text = text.replace("\r\n", "\n").replace('\r', "\n");
+    if text.trim_end().lines().count() <= 1 {
+        if app.mode() == Mode::TextInput {
+            app.textarea.insert_str(text.trim_end());
         }
+        return Ok(());
     }
+    if app.storage.read_only() {
+        app.status_message = Some(READ_ONLY_MESSAGE.to_string());
+        return Ok(());
+    }
+    let added = app.add_tasks_from_lines(&text);
+    app.status_message = Some(format!("Added {} task(s)", added));
+    Ok(())
 }
@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::thread;
+use chrono::{DateTime, Duration, Utc};
+use dirs::home_dir;
+use serde::{Serialize, Deserialize};
+use crate::config::Config;
+use crate::model::{Database, PrinteableTask};
+
+/// Something a hook can be run on.
+#[derive(Clone, Copy)]
+#[derive(Serialize)]
+#[derive(Deserialize)]
+#[derive(Eq)]
+#[derive(PartialEq)]
+#[derive(Hash)]
+#[derive(Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum HookEvent {
+    PomodoroStarted,
+    PomodoroFinished,
+    BreakOver,
+    TaskChecked,
+    TaskAdded,
+}
+
+/// What a hook gets on stdin.
+#[derive(Serialize)]
+struct Payload<'a> {
+    event: HookEvent,
+    time: DateTime<Utc>,
+    tasks: &'a [PrinteableTask],
+}
+
+/// Runs the executables configured for events.
+pub struct Hooks {
+    commands: HashMap<HookEvent, String>,
+    break_length: Duration,
+    /// When the break after the last pomodoro is over.
+    break_over_at: Option<DateTime<Utc>>,
+}
+
+/// `~/` at the start of a path stands for the home directory.
+fn expand_home(command: &str) -> PathBuf {
+    match (command.strip_prefix("~/"), home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(command),
+    }
+}
+
+impl Hooks {
+    pub fn new(config: &Config) -> Hooks {
+        Hooks {
+            commands: config.hooks.clone(),
+            break_length: Duration::minutes(config.break_minutes.into()),
+            break_over_at: None,
+        }
+    }
+
+    /// Runs the hook of `event` in the background, if one is configured.
+    pub fn fire(&mut self, event: HookEvent, data: &Database, task_ids: &[u32]) -> Result<(), String> {
+        let now = Utc::now();
+        match event {
+            HookEvent::PomodoroFinished => self.break_over_at = Some(now + self.break_length),
+            HookEvent::PomodoroStarted => self.break_over_at = None,
+            _ => {}
+        }
+        let command = match self.commands.get(&event) {
+            Some(command) => command,
+            None => return Ok(()),
+        };
+        let tasks = task_ids.iter().filter_map(|id| data.task_printeable(*id)).collect::<Vec<PrinteableTask>>();
+        let payload = serde_json::to_string(&Payload { event, time: now, tasks: &tasks }).map_err(|error| error.to_string())?;
+        let event_name = serde_json::to_value(event).ok().and_then(|value| value.as_str().map(str::to_string)).unwrap_or_default();
+        let mut child = Command::new(expand_home(command))
+            .env("PTI_EVENT", &event_name)
+            .env("PTI_TASK_IDS", tasks.iter().map(|task| task.id.to_string()).collect::<Vec<String>>().join(","))
+            .env("PTI_TASK_DESCRIPTIONS", tasks.iter().map(|task| task.description.as_str()).collect::<Vec<&str>>().join("\n"))
+            .env("PTI_TASK_CATEGORIES", tasks.iter().map(|task| task.category.name.as_str()).collect::<Vec<&str>>().join(","))
+            .stdin(Stdio::piped())
+            // Output would end up in the middle of the user interface
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|error| format!("Could not run the {} hook {}: {}", event_name, command, error))?;
+        // Hooks may take their time, pti does not wait for them
+        thread::spawn(move || {
+            if let Some(mut stdin) = child.stdin.take() {
+                let _ = stdin.write_all(payload.as_bytes());
+            }
+            let _ = child.wait();
+        });
+        Ok(())
+    }
+
+    /// Fires `break_over` once the break after a pomodoro has passed without a new one.
+    pub fn tick(&mut self, data: &Database) -> Result<(), String> {
+        match self.break_over_at {
+            Some(end) if end <= Utc::now() => {
+                self.break_over_at = None;
+                match data.pomodoro_end_time() {
+                    Some(_) => Ok(()),
+                    None => self.fire(HookEvent::BreakOver, data, &[]),
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,289 @@
+use std::time::{Duration, SystemTime};
+use chrono::{Local, Utc};
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server};
+use crate::app::AppResult;
+use crate::backup;
+use crate::sync::PendingCommit;
+use crate::config::Config;
+use crate::model::Database;
+use crate::hooks::{HookEvent, Hooks};
+use crate::notification::NotificationManager;
+use crate::quickadd;
+use crate::storage::{self, Storage};
+
+/// The port `pti serve` listens on without `--port`.
+pub const DEFAULT_PORT: u16 = 7431;
+
+/// How often the timer is checked when no requests come in.
+const TICK: Duration = Duration::from_secs(1);
+
+/// A failed request, with its status code.
+type ApiError = (u16, String);
+
+struct Api {
+    port: u16,
+    /// Origins which may call the API, see [`Config::http_origins`].
+    origins: Vec<String>,
+    storage: Box<dyn Storage>,
+    data: Database,
+    last_modified: Option<SystemTime>,
+    notification_manager: NotificationManager,
+    hooks: Hooks,
+    /// Saves which still have to be committed for syncing.
+    pending_commit: PendingCommit,
+}
+
+/// Serves the HTTP API on localhost until killed.
+pub fn serve(port: u16) -> AppResult<()> {
+    let (config, _) = Config::load();
+    // Goes through the daemon if it runs
+    let mut storage = storage::open_writable(config.storage)?;
+    let data = storage.load()?.unwrap_or_else(Database::example_db);
+    let server = Server::http(("127.0.0.1", port)).map_err(|error| format!("Cannot listen on port {}: {}", port, error))?;
+    println!("pti serving on http://127.0.0.1:{}", port);
+
+    let mut api = Api {
+        port,
+        origins: config.http_origins.clone(),
+        last_modified: storage.modified(),
+        storage,
+        data,
+        notification_manager: NotificationManager::new(),
+        hooks: Hooks::new(&config),
+        pending_commit: PendingCommit::default(),
+    };
+    loop {
+        if let Some(request) = server.recv_timeout(TICK)? {
+            api.respond(request);
+        }
+        api.tick();
+    }
+}
+
+fn read_body(request: &mut Request) -> Result<Value, ApiError> {
+    let mut body = String::new();
+    request.as_reader().read_to_string(&mut body).map_err(|error| (400, error.to_string()))?;
+    serde_json::from_str(&body).map_err(|error| (400, format!("The body is not valid JSON: {}", error)))
+}
+
+fn header<'a>(headers: &'a [Header], name: &'static str) -> Option<&'a str> {
+    headers.iter().find(|header| header.field.equiv(name)).map(|header| header.value.as_str())
+}
+
+/// Only lets requests through which are meant for this server: another Host
+/// means a web page reached it through DNS rebinding, another Origin a web page
+/// calling it, and a POST without JSON may be a plain form of a web page.
+fn check_request(method: &Method, headers: &[Header], port: u16, origins: &[String]) -> Result<(), ApiError> {
+    let hosts = [format!("127.0.0.1:{}", port), format!("localhost:{}", port)];
+    if !header(headers, "Host").is_some_and(|host| hosts.contains(&host.to_ascii_lowercase())) {
+        return Err((403, format!("Only requests to {} or {} are served", hosts[0], hosts[1])));
+    }
+    if let Some(origin) = header(headers, "Origin").filter(|origin| !origins.iter().any(|allowed| allowed == origin)) {
+        return Err((403, format!("Requests from {} are not allowed, add it to http_origins in config.json", origin)));
+    }
+    let json = header(headers, "Content-Type")
+        .and_then(|content_type| content_type.split(';').next())
+        .is_some_and(|content_type| content_type.trim().eq_ignore_ascii_case("application/json"));
+    if *method == Method::Post && !json {
+        return Err((415, "POST requests need Content-Type: application/json".to_string()));
+    }
+    Ok(())
+}
+
+fn task_param(body: &Value) -> Result<u32, ApiError> {
+    body.get("task").and_then(Value::as_u64).and_then(|task| u32::try_from(task).ok()).ok_or((400, "task has to be a task id".to_string()))
+}
+
+impl Api {
+    fn respond(&mut self, mut request: Request) {
+        let checked = check_request(request.method(), request.headers(), self.port, &self.origins);
+        // Allowed origins get told so, browsers ask before sending JSON
+        let origin = header(request.headers(), "Origin").filter(|_| checked.is_ok()).map(str::to_string);
+        let (status, body) = match checked.and_then(|_| match request.method() {
+            Method::Options => Ok((204, Value::Null)),
+            _ => self.route(&mut request),
+        }) {
+            Ok((status, body)) => (status, body),
+            Err((status, message)) => (status, json!({ "error": message })),
+        };
+        let header = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).expect("Static header is valid");
+        let mut response = Response::from_string(match body {
+            Value::Null => String::new(),
+            body => body.to_string(),
+        }).with_status_code(status).with_header(header);
+        if let Some(origin) = origin {
+            for (name, value) in [
+                ("Access-Control-Allow-Origin", origin.as_str()),
+                ("Access-Control-Allow-Methods", "GET, POST"),
+                ("Access-Control-Allow-Headers", "Content-Type"),
+                ("Vary", "Origin"),
+            ] {
+                response.add_header(Header::from_bytes(name.as_bytes(), value.as_bytes()).expect("Origin was a valid header"));
+            }
+        }
+        let _ = request.respond(response);
+    }
+
+    fn route(&mut self, request: &mut Request) -> Result<(u16, Value), ApiError> {
+        self.reload_if_changed()?;
+        let path = request.url().split('?').next().unwrap_or_default().to_string();
+        let segments: Vec<&str> = path.split('/').filter(|segment| !segment.is_empty()).collect();
+        let method = request.method().clone();
+        match (&method, segments.as_slice()) {
+            (Method::Get, ["tasks"]) => Ok((200, json!(self.data.tasks_printeable()))),
+            (Method::Post, ["tasks"]) => {
+                let body = read_body(request)?;
+                let text = body.get("text").and_then(Value::as_str).ok_or((400, "text is missing".to_string()))?;
+                let parsed = quickadd::parse(text, self.data.categories(), Local::now().date_naive());
+                if parsed.description.is_empty() {
+                    return Err((400, "The task has no description".to_string()));
+                }
+                let task = self.data.add_parsed_task(&parsed, None);
+                self.save()?;
+                self.fire_hook(HookEvent::TaskAdded, &[task]);
+                Ok((201, json!(self.data.task_printeable(task))))
+            }
+            (Method::Post, ["tasks", task, "check"]) => {
+                let task = task.parse().ok().filter(|&task| self.data.has_task(task)).ok_or((404, format!("There is no task {}", task)))?;
+                self.data.check_task(task);
+                self.save()?;
+                if self.data.task_done(task) {
+                    self.fire_hook(HookEvent::TaskChecked, &[task]);
+                }
+                Ok((200, json!(self.data.task_printeable(task))))
+            }
+            (Method::Post, ["pomodoro", "start"]) => {
+                let task = task_param(&read_body(request)?)?;
+                if !self.data.has_task(task) {
+                    return Err((404, format!("There is no task {}", task)));
+                }
+                if !self.data.task_in_pomodoro(task) {
+                    let was_running = self.data.pomodoro_end_time().is_some();
+                    self.data.toggle_pomodoro(task);
+                    self.save()?;
+                    if !was_running {
+                        self.fire_hook(HookEvent::PomodoroStarted, &[task]);
+                    }
+                }
+                Ok((200, self.pomodoro()))
+            }
+            (Method::Get, ["pomodoro"]) => Ok((200, self.pomodoro())),
+            _ => Err((404, format!("No route for {} {}", method, path))),
+        }
+    }
+
+    /// The running pomodoro and its tasks.
+    fn pomodoro(&self) -> Value {
+        let tasks = self.data.task_ids()
+            .into_iter()
+            .filter(|&task| self.data.task_in_pomodoro(task))
+            .filter_map(|task| self.data.task_printeable(task))
+            .collect::<Vec<_>>();
+        let end = self.data.pomodoro_end_time();
+        json!({
+            "running": end.is_some(),
+            "ends_at": end,
+            "remaining_seconds": end.map(|end| (end - Utc::now()).num_seconds().max(0)),
+            "tasks": tasks,
+        })
+    }
+
+    /// Picks up changes made by the daemon or by hand.
+    fn reload_if_changed(&mut self) -> Result<(), ApiError> {
+        if self.storage.modified() == self.last_modified {
+            return Ok(());
+        }
+        self.last_modified = self.storage.modified();
+        if let Some(data) = self.storage.load().map_err(|error| (500, error.to_string()))? {
+            self.data = data;
+        }
+        Ok(())
+    }
+
+    /// Ends the pomodoro once it is over, unless the daemon does that.
+    fn tick(&mut self) {
+        if !self.storage.runs_timer() {
+            return;
+        }
+        let task_ids = self.data.pomodoro_task_ids();
+        if let Some(tasks) = self.data.check_active_pomodoro_over() {
+            let body = format!("{} Tasks are over:\n{}", tasks.len(), tasks.join("\n"));
+            self.notification_manager.notify("Pomodoro over!", &body);
+            if let Err((_, message)) = self.save() {
+                eprintln!("Could not save: {}", message);
+            }
+            self.fire_hook(HookEvent::PomodoroFinished, &task_ids);
+        }
+        if let Err(error) = self.hooks.tick(&self.data) {
+            eprintln!("{}", error);
+        }
+        if let Err(error) = self.pending_commit.tick(Utc::now()) {
+            eprintln!("Could not commit the database: {}", error);
+        }
+    }
+
+    fn fire_hook(&mut self, event: HookEvent, task_ids: &[u32]) {
+        if let Err(error) = self.hooks.fire(event, &self.data, task_ids) {
+            eprintln!("{}", error);
+        }
+    }
+
+    fn save(&mut self) -> Result<(), ApiError> {
+        self.data.last_seen = Some(Utc::now());
+        self.storage.save(&self.data).map_err(|error| (500, error.to_string()))?;
+        self.last_modified = self.storage.modified();
+        // The daemon backs up and commits its own saves
+        if self.storage.runs_timer() {
+            self.pending_commit.saved();
+            if let Err(error) = backup::write(&self.data) {
+                eprintln!("Could not write a backup: {}", error);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(headers: &[(&str, &str)]) -> Vec<Header> {
+        headers.iter().map(|(name, value)| Header::from_bytes(name.as_bytes(), value.as_bytes()).unwrap()).collect()
+    }
+
+    fn check(method: Method, list: &[(&str, &str)]) -> Result<(), u16> {
+        check_request(&method, &headers(list), 7431, &["moz-extension://pti".to_string()]).map_err(|(status, _)| status)
+    }
+
+    #[test]
+    fn only_local_hosts_are_served() {
+        assert_eq!(check(Method::Get, &[("Host", "127.0.0.1:7431")]), Ok(()));
+        assert_eq!(check(Method::Get, &[("Host", "localhost:7431")]), Ok(()));
+        assert_eq!(check(Method::Get, &[("Host", "attacker.example:7431")]), Err(403));
+        assert_eq!(check(Method::Get, &[("Host", "localhost:8080")]), Err(403));
+        assert_eq!(check(Method::Get, &[]), Err(403));
+    }
+
+    #[test]
+    fn foreign_origins_are_refused() {
+        assert_eq!(check(Method::Get, &[("Host", "localhost:7431"), ("Origin", "https://attacker.example")]), Err(403));
+        assert_eq!(check(Method::Get, &[("Host", "localhost:7431"), ("Origin", "moz-extension://pti")]), Ok(()));
+    }
+
+    #[test]
+    fn posts_need_json() {
+        let host = ("Host", "127.0.0.1:7431");
+        assert_eq!(check(Method::Post, &[host, ("Content-Type", "text/plain")]), Err(415));
+        assert_eq!(check(Method::Post, &[host]), Err(415));
+        assert_eq!(check(Method::Post, &[host, ("Content-Type", "application/json; charset=utf-8")]), Ok(()));
+    }
+
+    #[test]
+    fn task_ids_have_to_fit() {
+        assert_eq!(task_param(&json!({ "task": 3 })), Ok(3));
+        assert!(task_param(&json!({ "task": u64::from(u32::MAX) + 1 })).is_err());
+        assert!(task_param(&json!({ "task": -1 })).is_err());
+        assert!(task_param(&json!({})).is_err());
+    }
+}
@@ -0,0 +1,531 @@
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use serde::Deserialize;
+use serde_json::Value;
+use ulid::Ulid;
+use crate::model::{Database, Priority};
+use crate::quickadd::{self, QuickAdd};
+
+#[derive(Clone, Copy)]
+#[derive(Eq)]
+#[derive(PartialEq)]
+#[derive(Debug)]
+pub enum Format {
+    TodoTxt,
+    Markdown,
+    Taskwarrior,
+    Org,
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Option<Format> {
+        match name {
+            "todotxt" | "todo.txt" => Some(Format::TodoTxt),
+            "md" | "markdown" => Some(Format::Markdown),
+            "taskwarrior" => Some(Format::Taskwarrior),
+            "org" => Some(Format::Org),
+            _ => None,
+        }
+    }
+}
+
+/// A task read from another tool, before it gets an id.
+#[derive(Clone)]
+#[derive(Default)]
+#[derive(Debug)]
+pub struct ImportedTask {
+    /// The uid the task had when pti exported it, to update it instead of adding it again.
+    pub uid: Option<String>,
+    pub description: String,
+    pub done: bool,
+    /// Created if there is no category of this name yet.
+    pub category: Option<String>,
+    pub tags: Vec<String>,
+    pub priority: Option<Priority>,
+    pub due: Option<NaiveDate>,
+    pub estimate: Option<u32>,
+    pub added: Option<DateTime<Utc>>,
+    /// Index of the parent among the tasks read before this one.
+    pub parent: Option<usize>,
+    /// Start and end of the times worked on the task.
+    pub clocks: Vec<(DateTime<Utc>, DateTime<Utc>)>,
+}
+
+/// What an import added to the database.
+pub struct Summary {
+    pub tasks: usize,
+    /// Tasks which were in the database already.
+    pub updated: usize,
+    pub categories: Vec<String>,
+}
+
+fn parse_date(text: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(text, "%Y-%m-%d").ok()
+}
+
+fn local_midnight(date: NaiveDate) -> Option<DateTime<Utc>> {
+    let midnight = date.and_hms_opt(0, 0, 0)?;
+    Local.from_local_datetime(&midnight).earliest().map(|time| time.with_timezone(&Utc))
+}
+
+/// todo.txt priorities go from A to Z, pti only has three.
+fn priority_from_letter(letter: char) -> Priority {
+    match letter {
+        'A' => Priority::High,
+        'B' => Priority::Medium,
+        _ => Priority::Low,
+    }
+}
+
+/// One task per line: `x (A) 2023-06-01 Call mom +family @phone due:2023-06-05`.
+/// The first `+project` becomes the category, `@contexts` become tags.
+pub fn todo_txt(text: &str) -> Vec<ImportedTask> {
+    text.lines().filter(|line| !line.trim().is_empty()).map(|line| {
+        let mut task = ImportedTask::default();
+        let mut words = line.split_whitespace().peekable();
+        if words.peek() == Some(&"x") {
+            task.done = true;
+            words.next();
+            // Completion date
+            if words.peek().and_then(|word| parse_date(word)).is_some() {
+                words.next();
+            }
+        }
+        if let Some(priority) = words.peek().and_then(|word| word.strip_prefix('(')?.strip_suffix(')')) {
+            if let [letter @ 'A'..='Z'] = priority.chars().collect::<Vec<char>>()[..] {
+                task.priority = Some(priority_from_letter(letter));
+                words.next();
+            }
+        }
+        if let Some(added) = words.peek().and_then(|word| parse_date(word)) {
+            task.added = local_midnight(added);
+            words.next();
+        }
+        let mut description = Vec::new();
+        for word in words {
+            match word {
+                _ if word.len() > 1 && word.starts_with('+') => match task.category {
+                    None => task.category = Some(word[1..].to_string()),
+                    Some(_) => task.tags.push(word[1..].to_string()),
+                },
+                _ if word.len() > 1 && word.starts_with('@') => task.tags.push(word[1..].to_string()),
+                _ if word.starts_with("due:") && parse_date(&word[4..]).is_some() => task.due = parse_date(&word[4..]),
+                // Completed tasks keep their priority like this
+                _ if word.len() == 5 && word.starts_with("pri:") => task.priority = word.chars().last().map(priority_from_letter),
+                _ => description.push(word),
+            }
+        }
+        task.description = description.join(" ");
+        task
+    }).filter(|task| !task.description.is_empty()).collect()
+}
+
+/// `- [ ]` and `- [x]` items, indented items become subtasks. Quick-add syntax
+/// in the items is picked up like when pasting, an `@category` which does not
+/// exist yet is created.
+pub fn markdown(text: &str, data: &Database) -> Vec<ImportedTask> {
+    let today = Local::now().date_naive();
+    let mut tasks: Vec<ImportedTask> = Vec::new();
+    // Indentation and index of the items which can still get subitems
+    let mut parents: Vec<(usize, usize)> = Vec::new();
+    for line in text.lines() {
+        let item = quickadd::list_item(line);
+        let (level, done) = match item.checkbox {
+            Some(done) => (item.indent, done),
+            None => continue,
+        };
+        let mut parsed = quickadd::parse(item.text, data.categories(), today);
+        let category = match parsed.category.and_then(|id| data.categories().iter().find(|category| category.id == id)) {
+            Some(category) => Some(category.name.clone()),
+            // Created by apply()
+            None => parsed.unknown_category.take().inspect(|name| {
+                let word = format!("@{}", name);
+                parsed.description = parsed.description.split_whitespace().filter(|other| *other != word).collect::<Vec<&str>>().join(" ");
+            }),
+        };
+        if parsed.description.is_empty() {
+            continue;
+        }
+        while parents.last().is_some_and(|(parent_level, _)| *parent_level >= level) {
+            parents.pop();
+        }
+        tasks.push(ImportedTask {
+            uid: None,
+            description: parsed.description,
+            done,
+            category,
+            tags: parsed.tags,
+            priority: parsed.priority,
+            due: parsed.due,
+            estimate: parsed.estimate,
+            added: None,
+            parent: parents.last().map(|(_, index)| *index),
+            clocks: Vec::new(),
+        });
+        parents.push((level, tasks.len() - 1));
+    }
+    tasks
+}
+
+/// A task of `task export`, only the fields pti has a place for.
+#[derive(Deserialize)]
+struct TaskwarriorTask {
+    description: String,
+    status: String,
+    #[serde(default)]
+    project: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    priority: Option<String>,
+    #[serde(default)]
+    due: Option<String>,
+    #[serde(default)]
+    entry: Option<String>,
+}
+
+/// Taskwarrior writes times like `20230601T120000Z`.
+fn parse_taskwarrior_time(text: &str) -> Option<DateTime<Utc>> {
+    NaiveDateTime::parse_from_str(text, "%Y%m%dT%H%M%SZ").ok().map(|time| Utc.from_utc_datetime(&time))
+}
+
+/// The output of `task export`, a JSON array or one object per line as
+/// older versions write it. Deleted tasks are skipped.
+pub fn taskwarrior(text: &str) -> Result<Vec<ImportedTask>, String> {
+    let values = match serde_json::from_str::<Vec<Value>>(text) {
+        Ok(values) => values,
+        Err(_) => text.lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str::<Value>(line.trim().trim_end_matches(',')))
+            .collect::<Result<Vec<Value>, _>>()
+            .map_err(|error| format!("Not a Taskwarrior export: {}", error))?,
+    };
+    let mut tasks = Vec::new();
+    for value in values {
+        let task: TaskwarriorTask = serde_json::from_value(value).map_err(|error| format!("Not a Taskwarrior task: {}", error))?;
+        if task.status == "deleted" {
+            continue;
+        }
+        tasks.push(ImportedTask {
+            description: task.description,
+            done: task.status == "completed",
+            category: task.project,
+            tags: task.tags,
+            priority: match task.priority.as_deref() {
+                Some("H") => Some(Priority::High),
+                Some("M") => Some(Priority::Medium),
+                Some("L") => Some(Priority::Low),
+                _ => None,
+            },
+            due: task.due.as_deref().and_then(parse_taskwarrior_time).map(|due| due.with_timezone(&Local).date_naive()),
+            added: task.entry.as_deref().and_then(parse_taskwarrior_time),
+            ..ImportedTask::default()
+        });
+    }
+    Ok(tasks)
+}
+
+/// An org timestamp like `[2023-06-01 Thu 09:25]` or `<2023-06-05 Mon>`. The
+/// weekday is skipped, Emacs writes it in the language of the user.
+fn parse_org_time(text: &str) -> Option<DateTime<Utc>> {
+    let inner = text.trim().strip_prefix(['[', '<'])?.strip_suffix([']', '>'])?;
+    let mut parts = inner.split_whitespace();
+    let date = parse_date(parts.next()?)?;
+    match parts.find(|part| part.contains(':')) {
+        Some(time) => {
+            let time = NaiveDateTime::parse_from_str(&format!("{} {}", date, time), "%Y-%m-%d %H:%M").ok()?;
+            Local.from_local_datetime(&time).earliest().map(|time| time.with_timezone(&Utc))
+        }
+        None => local_midnight(date),
+    }
+}
+
+/// Headings with a `TODO` or `DONE` keyword, as written by `pti export --format org`.
+/// Priorities, tags, `DEADLINE:`, the `ID`, `CATEGORY`, `CREATED` and `ESTIMATE`
+/// properties and closed `CLOCK:` entries are read, other headings only nest.
+/// IDs which are no pti uid, e.g. the UUIDs of org-id, are ignored.
+pub fn org(text: &str) -> Vec<ImportedTask> {
+    let mut tasks: Vec<ImportedTask> = Vec::new();
+    // Level and index of the headings which can still get subtasks, None for headings without keyword
+    let mut parents: Vec<(usize, Option<usize>)> = Vec::new();
+    // Whether the lines below the last heading belong to a task
+    let mut in_task = false;
+    for line in text.lines() {
+        let level = line.chars().take_while(|c| *c == '*').count();
+        if level > 0 && line[level..].starts_with(' ') {
+            while parents.last().is_some_and(|(parent_level, _)| *parent_level >= level) {
+                parents.pop();
+            }
+            let mut words = line[level..].split_whitespace().peekable();
+            let done = match words.peek() {
+                Some(&"TODO") => false,
+                Some(&"DONE") => true,
+                _ => {
+                    parents.push((level, None));
+                    in_task = false;
+                    continue;
+                }
+            };
+            words.next();
+            let mut task = ImportedTask { done, ..ImportedTask::default() };
+            if let Some(letter) = words.peek().and_then(|word| word.strip_prefix("[#")?.strip_suffix(']')?.chars().next()) {
+                task.priority = Some(priority_from_letter(letter));
+                words.next();
+            }
+            let mut words = words.collect::<Vec<&str>>();
+            if let Some(tags) = words.last().filter(|word| word.len() > 2 && word.starts_with(':') && word.ends_with(':')) {
+                task.tags = tags.split(':').filter(|tag| !tag.is_empty()).map(str::to_string).collect();
+                words.pop();
+            }
+            task.description = words.join(" ");
+            if task.description.is_empty() {
+                parents.push((level, None));
+                in_task = false;
+                continue;
+            }
+            task.parent = parents.iter().rev().find_map(|(_, index)| *index);
+            tasks.push(task);
+            parents.push((level, Some(tasks.len() - 1)));
+            in_task = true;
+            continue;
+        }
+        let task = match tasks.last_mut() {
+            Some(task) if in_task => task,
+            _ => continue,
+        };
+        let line = line.trim();
+        if let Some(clock) = line.strip_prefix("CLOCK:") {
+            // Clocks still running have no end
+            let range = clock.split("=>").next().unwrap_or_default();
+            if let Some((start, end)) = range.split_once("--") {
+                if let (Some(start), Some(end)) = (parse_org_time(start), parse_org_time(end)) {
+                    task.clocks.push((start, end));
+                }
+            }
+        } else if let Some(deadline) = line.find("DEADLINE:").map(|index| &line[index + 9..]) {
+            let deadline = deadline.trim_start();
+            let end = deadline.find(['>', ']']).map_or(deadline.len(), |index| index + 1);
+            task.due = parse_org_time(&deadline[..end]).map(|due| due.with_timezone(&Local).date_naive());
+        } else if let Some(id) = line.strip_prefix(":ID:") {
+            task.uid = Some(id.trim().to_string()).filter(|id| Ulid::from_string(id).is_ok());
+        } else if let Some(category) = line.strip_prefix(":CATEGORY:") {
+            task.category = Some(category.trim().to_string()).filter(|category| !category.is_empty());
+        } else if let Some(created) = line.strip_prefix(":CREATED:") {
+            task.added = parse_org_time(created);
+        } else if let Some(estimate) = line.strip_prefix(":ESTIMATE:") {
+            task.estimate = estimate.trim().parse().ok();
+        }
+    }
+    tasks
+}
+
+/// Org clocks only have minutes, so a clock which matches a pomodoro to the
+/// minute is that pomodoro coming back.
+fn same_minute(time: DateTime<Utc>, other: DateTime<Utc>) -> bool {
+    time.timestamp().div_euclid(60) == other.timestamp().div_euclid(60)
+}
+
+/// Adds the tasks to the database, creating their categories on demand. Tasks
+/// with the uid of a task in the database update that task, their clocks are
+/// added unless they are recorded already.
+pub fn apply(data: &mut Database, tasks: Vec<ImportedTask>) -> Summary {
+    let mut summary = Summary { tasks: 0, updated: 0, categories: Vec::new() };
+    let mut ids: Vec<u32> = Vec::new();
+    for task in tasks {
+        let category = task.category.as_ref().map(|name| match data.get_category_by_name(name) {
+            Some(category) => category.id,
+            None => {
+                summary.categories.push(name.clone());
+                data.add_category(name.clone(), None).expect("Category without hotkey is new")
+            }
+        });
+        let parsed = QuickAdd {
+            description: task.description,
+            category,
+            tags: task.tags,
+            priority: task.priority,
+            due: task.due,
+            estimate: task.estimate,
+            ..QuickAdd::default()
+        };
+        let parent = task.parent.and_then(|index| ids.get(index).copied());
+        let id = match task.uid.as_deref().and_then(|uid| data.task_with_uid(uid)) {
+            Some(id) => {
+                data.update_parsed_task(id, &parsed, parent);
+                summary.updated += 1;
+                id
+            }
+            None => {
+                let id = data.add_parsed_task(&parsed, parent);
+                if let Some(uid) = task.uid {
+                    data.set_task_uid(id, uid);
+                }
+                if let Some(added) = task.added {
+                    data.set_task_added(id, added);
+                }
+                summary.tasks += 1;
+                id
+            }
+        };
+        data.set_task_done(id, task.done);
+        let recorded = data.segments_since(DateTime::<Utc>::MIN_UTC)
+            .into_iter()
+            .filter(|segment| segment.task_id == id)
+            .collect::<Vec<_>>();
+        for (start, end) in task.clocks {
+            if !recorded.iter().any(|segment| same_minute(segment.start_time, start) && same_minute(segment.end_time, end)) {
+                data.add_past_pomodoro(id, start, end);
+            }
+        }
+        ids.push(id);
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use crate::export::{self, Filter, Rows};
+    use super::*;
+
+    fn time(text: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(text).unwrap().with_timezone(&Utc)
+    }
+
+    fn database() -> Database {
+        let mut data = Database::example_db();
+        let parent = data.add_parsed_task(&QuickAdd { description: "Write the report".to_string(), ..QuickAdd::default() }, None);
+        let child = data.add_parsed_task(&QuickAdd { description: "Collect the numbers".to_string(), ..QuickAdd::default() }, Some(parent));
+        data.add_past_pomodoro(child, time("2023-06-01T09:00:13Z"), time("2023-06-01T09:25:13Z"));
+        data
+    }
+
+    fn org_export(data: &Database) -> String {
+        let filter = Filter { since: DateTime::<Utc>::MIN_UTC, category: None };
+        export::render(data, &data.segments_since(filter.since), &filter, export::Format::Org, Rows::Tasks)
+    }
+
+    #[test]
+    fn org_round_trip_changes_nothing() {
+        let mut data = database();
+        let before = data.clone();
+        let tasks = org(&org_export(&data));
+        let summary = apply(&mut data, tasks);
+        assert_eq!((summary.tasks, summary.updated), (0, 2));
+        assert_eq!(data, before);
+    }
+
+    #[test]
+    fn org_round_trip_takes_edits_and_new_clocks() {
+        let mut data = database();
+        let child = data.task_ids()[1];
+        let text = org_export(&data)
+            .replace("** TODO Collect the numbers", "** DONE Collect the numbers")
+            .replace(":LOGBOOK:\n", ":LOGBOOK:\nCLOCK: [2023-06-02 Fri 10:00]--[2023-06-02 Fri 10:30] =>  0:30\n");
+        apply(&mut data, org(&text));
+        assert!(data.task_done(child));
+        let segments = data.segments_since(DateTime::<Utc>::MIN_UTC);
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].start_time, time("2023-06-01T09:00:13Z"));
+        assert_eq!(segments[1].end_time - segments[1].start_time, Duration::minutes(30));
+    }
+
+    #[test]
+    fn org_ids_of_other_tools_are_ignored() {
+        let tasks = org("* TODO Call mom\n:PROPERTIES:\n:ID: 5f0c6d2e-8f3a-4c55-9d1b-3b7b1c0e2a11\n:END:\n");
+        assert_eq!(tasks[0].uid, None);
+    }
+
+    fn date(text: &str) -> NaiveDate {
+        parse_date(text).unwrap()
+    }
+
+    #[test]
+    fn todo_txt_reads_priorities_projects_and_contexts() {
+        let tasks = todo_txt("(B) 2023-06-01 Write the report +work +report @desk due:2023-06-05\n\n");
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].description, "Write the report");
+        assert!(!tasks[0].done);
+        assert_eq!(tasks[0].priority, Some(Priority::Medium));
+        assert_eq!(tasks[0].category.as_deref(), Some("work"));
+        assert_eq!(tasks[0].tags, vec!["report", "desk"]);
+        assert_eq!(tasks[0].due, Some(date("2023-06-05")));
+        assert_eq!(tasks[0].added, local_midnight(date("2023-06-01")));
+    }
+
+    #[test]
+    fn todo_txt_skips_the_completion_date_of_done_tasks() {
+        let tasks = todo_txt("x 2023-06-02 2023-06-01 Call mom +family pri:A\nx Water the plants");
+        assert!(tasks.iter().all(|task| task.done));
+        assert_eq!(tasks[0].description, "Call mom");
+        assert_eq!(tasks[0].added, local_midnight(date("2023-06-01")));
+        assert_eq!(tasks[0].priority, Some(Priority::High));
+        assert_eq!(tasks[1].description, "Water the plants");
+        assert_eq!(tasks[1].added, None);
+    }
+
+    #[test]
+    fn taskwarrior_reads_both_export_formats() {
+        let array = r#"[
+            {"description": "Call mom", "status": "pending", "project": "family", "tags": ["phone"], "priority": "H", "entry": "20230601T120000Z"},
+            {"description": "Old idea", "status": "deleted"},
+            {"description": "Pay rent", "status": "completed"}
+        ]"#;
+        let tasks = taskwarrior(array).unwrap();
+        assert_eq!(tasks.iter().map(|task| task.description.as_str()).collect::<Vec<_>>(), vec!["Call mom", "Pay rent"]);
+        assert_eq!(tasks[0].category.as_deref(), Some("family"));
+        assert_eq!(tasks[0].tags, vec!["phone"]);
+        assert_eq!(tasks[0].priority, Some(Priority::High));
+        assert_eq!(tasks[0].added, Some(time("2023-06-01T12:00:00Z")));
+        assert!(tasks[1].done);
+
+        let lines = "{\"description\": \"Call mom\", \"status\": \"pending\"},\n{\"description\": \"Pay rent\", \"status\": \"completed\"}\n";
+        assert_eq!(taskwarrior(lines).unwrap().len(), 2);
+        assert!(taskwarrior("not json").is_err());
+    }
+
+    #[test]
+    fn org_reads_headings_with_their_details() {
+        let text = "\
+* Projects
+** TODO [#A] Write the report :work:writing:
+   DEADLINE: <2023-06-05 Mon>
+   :PROPERTIES:
+   :CATEGORY: work
+   :ESTIMATE: 3
+   :END:
+   :LOGBOOK:
+   CLOCK: [2023-06-01 Thu 09:00]--[2023-06-01 Thu 09:25] =>  0:25
+   CLOCK: [2023-06-01 Thu 10:00]
+   :END:
+*** DONE Collect the numbers
+* Notes
+** Not a task
+";
+        let tasks = org(text);
+        assert_eq!(tasks.len(), 2);
+        let report = &tasks[0];
+        assert_eq!(report.description, "Write the report");
+        assert_eq!(report.priority, Some(Priority::High));
+        assert_eq!(report.tags, vec!["work", "writing"]);
+        assert_eq!(report.due, Some(date("2023-06-05")));
+        assert_eq!(report.category.as_deref(), Some("work"));
+        assert_eq!(report.estimate, Some(3));
+        assert_eq!(report.parent, None);
+        // The running clock has no end yet
+        assert_eq!(report.clocks.len(), 1);
+        assert_eq!(report.clocks[0].1 - report.clocks[0].0, Duration::minutes(25));
+        assert!(tasks[1].done);
+        assert_eq!(tasks[1].parent, Some(0));
+    }
+
+    #[test]
+    fn markdown_creates_unknown_categories() {
+        let mut data = Database::example_db();
+        let tasks = markdown("- [ ] Plan the trip @travel !high\n", &data);
+        assert_eq!(tasks[0].description, "Plan the trip");
+        assert_eq!(tasks[0].category.as_deref(), Some("travel"));
+        let summary = apply(&mut data, tasks);
+        assert_eq!(summary.categories, vec!["travel"]);
+        assert!(data.get_category_by_name("travel").is_some());
+    }
+}
@@ -0,0 +1,572 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::read_to_string;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use serde::{Serialize, Deserialize};
+use crate::constants::{KEYMAP_FILE, get_full_path};
+use crate::model::Category;
+
+/// The view a key binding is active in.
+#[derive(Clone, Copy)]
+#[derive(Serialize)]
+#[derive(Deserialize)]
+#[derive(Eq)]
+#[derive(PartialEq)]
+#[derive(Hash)]
+#[derive(Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    Global,
+    Todo,
+    TextInput,
+    Category,
+    /// Asking which side of a conflicting change to keep.
+    Conflict,
+    /// Asking what to do with a pomodoro which ran while pti was closed.
+    Interruption,
+}
+
+/// Everything a key binding can trigger.
+#[derive(Clone, Copy)]
+#[derive(Serialize)]
+#[derive(Deserialize)]
+#[derive(Eq)]
+#[derive(PartialEq)]
+#[derive(Hash)]
+#[derive(Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Quit,
+    SelectPrevious,
+    SelectNext,
+    SelectFirst,
+    SelectLast,
+    EnterTextInput,
+    CheckTask,
+    DeleteTask,
+    MoveTaskUp,
+    MoveTaskDown,
+    TogglePomodoro,
+    CategoryView,
+    TestNotification,
+    AddTask,
+    LeaveCategoryView,
+    LeaveToTextInput,
+    MakeDefaultCategory,
+    ToggleCategoryVisible,
+    ShowHelp,
+    OpenCommandLine,
+    ToggleMark,
+    VisualMark,
+    KeepMine,
+    TakeTheirs,
+    KeepInterruptedTime,
+    TruncateInterruptedTime,
+    DiscardInterruptedPomodoro,
+}
+
+impl Action {
+    /// Short explanation shown in the help overlay.
+    pub fn description(&self) -> &'static str {
+        match self {
+            Action::Quit => "Quit",
+            Action::SelectPrevious => "Move cursor up",
+            Action::SelectNext => "Move cursor down",
+            Action::SelectFirst => "Jump to the first entry",
+            Action::SelectLast => "Jump to the last entry",
+            Action::EnterTextInput => "Start typing a task, clears marks first",
+            Action::CheckTask => "Check a task",
+            Action::DeleteTask => "Delete a task, subtasks move up one level",
+            Action::MoveTaskUp => "Move task up",
+            Action::MoveTaskDown => "Move task down",
+            Action::TogglePomodoro => "Toggle task in the pomodoro, start one if not running",
+            Action::CategoryView => "Category view",
+            Action::TestNotification => "Test notifications",
+            Action::AddTask => "Save task",
+            Action::LeaveCategoryView => "Back to the todo list",
+            Action::LeaveToTextInput => "Back to typing a task",
+            Action::MakeDefaultCategory => "Make category default",
+            Action::ToggleCategoryVisible => "Toggle visibility of the category",
+            Action::ShowHelp => "Show this help",
+            Action::OpenCommandLine => "Open the command line, see :help",
+            Action::ToggleMark => "Mark a task for bulk operations",
+            Action::VisualMark => "Start or end marking a range of tasks",
+            Action::KeepMine => "Keep my side of a conflict",
+            Action::TakeTheirs => "Take the side changed outside of pti",
+            Action::KeepInterruptedTime => "Keep the time of an interrupted pomodoro",
+            Action::TruncateInterruptedTime => "Only count an interrupted pomodoro until the interruption",
+            Action::DiscardInterruptedPomodoro => "Discard an interrupted pomodoro",
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+#[derive(Serialize)]
+#[derive(Deserialize)]
+#[derive(Eq)]
+#[derive(PartialEq)]
+#[derive(Debug)]
+#[derive(Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Preset {
+    #[default]
+    Default,
+    Vim,
+}
+
+/// A single key press, normalized so that it can be compared against bindings.
+#[derive(Clone, Copy)]
+#[derive(Eq)]
+#[derive(PartialEq)]
+#[derive(Hash)]
+#[derive(Debug)]
+pub struct Key {
+    pub code: KeyCode,
+    pub modifiers: KeyModifiers,
+}
+
+impl Key {
+    pub fn new(code: KeyCode, modifiers: KeyModifiers) -> Key {
+        // The shift state of characters is already part of the character itself
+        let modifiers = match code {
+            KeyCode::Char(_) => modifiers - KeyModifiers::SHIFT,
+            _ => modifiers,
+        };
+        Key { code, modifiers }
+    }
+
+    pub fn plain(character: char) -> Key {
+        Key::new(KeyCode::Char(character), KeyModifiers::NONE)
+    }
+
+    pub fn ctrl(character: char) -> Key {
+        Key::new(KeyCode::Char(character), KeyModifiers::CONTROL)
+    }
+
+    /// Parses keys like `x`, `G`, `space`, `pagedown` or `ctrl+q`. `shift+` is
+    /// refused for characters, they are written as typed, e.g. `G`.
+    pub fn parse(text: &str) -> Result<Key, String> {
+        let mut modifiers = KeyModifiers::NONE;
+        let mut rest = text;
+        // Split off modifiers, but keep a literal '+' as key
+        while let Some((modifier, tail)) = rest.split_once('+') {
+            if tail.is_empty() {
+                break;
+            }
+            modifiers |= match modifier.to_lowercase().as_str() {
+                "ctrl" | "control" => KeyModifiers::CONTROL,
+                "alt" => KeyModifiers::ALT,
+                "shift" => KeyModifiers::SHIFT,
+                _ => return Err(format!("Unknown modifier '{}' in key '{}'", modifier, text)),
+            };
+            rest = tail;
+        }
+        let mut chars = rest.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(character), None) => KeyCode::Char(character),
+            _ => match rest.to_lowercase().as_str() {
+                "space" => KeyCode::Char(' '),
+                "enter" | "return" => KeyCode::Enter,
+                "esc" | "escape" => KeyCode::Esc,
+                "tab" => KeyCode::Tab,
+                "backtab" => KeyCode::BackTab,
+                "backspace" => KeyCode::Backspace,
+                "delete" | "del" => KeyCode::Delete,
+                "insert" | "ins" => KeyCode::Insert,
+                "home" => KeyCode::Home,
+                "end" => KeyCode::End,
+                "up" => KeyCode::Up,
+                "down" => KeyCode::Down,
+                "left" => KeyCode::Left,
+                "right" => KeyCode::Right,
+                "pageup" | "pgup" => KeyCode::PageUp,
+                "pagedown" | "pgdown" | "pgdn" => KeyCode::PageDown,
+                name => match name.strip_prefix('f').and_then(|number| number.parse::<u8>().ok()) {
+                    Some(number) if (1..=12).contains(&number) => KeyCode::F(number),
+                    _ => return Err(format!("Unknown key '{}'", text)),
+                },
+            },
+        };
+        // Would silently bind the lowercase character
+        if let (KeyCode::Char(character), true) = (code, modifiers.contains(KeyModifiers::SHIFT)) {
+            return Err(format!("Write shifted characters as they are typed, e.g. '{}' instead of '{}'", character.to_uppercase(), text));
+        }
+        Ok(Key::new(code, modifiers))
+    }
+}
+
+impl From<KeyEvent> for Key {
+    fn from(key_event: KeyEvent) -> Key {
+        Key::new(key_event.code, key_event.modifiers)
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            write!(f, "ctrl+")?;
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            write!(f, "alt+")?;
+        }
+        if self.modifiers.contains(KeyModifiers::SHIFT) {
+            write!(f, "shift+")?;
+        }
+        match self.code {
+            KeyCode::Char(' ') => write!(f, "space"),
+            KeyCode::Char(character) => write!(f, "{}", character),
+            KeyCode::Enter => write!(f, "enter"),
+            KeyCode::Esc => write!(f, "esc"),
+            KeyCode::Tab => write!(f, "tab"),
+            KeyCode::BackTab => write!(f, "backtab"),
+            KeyCode::Backspace => write!(f, "backspace"),
+            KeyCode::Delete => write!(f, "delete"),
+            KeyCode::Insert => write!(f, "insert"),
+            KeyCode::Home => write!(f, "home"),
+            KeyCode::End => write!(f, "end"),
+            KeyCode::Up => write!(f, "up"),
+            KeyCode::Down => write!(f, "down"),
+            KeyCode::Left => write!(f, "left"),
+            KeyCode::Right => write!(f, "right"),
+            KeyCode::PageUp => write!(f, "pageup"),
+            KeyCode::PageDown => write!(f, "pagedown"),
+            KeyCode::F(number) => write!(f, "f{}", number),
+            other => write!(f, "{:?}", other),
+        }
+    }
+}
+
+/// A sequence of keys which triggers an action, e.g. `g g` in the vim preset.
+pub type Chord = Vec<Key>;
+
+pub fn chord_string(chord: &[Key]) -> String {
+    chord.iter().map(|key| key.to_string()).collect::<Vec<String>>().join(" ")
+}
+
+/// Keys in a chord are separated by whitespace, e.g. `d d` or `ctrl+q`.
+pub fn parse_chord(text: &str) -> Result<Chord, String> {
+    let chord = text.split_whitespace().map(Key::parse).collect::<Result<Chord, String>>()?;
+    if chord.is_empty() {
+        return Err("Empty key binding".to_string());
+    }
+    Ok(chord)
+}
+
+#[derive(Clone)]
+#[derive(Debug)]
+pub struct Binding {
+    pub mode: Mode,
+    pub action: Action,
+    pub chord: Chord,
+}
+
+/// The on-disk format of the keymap file. Bindings given here replace the
+/// preset bindings of the same action in the same mode.
+#[derive(Serialize)]
+#[derive(Deserialize)]
+#[derive(Default)]
+#[derive(Debug)]
+pub struct KeymapFile {
+    #[serde(default)]
+    pub preset: Preset,
+    #[serde(default)]
+    pub bindings: HashMap<Mode, HashMap<Action, Vec<String>>>,
+}
+
+/// Result of looking up the keys pressed so far.
+#[derive(Eq)]
+#[derive(PartialEq)]
+#[derive(Debug)]
+pub enum Lookup {
+    Action(Action),
+    /// The keys are the beginning of a longer chord, wait for more.
+    Pending,
+    NoMatch,
+}
+
+#[derive(Clone)]
+#[derive(Debug)]
+pub struct Keymap {
+    pub bindings: Vec<Binding>,
+}
+
+fn preset_bindings(preset: Preset) -> Vec<(Mode, Action, &'static [&'static str])> {
+    let mut bindings: Vec<(Mode, Action, &'static [&'static str])> = vec![
+        (Mode::Global, Action::Quit, &["ctrl+q", "ctrl+Q", "ctrl+c", "ctrl+C"]),
+        (Mode::Global, Action::ShowHelp, &["f1"]),
+
+        (Mode::Todo, Action::SelectPrevious, &["up"]),
+        (Mode::Todo, Action::SelectNext, &["down"]),
+        (Mode::Todo, Action::MoveTaskUp, &["pageup"]),
+        (Mode::Todo, Action::MoveTaskDown, &["pagedown"]),
+        (Mode::Todo, Action::EnterTextInput, &["esc", "enter"]),
+        (Mode::Todo, Action::CheckTask, &["x", "X", "space"]),
+        (Mode::Todo, Action::TogglePomodoro, &["p", "P"]),
+        (Mode::Todo, Action::CategoryView, &["c", "C", "h", "H"]),
+        (Mode::Todo, Action::TestNotification, &["n", "N"]),
+        (Mode::Todo, Action::DeleteTask, &["delete"]),
+        (Mode::Todo, Action::SelectFirst, &["home"]),
+        (Mode::Todo, Action::SelectLast, &["end"]),
+        (Mode::Todo, Action::ShowHelp, &["?"]),
+        (Mode::Todo, Action::OpenCommandLine, &[":"]),
+        (Mode::Todo, Action::ToggleMark, &["m"]),
+        (Mode::Todo, Action::VisualMark, &["v"]),
+
+        (Mode::TextInput, Action::SelectPrevious, &["up"]),
+        (Mode::TextInput, Action::SelectNext, &["down"]),
+        (Mode::TextInput, Action::AddTask, &["enter"]),
+
+        (Mode::Category, Action::SelectPrevious, &["up"]),
+        (Mode::Category, Action::SelectNext, &["down"]),
+        (Mode::Category, Action::LeaveCategoryView, &["c", "C"]),
+        (Mode::Category, Action::LeaveToTextInput, &["esc"]),
+        (Mode::Category, Action::MakeDefaultCategory, &["d", "D"]),
+        (Mode::Category, Action::ToggleCategoryVisible, &["x", "X", "space"]),
+        (Mode::Category, Action::ShowHelp, &["?"]),
+
+        (Mode::Conflict, Action::KeepMine, &["m"]),
+        (Mode::Conflict, Action::TakeTheirs, &["t"]),
+
+        (Mode::Interruption, Action::KeepInterruptedTime, &["k"]),
+        (Mode::Interruption, Action::TruncateInterruptedTime, &["t"]),
+        (Mode::Interruption, Action::DiscardInterruptedPomodoro, &["d"]),
+    ];
+    match preset {
+        Preset::Default => {}
+        Preset::Vim => {
+            bindings.extend([
+                (Mode::Todo, Action::SelectPrevious, &["k"] as &'static [&'static str]),
+                (Mode::Todo, Action::SelectNext, &["j"]),
+                (Mode::Todo, Action::MoveTaskUp, &["K"]),
+                (Mode::Todo, Action::MoveTaskDown, &["J"]),
+                (Mode::Todo, Action::DeleteTask, &["d d"]),
+                (Mode::Todo, Action::SelectFirst, &["g g"]),
+                (Mode::Todo, Action::SelectLast, &["G"]),
+                (Mode::Todo, Action::EnterTextInput, &["i"]),
+                (Mode::Todo, Action::VisualMark, &["V"]),
+                (Mode::Category, Action::SelectPrevious, &["k"]),
+                (Mode::Category, Action::SelectNext, &["j"]),
+            ]);
+        }
+    }
+    bindings
+}
+
+impl Keymap {
+    pub fn from_preset(preset: Preset) -> Keymap {
+        let bindings = preset_bindings(preset)
+            .into_iter()
+            .flat_map(|(mode, action, chords)| chords.iter().map(move |chord| Binding {
+                mode,
+                action,
+                chord: parse_chord(chord).expect("Invalid preset key binding"),
+            }))
+            .collect();
+        Keymap { bindings }
+    }
+
+    /// Builds a keymap from the preset named in the file and applies its overrides.
+    /// Invalid entries are skipped and reported back.
+    pub fn from_file(file: &KeymapFile) -> (Keymap, Vec<String>) {
+        let mut keymap = Keymap::from_preset(file.preset);
+        let mut warnings = Vec::new();
+        for (mode, actions) in file.bindings.iter() {
+            for (action, chords) in actions.iter() {
+                keymap.bindings.retain(|binding| !(binding.mode == *mode && binding.action == *action));
+                for chord in chords {
+                    match parse_chord(chord) {
+                        Ok(chord) => keymap.bindings.push(Binding { mode: *mode, action: *action, chord }),
+                        Err(error) => warnings.push(error),
+                    }
+                }
+            }
+        }
+        (keymap, warnings)
+    }
+
+    /// Loads the keymap file from the storage directory, falling back to the
+    /// default preset if there is none.
+    pub fn load() -> (Keymap, Vec<String>) {
+        let path = get_full_path(KEYMAP_FILE);
+        match read_to_string(path.as_path()) {
+            Ok(data) => match serde_json::from_str::<KeymapFile>(&data) {
+                Ok(file) => Keymap::from_file(&file),
+                Err(error) => (
+                    Keymap::from_preset(Preset::Default),
+                    vec![format!("Could not parse {}: {}", path.display(), error)],
+                ),
+            },
+            Err(_) => (Keymap::from_preset(Preset::Default), Vec::new()),
+        }
+    }
+
+    /// Looks up the keys pressed so far, global bindings take precedence.
+    pub fn lookup(&self, mode: Mode, keys: &[Key]) -> Lookup {
+        let mut pending = false;
+        for binding_mode in [Mode::Global, mode] {
+            for binding in self.bindings.iter().filter(|binding| binding.mode == binding_mode) {
+                if binding.chord == keys {
+                    return Lookup::Action(binding.action);
+                }
+                if binding.chord.len() > keys.len() && binding.chord.starts_with(keys) {
+                    pending = true;
+                }
+            }
+        }
+        if pending {
+            Lookup::Pending
+        } else {
+            Lookup::NoMatch
+        }
+    }
+
+    pub fn bindings_for(&self, mode: Mode) -> impl Iterator<Item = &Binding> {
+        self.bindings.iter().filter(move |binding| binding.mode == mode)
+    }
+
+    /// All chords of each action available in the mode, including global ones,
+    /// in the order they are bound.
+    pub fn actions_for(&self, mode: Mode) -> Vec<(Action, Vec<String>)> {
+        let mut actions: Vec<(Action, Vec<String>)> = Vec::new();
+        for binding in self.bindings_for(Mode::Global).chain(self.bindings_for(mode)) {
+            let chord = chord_string(&binding.chord);
+            match actions.iter_mut().find(|(action, _)| *action == binding.action) {
+                Some((_, chords)) => chords.push(chord),
+                None => actions.push((binding.action, vec![chord])),
+            }
+        }
+        actions
+    }
+
+    /// Reports bindings which shadow each other or category hotkeys.
+    pub fn validate(&self, categories: &[Category]) -> Vec<String> {
+        let mut conflicts = Vec::new();
+        for (index, binding) in self.bindings.iter().enumerate() {
+            for other in self.bindings[index + 1..].iter() {
+                let same_scope = binding.mode == other.mode || binding.mode == Mode::Global || other.mode == Mode::Global;
+                if !same_scope || (binding.action == other.action && binding.chord == other.chord) {
+                    continue;
+                }
+                if binding.chord == other.chord {
+                    conflicts.push(format!("'{}' is bound to both {:?} and {:?}", chord_string(&binding.chord), binding.action, other.action));
+                } else if other.chord.starts_with(&binding.chord) || binding.chord.starts_with(&other.chord) {
+                    let (short, long) = if binding.chord.len() < other.chord.len() { (binding, other) } else { (other, binding) };
+                    conflicts.push(format!("'{}' ({:?}) makes '{}' ({:?}) unreachable", chord_string(&short.chord), short.action, chord_string(&long.chord), long.action));
+                }
+            }
+        }
+
+        for category in categories {
+            let hotkey = match category.hotkey {
+                Some(hotkey) => hotkey,
+                None => continue,
+            };
+            for binding in self.bindings.iter() {
+                let first_key = binding.chord[0];
+                if matches!(binding.mode, Mode::Global | Mode::Todo) && first_key == Key::plain(hotkey) {
+                    conflicts.push(format!("Hotkey '{}' of category {} is shadowed by {:?}", hotkey, category.name, binding.action));
+                }
+                if binding.mode == Mode::Global && first_key == Key::ctrl(hotkey) {
+                    conflicts.push(format!("Visibility toggle ctrl+{} of category {} is shadowed by {:?}", hotkey, category.name, binding.action));
+                }
+            }
+        }
+        conflicts
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap::from_preset(Preset::Default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keymap(bindings: &[(Mode, Action, &str)]) -> Keymap {
+        Keymap {
+            bindings: bindings.iter().map(|(mode, action, chord)| Binding { mode: *mode, action: *action, chord: parse_chord(chord).unwrap() }).collect(),
+        }
+    }
+
+    #[test]
+    fn keys_are_parsed() {
+        assert_eq!(Key::parse("x"), Ok(Key::plain('x')));
+        assert_eq!(Key::parse("G"), Ok(Key::plain('G')));
+        assert_eq!(Key::parse("space"), Ok(Key::plain(' ')));
+        assert_eq!(Key::parse("+"), Ok(Key::plain('+')));
+        assert_eq!(Key::parse("ctrl++"), Ok(Key::ctrl('+')));
+        assert_eq!(Key::parse("Ctrl+q"), Ok(Key::ctrl('q')));
+        assert_eq!(Key::parse("PageDown"), Ok(Key::new(KeyCode::PageDown, KeyModifiers::NONE)));
+        assert_eq!(Key::parse("shift+tab"), Ok(Key::new(KeyCode::Tab, KeyModifiers::SHIFT)));
+        assert_eq!(Key::parse("alt+f5"), Ok(Key::new(KeyCode::F(5), KeyModifiers::ALT)));
+        assert!(Key::parse("f13").is_err());
+        assert!(Key::parse("hyper+x").is_err());
+        assert!(Key::parse("nokey").is_err());
+    }
+
+    #[test]
+    fn shifted_characters_are_refused() {
+        assert!(Key::parse("shift+x").is_err());
+        assert!(Key::parse("ctrl+shift+x").is_err());
+    }
+
+    #[test]
+    fn keys_print_as_they_are_parsed() {
+        for text in ["x", "G", "space", "ctrl+q", "alt+enter", "shift+tab", "f1"] {
+            assert_eq!(Key::parse(text).unwrap().to_string(), text);
+        }
+        // The terminal reports shift along with the uppercase character
+        assert_eq!(Key::new(KeyCode::Char('G'), KeyModifiers::SHIFT), Key::plain('G'));
+    }
+
+    #[test]
+    fn chords_are_split_at_whitespace() {
+        assert_eq!(parse_chord("g g"), Ok(vec![Key::plain('g'), Key::plain('g')]));
+        assert_eq!(parse_chord(" ctrl+x  d "), Ok(vec![Key::ctrl('x'), Key::plain('d')]));
+        assert!(parse_chord("  ").is_err());
+        assert!(parse_chord("g nokey").is_err());
+    }
+
+    #[test]
+    fn chords_wait_for_their_next_key() {
+        let keymap = Keymap::from_preset(Preset::Vim);
+        assert_eq!(keymap.lookup(Mode::Todo, &[Key::plain('g')]), Lookup::Pending);
+        assert_eq!(keymap.lookup(Mode::Todo, &[Key::plain('g'), Key::plain('g')]), Lookup::Action(Action::SelectFirst));
+        assert_eq!(keymap.lookup(Mode::Todo, &[Key::plain('g'), Key::plain('x')]), Lookup::NoMatch);
+        assert_eq!(keymap.lookup(Mode::Category, &[Key::plain('g')]), Lookup::NoMatch);
+        // Global bindings work in every mode
+        assert_eq!(keymap.lookup(Mode::Category, &[Key::ctrl('q')]), Lookup::Action(Action::Quit));
+    }
+
+    #[test]
+    fn overrides_replace_the_preset_keys_of_an_action() {
+        let file: KeymapFile = serde_json::from_str(r#"{"bindings": {"todo": {"check_task": ["c c", "shift+c"]}}}"#).unwrap();
+        let (keymap, warnings) = Keymap::from_file(&file);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(keymap.lookup(Mode::Todo, &[Key::plain('x')]), Lookup::NoMatch);
+        assert_eq!(keymap.lookup(Mode::Todo, &[Key::plain('c'), Key::plain('c')]), Lookup::Action(Action::CheckTask));
+    }
+
+    #[test]
+    fn the_presets_have_no_conflicts() {
+        assert_eq!(Keymap::from_preset(Preset::Default).validate(&[]), Vec::<String>::new());
+        assert_eq!(Keymap::from_preset(Preset::Vim).validate(&[]), Vec::<String>::new());
+    }
+
+    #[test]
+    fn conflicts_are_reported() {
+        let same_key = keymap(&[(Mode::Todo, Action::CheckTask, "x"), (Mode::Todo, Action::DeleteTask, "x")]);
+        assert_eq!(same_key.validate(&[]).len(), 1);
+        let prefix = keymap(&[(Mode::Todo, Action::SelectFirst, "g"), (Mode::Todo, Action::SelectLast, "g g")]);
+        assert_eq!(prefix.validate(&[]).len(), 1);
+        let global = keymap(&[(Mode::Global, Action::Quit, "q"), (Mode::Category, Action::LeaveCategoryView, "q")]);
+        assert_eq!(global.validate(&[]).len(), 1);
+        let other_modes = keymap(&[(Mode::Todo, Action::CheckTask, "x"), (Mode::Category, Action::ToggleCategoryVisible, "x")]);
+        assert!(other_modes.validate(&[]).is_empty());
+        let hotkey = keymap(&[(Mode::Todo, Action::CheckTask, "w")]);
+        assert_eq!(hotkey.validate(&[Category::new(1, "work".to_string(), Some('w'))]).len(), 1);
+    }
+}
@@ -17,6 +17,68 @@ pub mod model;
 
 pub mod constants;
 
+/// Configurable key bindings.
+pub mod keymap;
+
+/// Settings from the config file.
+pub mod config;
+
+/// Colour themes.
+pub mod theme;
+
+/// The `:` command line.
+pub mod command;
+
+/// Quick-add syntax of the task input.
+pub mod quickadd;
+
+/// Persistence of the database.
+pub mod storage;
+
+/// Rolling backups of the database.
+pub mod backup;
+
+/// Upgrades of databases written by older versions.
+pub mod migration;
+
+/// Watching the database for changes made outside of pti.
+pub mod watcher;
+
+/// Merging two versions of the database task by task.
+pub mod merge;
+
+/// Syncing the database between machines through git.
+pub mod sync;
+
+/// Errors of the files in the storage directory.
+pub mod error;
+
+/// Screen for recovering a damaged database.
+pub mod recovery;
+
+/// Subcommands of the pti binary.
+pub mod cli;
+
+/// Exports of tasks and worked time for spreadsheets and notes.
+pub mod export;
+
+/// Timesheets for billing the time worked on categories.
+pub mod timesheet;
+
+/// Imports from todo.txt, Markdown checklists, Taskwarrior and org-mode.
+pub mod import;
+
+/// Executables run on events like a finished pomodoro.
+pub mod hooks;
+
+/// Background daemon owning the database and the timer.
+#[cfg(unix)]
+pub mod daemon;
+
+/// Localhost HTTP API for browser extensions and editor plugins.
+#[cfg(feature = "http")]
+pub mod http;
+
 #[cfg(not(feature = "notifications"))]
 pub mod notification {
     pub struct NotificationManager {}
@@ -1,22 +1,79 @@
 use pti::app::{App, AppResult};
+use pti::config::Config;
+use pti::error::PtiError;
 use pti::event::{Event, EventHandler};
-use pti::handler::handle_key_events;
-use pti::tui::Tui;
+use pti::handler::{handle_key_events, handle_paste_event};
+use pti::model::Database;
+use pti::tui::{self, Tui};
+use pti::{cli, recovery, storage};
+use std::env;
 use std::io;
+use std::process;
 use ratatui::backend::CrosstermBackend;
 use ratatui::Terminal;
 
 fn main() -> AppResult<()> {
-    // Create an application.
-    let mut app = App::new();
+    // Subcommands run without the user interface.
+    let args = env::args().skip(1).collect::<Vec<String>>();
+    if !args.is_empty() {
+        if let Err(error) = cli::run(&args) {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+        return Ok(());
+    }
+
+    // Errors before the terminal is set up end the program with a message.
+    let (config, warnings) = Config::load();
+    // If the daemon runs, this instance becomes its client
+    let mut storage = match storage::open_writable(config.storage) {
+        Ok(storage) => storage,
+        // Another instance is running, this one only shows its state
+        Err(PtiError::Locked(_)) => match storage::open_read_only(config.storage) {
+            Ok(storage) => storage,
+            Err(error) => {
+                eprintln!("{}", error);
+                process::exit(1);
+            }
+        },
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+    };
+    let loaded = storage.load();
+    // Files of a newer pti are not damaged, they must not be overwritten by the recovery.
+    // A read-only instance leaves the recovery to the owning one.
+    if let Err(error) = &loaded {
+        if storage.read_only() || matches!(error, PtiError::NewerSchema { .. }) {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+    }
 
     // Initialize the terminal user interface.
+    tui::install_panic_hook();
     let backend = CrosstermBackend::new(io::stderr());
     let terminal = Terminal::new(backend)?;
     let events = EventHandler::new(25);
     let mut tui = Tui::new(terminal, events);
     tui.init()?;
 
+    // A damaged database is recovered before the application starts.
+    let db = match loaded {
+        Ok(db) => db.unwrap_or_else(Database::example_db),
+        Err(error) => match recovery::run(&mut tui, storage.as_mut(), config.storage, error) {
+            Ok(Some(db)) => db,
+            result => {
+                tui.exit()?;
+                return result.map(|_| ());
+            }
+        },
+    };
+
+    // Create an application.
+    let mut app = App::new(config, warnings, storage, db);
+
     // Start the main loop.
     while app.running {
         // Render the user interface.
@@ -27,6 +84,7 @@ fn main() -> AppResult<()> {
             Event::Key(key_event) => handle_key_events(key_event, &mut app)?,
             Event::Mouse(_) => {}
             Event::Resize(_, _) => {}
+            Event::Paste(text) => handle_paste_event(text, &mut app)?,
         }
     }
 
@@ -0,0 +1,342 @@
+use std::collections::HashMap;
+use serde_json::Value;
+use crate::app::AppResult;
+use crate::migration::{self, SCHEMA_VERSION};
+use crate::model::{Category, Database, Pomodoro, Task};
+
+/// The outcome of merging two versions of the database.
+pub struct Merged {
+    pub database: Database,
+    /// Edits made on both sides which could not be combined, in words. Ours was kept.
+    pub conflicts: Vec<String>,
+}
+
+struct Merger {
+    conflicts: Vec<String>,
+}
+
+fn task_name(task: &Task) -> String {
+    format!("task \"{}\"", task.description)
+}
+
+fn category_name(category: &Category) -> String {
+    format!("category {}", category.name)
+}
+
+/// Pomodoros on either side, without the ones removed on one side since `base`.
+fn pomodoros(base: &[Pomodoro], ours: &[Pomodoro], theirs: &[Pomodoro]) -> Vec<Pomodoro> {
+    let mut merged = ours.iter()
+        .filter(|pomodoro| theirs.contains(pomodoro) || !base.contains(pomodoro))
+        .cloned()
+        .collect::<Vec<Pomodoro>>();
+    merged.extend(theirs.iter().filter(|pomodoro| !ours.contains(pomodoro) && !base.contains(pomodoro)).cloned());
+    merged.sort_by_key(|pomodoro| pomodoro.start_time);
+    merged
+}
+
+impl Merger {
+    /// Takes the side which changed the value since `base`. If both changed it
+    /// differently, ours is kept and the conflict noted.
+    fn field<T: PartialEq + Clone>(&mut self, owner: &str, field: &str, base: Option<&T>, ours: &T, theirs: &T) -> T {
+        if ours == theirs || base == Some(theirs) {
+            ours.clone()
+        } else if base == Some(ours) {
+            theirs.clone()
+        } else {
+            self.conflicts.push(format!("{}: {} changed on both sides", owner, field));
+            ours.clone()
+        }
+    }
+
+    fn task(&mut self, base: Option<&Task>, ours: &Task, theirs: &Task) -> Task {
+        let owner = task_name(ours);
+        Task {
+            id: ours.id,
+            uid: ours.uid.clone(),
+            description: self.field(&owner, "description", base.map(|task| &task.description), &ours.description, &theirs.description),
+            done: self.field(&owner, "done", base.map(|task| &task.done), &ours.done, &theirs.done),
+            past_pomodoros: pomodoros(base.map_or(&[], |task| &task.past_pomodoros), &ours.past_pomodoros, &theirs.past_pomodoros),
+            active_pomodoro_jointime: self.field(&owner, "running pomodoro", base.map(|task| &task.active_pomodoro_jointime), &ours.active_pomodoro_jointime, &theirs.active_pomodoro_jointime),
+            parent: self.field(&owner, "parent", base.map(|task| &task.parent), &ours.parent, &theirs.parent),
+            category: self.field(&owner, "category", base.map(|task| &task.category), &ours.category, &theirs.category),
+            date_added: ours.date_added.min(theirs.date_added),
+            // Reordering touches many tasks at once, not worth a conflict
+            order: match base {
+                Some(base) if base.order == ours.order => theirs.order,
+                _ => ours.order,
+            },
+            tags: self.field(&owner, "tags", base.map(|task| &task.tags), &ours.tags, &theirs.tags),
+            priority: self.field(&owner, "priority", base.map(|task| &task.priority), &ours.priority, &theirs.priority),
+            estimate: self.field(&owner, "estimate", base.map(|task| &task.estimate), &ours.estimate, &theirs.estimate),
+            due: self.field(&owner, "due date", base.map(|task| &task.due), &ours.due, &theirs.due),
+        }
+    }
+
+    fn category(&mut self, base: Option<&Category>, ours: &Category, theirs: &Category) -> Category {
+        let owner = category_name(ours);
+        Category {
+            id: ours.id,
+            name: self.field(&owner, "name", base.map(|category| &category.name), &ours.name, &theirs.name),
+            hotkey: self.field(&owner, "hotkey", base.map(|category| &category.hotkey), &ours.hotkey, &theirs.hotkey),
+            visible: self.field(&owner, "visibility", base.map(|category| &category.visible), &ours.visible, &theirs.visible),
+            color: self.field(&owner, "colour", base.map(|category| &category.color), &ours.color, &theirs.color),
+            billing: self.field(&owner, "billing", base.map(|category| &category.billing), &ours.billing, &theirs.billing),
+        }
+    }
+
+    /// Matches items by `id`. Items on one side only were added there, or deleted on
+    /// the other side, which wins unless the item was changed meanwhile.
+    fn items<T: Clone + PartialEq, K: PartialEq>(
+        &mut self,
+        base: &[T],
+        ours: &[T],
+        theirs: &[T],
+        id: fn(&T) -> K,
+        name: fn(&T) -> String,
+        merge: fn(&mut Merger, Option<&T>, &T, &T) -> T,
+    ) -> Vec<T> {
+        let find = |items: &[T], wanted: K| items.iter().find(|item| id(item) == wanted).cloned();
+        let mut merged = Vec::new();
+        for item in ours {
+            let base_item = find(base, id(item));
+            match (find(theirs, id(item)), base_item) {
+                (Some(other), base_item) => merged.push(merge(self, base_item.as_ref(), item, &other)),
+                (None, None) => merged.push(item.clone()),
+                (None, Some(base_item)) if base_item == *item => {}
+                (None, Some(_)) => {
+                    self.conflicts.push(format!("{}: deleted on their side but changed on ours, kept it", name(item)));
+                    merged.push(item.clone());
+                }
+            }
+        }
+        for item in theirs.iter().filter(|item| find(ours, id(item)).is_none()) {
+            match find(base, id(item)) {
+                None => merged.push(item.clone()),
+                Some(base_item) if base_item == *item => {}
+                Some(_) => {
+                    self.conflicts.push(format!("{}: deleted on our side but changed on theirs, kept it", name(item)));
+                    merged.push(item.clone());
+                }
+            }
+        }
+        merged
+    }
+}
+
+/// Gives the categories which both sides added under the same id, and their tasks
+/// which took a number ours already uses, new ids on their side. Categories with
+/// the same name count as the same category, tasks with different uids added at
+/// the same time with the same description as the same task.
+fn remap(base: Option<&Database>, ours: &Database, theirs: &Database) -> Database {
+    let in_base_categories = |id: u32| base.is_some_and(|base| base.categories.iter().any(|category| category.id == id));
+
+    let mut next_category = ours.categories.iter().chain(theirs.categories.iter()).map(|category| category.id).max().unwrap_or(0) + 1;
+    let mut categories: HashMap<u32, u32> = HashMap::new();
+    for category in theirs.categories.iter().filter(|category| !in_base_categories(category.id)) {
+        let id = match ours.categories.iter().find(|other| other.name == category.name) {
+            Some(other) => other.id,
+            None if ours.categories.iter().any(|other| other.id == category.id) => {
+                let id = next_category;
+                next_category += 1;
+                id
+            }
+            None => category.id,
+        };
+        categories.insert(category.id, id);
+    }
+
+    // E.g. copies of a database which got their uids when they were migrated separately
+    let has_uid = |tasks: &[Task], uid: &str| tasks.iter().any(|task| task.uid == uid);
+    let uids: HashMap<String, String> = theirs.tasks.iter()
+        .filter(|item| !has_uid(&ours.tasks, &item.uid))
+        .filter_map(|item| ours.tasks.iter()
+            .find(|other| other.date_added == item.date_added && other.description == item.description && !has_uid(&theirs.tasks, &other.uid))
+            .map(|other| (item.uid.clone(), other.uid.clone())))
+        .collect();
+
+    let mut next_task = ours.next_task_id.max(theirs.next_task_id);
+    let category = |id: u32| categories.get(&id).copied().unwrap_or(id);
+    let mut remapped = theirs.clone();
+    for item in remapped.tasks.iter_mut() {
+        if let Some(uid) = uids.get(&item.uid) {
+            item.uid = uid.clone();
+        }
+        if let Some(parent) = item.parent.as_ref().and_then(|parent| uids.get(parent)) {
+            item.parent = Some(parent.clone());
+        }
+        let taken = ours.tasks.iter().any(|other| other.id == item.id && other.uid != item.uid);
+        if taken && !ours.tasks.iter().any(|other| other.uid == item.uid) {
+            item.id = next_task;
+            next_task += 1;
+        }
+        item.category = category(item.category);
+    }
+    remapped.next_task_id = next_task;
+    for item in remapped.categories.iter_mut() {
+        item.id = category(item.id);
+        // A hotkey can only stand for one category
+        if !in_base_categories(item.id) && ours.categories.iter().any(|other| other.id != item.id && other.hotkey.is_some() && other.hotkey == item.hotkey) {
+            item.hotkey = None;
+        }
+    }
+    // Categories merged into one of ours by name
+    let mut seen = Vec::new();
+    remapped.categories.retain(|item| {
+        let keep = !seen.contains(&item.id);
+        seen.push(item.id);
+        keep
+    });
+    remapped.default_category_id = category(remapped.default_category_id);
+    remapped
+}
+
+/// A database file of any schema version, upgraded in memory only.
+pub fn parse(text: &str) -> AppResult<Database> {
+    let mut value: Value = serde_json::from_str(text)?;
+    migration::upgrade(&mut value)?;
+    Ok(serde_json::from_value(value)?)
+}
+
+/// Parents which are gone or lead in a circle make a task top-level, tasks of a
+/// category which is gone move to the default category.
+fn repair(database: &mut Database) {
+    let parents = database.tasks.iter().map(|task| (task.uid.clone(), task.parent.clone())).collect::<HashMap<String, Option<String>>>();
+    for task in database.tasks.iter_mut() {
+        let mut ancestor = task.parent.clone();
+        let mut steps = 0;
+        while let Some(uid) = ancestor {
+            if !parents.contains_key(&uid) || uid == task.uid || steps > parents.len() {
+                task.parent = None;
+                break;
+            }
+            ancestor = parents.get(&uid).cloned().flatten();
+            steps += 1;
+        }
+    }
+    if !database.categories.iter().any(|category| category.id == database.default_category_id) {
+        database.default_category_id = database.categories.first().map_or(0, |category| category.id);
+    }
+    let default = database.default_category_id;
+    let categories = database.categories.iter().map(|category| category.id).collect::<Vec<u32>>();
+    for task in database.tasks.iter_mut().filter(|task| !categories.contains(&task.category)) {
+        task.category = default;
+    }
+}
+
+/// Merges two versions of the database task by task. `base` is the version both
+/// started from, without one nothing counts as deleted and the result is the union.
+pub fn merge(base: Option<&Database>, ours: &Database, theirs: &Database) -> Merged {
+    let theirs = remap(base, ours, theirs);
+    let mut merger = Merger { conflicts: Vec::new() };
+    let categories = merger.items(
+        base.map_or(&[], |base| &base.categories),
+        &ours.categories,
+        &theirs.categories,
+        |category| category.id,
+        category_name,
+        Merger::category,
+    );
+    let tasks = merger.items(
+        base.map_or(&[], |base| &base.tasks),
+        &ours.tasks,
+        &theirs.tasks,
+        |task| task.uid.clone(),
+        task_name,
+        Merger::task,
+    );
+    let mut database = Database {
+        schema_version: SCHEMA_VERSION,
+        tasks,
+        categories,
+        pomodoro_duration_minutes: merger.field("settings", "pomodoro duration", base.map(|base| &base.pomodoro_duration_minutes), &ours.pomodoro_duration_minutes, &theirs.pomodoro_duration_minutes),
+        active_pomodoro_starttime: merger.field("settings", "running pomodoro", base.map(|base| &base.active_pomodoro_starttime), &ours.active_pomodoro_starttime, &theirs.active_pomodoro_starttime),
+        default_category_id: merger.field("settings", "default category", base.map(|base| &base.default_category_id), &ours.default_category_id, &theirs.default_category_id),
+        last_seen: ours.last_seen.max(theirs.last_seen),
+        next_task_id: ours.next_task_id.max(theirs.next_task_id),
+    };
+    repair(&mut database);
+    Merged { database, conflicts: merger.conflicts }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::quickadd::QuickAdd;
+    use super::*;
+
+    fn add_task(database: &mut Database, description: &str, category: Option<u32>) -> u32 {
+        database.add_parsed_task(&QuickAdd { description: description.to_string(), category, ..QuickAdd::default() }, None)
+    }
+
+    fn task<'a>(database: &'a Database, description: &str) -> &'a crate::model::Task {
+        database.tasks.iter().find(|task| task.description == description).unwrap()
+    }
+
+    #[test]
+    fn tasks_keep_their_number_without_a_collision() {
+        let base = Database::example_db();
+        let ours = base.clone();
+        let mut theirs = base.clone();
+        let id = add_task(&mut theirs, "Added on their side", None);
+        let remapped = remap(Some(&base), &ours, &theirs);
+        assert_eq!(task(&remapped, "Added on their side").id, id);
+    }
+
+    #[test]
+    fn their_task_under_a_number_we_use_gets_a_new_one() {
+        let base = Database::example_db();
+        let mut ours = base.clone();
+        let mut theirs = base.clone();
+        let ours_id = add_task(&mut ours, "Added on our side", None);
+        let theirs_id = add_task(&mut theirs, "Added on their side", None);
+        assert_eq!(ours_id, theirs_id);
+
+        let remapped = remap(Some(&base), &ours, &theirs);
+        let moved = task(&remapped, "Added on their side");
+        assert_ne!(moved.id, ours_id);
+        assert!(remapped.next_task_id > moved.id);
+        let merged = merge(Some(&base), &ours, &theirs);
+        assert!(merged.conflicts.is_empty());
+        assert_eq!(merged.database.tasks.len(), 2);
+    }
+
+    #[test]
+    fn categories_added_under_the_same_id_are_told_apart_by_name() {
+        let base = Database::example_db();
+        let mut ours = base.clone();
+        let mut theirs = base.clone();
+        let work = ours.add_category("work".to_string(), None).unwrap();
+        let home = theirs.add_category("home".to_string(), None).unwrap();
+        assert_eq!(work, home);
+        add_task(&mut theirs, "Water the plants", Some(home));
+        // Added on both sides, so the same category
+        let ours_errands = ours.add_category("errands".to_string(), None).unwrap();
+        let theirs_errands = theirs.add_category("errands".to_string(), None).unwrap();
+        add_task(&mut theirs, "Buy stamps", Some(theirs_errands));
+
+        let remapped = remap(Some(&base), &ours, &theirs);
+        let home = remapped.categories.iter().find(|category| category.name == "home").unwrap().id;
+        assert_ne!(home, work);
+        assert_eq!(task(&remapped, "Water the plants").category, home);
+        assert_eq!(task(&remapped, "Buy stamps").category, ours_errands);
+        assert_eq!(remapped.categories.iter().filter(|category| category.name == "errands").count(), 1);
+    }
+
+    #[test]
+    fn tasks_with_different_uids_match_by_date_and_description() {
+        let mut ours = Database::example_db();
+        let id = add_task(&mut ours, "Write the report", None);
+        let mut theirs = ours.clone();
+        theirs.set_task_uid(id, "01H8XGJWBWBAQ4Z4G5K2Y7N1QF".to_string());
+        let child = theirs.add_parsed_task(&QuickAdd { description: "Collect the numbers".to_string(), ..QuickAdd::default() }, Some(id));
+        add_task(&mut ours, "Added on our side", None);
+
+        let merged = merge(None, &ours, &theirs).database;
+        assert_eq!(merged.tasks.iter().filter(|task| task.description == "Write the report").count(), 1);
+        let report = task(&merged, "Write the report");
+        assert_eq!(report.uid, task(&ours, "Write the report").uid);
+        assert_eq!(report.id, id);
+        let numbers = task(&merged, "Collect the numbers");
+        assert_eq!(numbers.parent.as_ref(), Some(&report.uid));
+        assert_ne!(numbers.id, child);
+    }
+}
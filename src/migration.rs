@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+use chrono::DateTime;
+use serde_json::{Map, Value};
+use ulid::Ulid;
+
+/// The version of the database format written by this version of pti.
+///
+/// Bump it whenever the format changes and add a step to [`MIGRATIONS`] which
+/// turns the previous version into the new one.
+pub const SCHEMA_VERSION: u32 = 4;
+
+type Migration = fn(&mut Map<String, Value>) -> Result<(), String>;
+
+/// The step at index `i` upgrades a database of version `i` to version `i + 1`.
+const MIGRATIONS: [Migration; SCHEMA_VERSION as usize] = [
+    v0_to_v1,
+    v1_to_v2,
+    v2_to_v3,
+    v3_to_v4,
+];
+
+/// Version 0 is the format before versioning, without tags, priorities,
+/// estimates and due dates on tasks and without category colours.
+fn v0_to_v1(database: &mut Map<String, Value>) -> Result<(), String> {
+    for task in objects(database, "tasks")? {
+        task.entry("tags").or_insert(Value::Array(Vec::new()));
+        task.entry("priority").or_insert(Value::Null);
+        task.entry("estimate").or_insert(Value::Null);
+        task.entry("due").or_insert(Value::Null);
+    }
+    for category in objects(database, "categories")? {
+        category.entry("color").or_insert(Value::Null);
+    }
+    Ok(())
+}
+
+/// Version 2 remembers when pti was last running.
+fn v1_to_v2(database: &mut Map<String, Value>) -> Result<(), String> {
+    database.entry("last_seen").or_insert(Value::Null);
+    Ok(())
+}
+
+/// Version 3 adds billing details to categories.
+fn v2_to_v3(database: &mut Map<String, Value>) -> Result<(), String> {
+    for category in objects(database, "categories")? {
+        category.entry("billing").or_insert(Value::Null);
+    }
+    Ok(())
+}
+
+/// Version 4 gives tasks a uid which stays the same across databases, parents
+/// refer to it instead of the task number. The uids are made of the creation
+/// time and the number, so that copies of a database upgraded on two machines agree.
+fn v3_to_v4(database: &mut Map<String, Value>) -> Result<(), String> {
+    let mut uids: HashMap<u64, String> = HashMap::new();
+    for task in objects(database, "tasks")? {
+        let id = task.get("id").and_then(Value::as_u64).ok_or("A task has no id")?;
+        let added = task.get("date_added")
+            .and_then(Value::as_str)
+            .and_then(|added| DateTime::parse_from_rfc3339(added).ok())
+            .ok_or(format!("Task {} has no valid date_added", id))?;
+        let uid = Ulid::from_parts(added.timestamp_millis().max(0) as u64, id.into()).to_string();
+        task.insert("uid".to_string(), Value::from(uid.clone()));
+        uids.insert(id, uid);
+    }
+    for task in objects(database, "tasks")? {
+        let parent = task.get("parent").and_then(Value::as_u64).and_then(|parent| uids.get(&parent).cloned());
+        task.insert("parent".to_string(), Value::from(parent));
+    }
+    let next_task_id = uids.keys().max().map_or(1, |id| id + 1);
+    database.insert("next_task_id".to_string(), Value::from(next_task_id));
+    Ok(())
+}
+
+/// The entries of an array of objects in the database.
+fn objects<'a>(database: &'a mut Map<String, Value>, key: &str) -> Result<Vec<&'a mut Map<String, Value>>, String> {
+    database.get_mut(key)
+        .and_then(Value::as_array_mut)
+        .ok_or(format!("'{}' is missing or not a list", key))?
+        .iter_mut()
+        .map(|entry| entry.as_object_mut().ok_or(format!("An entry of '{}' is not an object", key)))
+        .collect()
+}
+
+/// The schema version of a database, files from before versioning count as version 0.
+pub fn version_of(database: &Value) -> Result<u32, String> {
+    match database.get("schema_version") {
+        None => Ok(0),
+        Some(version) => version.as_u64()
+            .and_then(|version| u32::try_from(version).ok())
+            .ok_or(format!("schema_version {} is not a version number", version)),
+    }
+}
+
+/// Upgrades the json of a database to [`SCHEMA_VERSION`] in place and returns
+/// the version it had before. Fails for databases of a newer version of pti.
+pub fn upgrade(database: &mut Value) -> Result<u32, String> {
+    let version = version_of(database)?;
+    if version > SCHEMA_VERSION {
+        return Err(format!(
+            "The database has schema version {}, but this version of pti only understands up to {}. Please update pti.",
+            version, SCHEMA_VERSION
+        ));
+    }
+    let object = database.as_object_mut().ok_or("The database is not a json object")?;
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        migration(object).map_err(|error| format!("Could not upgrade the database from version {}: {}", from, error))?;
+    }
+    object.insert("schema_version".to_string(), Value::from(SCHEMA_VERSION));
+    Ok(version)
+}
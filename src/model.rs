@@ -1,37 +1,94 @@
-use chrono::{Duration, Utc, DateTime};
-use serde::{Serialize, Deserialize};
-use std::fs::File;
-use std::io::{Seek, Read, Write};
+use chrono::{Duration, Utc, DateTime, Local, NaiveDate};
+use serde::{Serialize, Serializer, Deserialize};
+use std::fmt;
+use ulid::Ulid;
+use crate::quickadd::QuickAdd;
+use crate::migration::SCHEMA_VERSION;
 
 #[derive(Clone)]
 #[derive(Serialize)]
 #[derive(Deserialize)]
+#[derive(PartialEq)]
 #[derive(Debug)]
 pub struct Pomodoro {
-    start_time: DateTime<Utc>,
-    end_time: DateTime<Utc>,
+    pub(crate) start_time: DateTime<Utc>,
+    pub(crate) end_time: DateTime<Utc>,
+}
+
+#[derive(Clone, Copy)]
+#[derive(Serialize)]
+#[derive(Deserialize)]
+#[derive(Eq)]
+#[derive(PartialEq)]
+#[derive(Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    High,
+    Medium,
+    Low,
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Priority::High => write!(f, "high"),
+            Priority::Medium => write!(f, "medium"),
+            Priority::Low => write!(f, "low"),
+        }
+    }
 }
 
 #[derive(Clone)]
 #[derive(Serialize)]
 #[derive(Deserialize)]
+#[derive(PartialEq)]
 #[derive(Debug)]
 pub struct Task {
-    id: u32,
-    description: String,
-    done: bool,
-    past_pomodoros: Vec<Pomodoro>,
-    active_pomodoro_jointime: Option<DateTime<Utc>>,
-    parent: Option<u32>,
-    category: u32,
-    date_added: DateTime<Utc>,
-    order: u32,
+    /// Short number shown to the user, unique within one database
+    pub(crate) id: u32,
+    /// ULID which stays the same when the task moves between databases
+    pub(crate) uid: String,
+    pub(crate) description: String,
+    pub(crate) done: bool,
+    pub(crate) past_pomodoros: Vec<Pomodoro>,
+    pub(crate) active_pomodoro_jointime: Option<DateTime<Utc>>,
+    /// The uid of the parent task
+    pub(crate) parent: Option<String>,
+    pub(crate) category: u32,
+    pub(crate) date_added: DateTime<Utc>,
+    pub(crate) order: u32,
+    #[serde(default)]
+    pub(crate) tags: Vec<String>,
+    #[serde(default)]
+    pub(crate) priority: Option<Priority>,
+    /// Estimated number of pomodoros
+    #[serde(default)]
+    pub(crate) estimate: Option<u32>,
+    #[serde(default)]
+    pub(crate) due: Option<NaiveDate>,
+}
+
+/// Who the work of a category is billed to, for timesheets.
+#[derive(Clone)]
+#[derive(Serialize)]
+#[derive(Deserialize)]
+#[derive(PartialEq)]
+#[derive(Debug)]
+pub struct Billing {
+    pub client: String,
+    /// Name of the project on the timesheet, the category name if not set
+    #[serde(default)]
+    pub project: Option<String>,
+    /// Per hour
+    #[serde(default)]
+    pub rate: Option<f64>,
+    #[serde(default)]
+    pub currency: Option<String>,
 }
 
 #[derive(Clone)]
 #[derive(Serialize)]
 #[derive(Deserialize)]
-#[derive(Eq)]
 #[derive(PartialEq)]
 #[derive(Debug)]
 pub struct Category {
@@ -39,18 +96,51 @@ pub struct Category {
     pub name: String,
     pub hotkey: Option<char>,
     pub visible: bool,
+    /// Colour of the category name in the todo table, e.g. `red` or `#ff8800`
+    #[serde(default)]
+    pub color: Option<String>,
+    #[serde(default)]
+    pub billing: Option<Billing>,
 }
 
 #[derive(Clone)]
 #[derive(Serialize)]
 #[derive(Deserialize)]
+#[derive(PartialEq)]
 #[derive(Debug)]
 pub struct Database {
-    tasks: Vec<Task>,
-    categories: Vec<Category>,
-    pomodoro_duration_minutes: u32,
-    active_pomodoro_starttime: Option<DateTime<Utc>>,
-    default_category_id: u32,
+    /// Version of the file format, see [`crate::migration`].
+    pub(crate) schema_version: u32,
+    pub(crate) tasks: Vec<Task>,
+    pub(crate) categories: Vec<Category>,
+    pub(crate) pomodoro_duration_minutes: u32,
+    pub(crate) active_pomodoro_starttime: Option<DateTime<Utc>>,
+    pub(crate) default_category_id: u32,
+    /// When pti last saved or quit, to tell how long it was closed.
+    pub(crate) last_seen: Option<DateTime<Utc>>,
+    /// Number of the next task, numbers of deleted tasks are not handed out again.
+    pub(crate) next_task_id: u32,
+}
+
+/// A stretch of time worked on a task, as used by reports.
+#[derive(Clone)]
+#[derive(Debug)]
+pub struct Segment {
+    pub task_id: u32,
+    pub category_id: u32,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+}
+
+impl Segment {
+    /// The part of the segment that lies after `since`.
+    pub fn time_spent_since(&self, since: DateTime<Utc>) -> Duration {
+        if self.end_time <= since {
+            Duration::zero()
+        } else {
+            self.end_time - self.start_time.max(since)
+        }
+    }
 }
 
 
@@ -73,6 +163,8 @@ impl Category {
             name,
             hotkey,
             visible: true,
+            color: None,
+            billing: None,
         }
     }
 }
@@ -82,6 +174,7 @@ impl Task {
     pub fn new(id: u32, description: String, category: u32) -> Task {
         Task {
             id,
+            uid: Ulid::new().to_string(),
             description,
             done: false,
             past_pomodoros: Vec::new(),
@@ -89,7 +182,11 @@ impl Task {
             category,
             parent: None,
             date_added: Utc::now(),
-            order: id,
+            order: 0,
+            tags: Vec::new(),
+            priority: None,
+            estimate: None,
+            due: None,
         }
     }
 
@@ -120,28 +217,48 @@ impl Task {
     }
 }
 
+/// Times are whole seconds in JSON, chrono's Duration has no serde support.
+fn serialize_seconds<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_i64(duration.num_seconds())
+}
+
+#[derive(Serialize)]
 pub struct PrinteableTask {
     pub id: u32,
+    pub uid: String,
     pub description: String,
     pub done: bool,
+    #[serde(rename = "time_spent_seconds", serialize_with = "serialize_seconds")]
     pub time_spent: Duration,
     pub pomodoro_active: bool,
+    pub parent: Option<u32>,
     pub indent: u32,
     pub category: Category,
     pub date_added: DateTime<Utc>,
+    pub tags: Vec<String>,
+    pub priority: Option<Priority>,
+    pub estimate: Option<u32>,
+    pub due: Option<NaiveDate>,
 }
 
 impl PrinteableTask {
-    pub fn new(task: &Task, categories: &[Category], indent: u32) -> PrinteableTask {
+    /// `tasks` are the other tasks, to give the number of the parent.
+    pub fn new(task: &Task, tasks: &[Task], categories: &[Category], indent: u32) -> PrinteableTask {
         PrinteableTask {
             id: task.id,
+            uid: task.uid.clone(),
             description: task.description.clone(),
             done: task.done,
             time_spent: task.time_spent(),
             pomodoro_active: task.pomodoro_active(),
+            parent: task.parent.as_ref().and_then(|parent| tasks.iter().find(|other| other.uid == *parent)).map(|parent| parent.id),
             indent,
             category: get_category_by_id(categories, task.category).unwrap().clone(),
             date_added: task.date_added,
+            tags: task.tags.clone(),
+            priority: task.priority,
+            estimate: task.estimate,
+            due: task.due,
         }
     }
 
@@ -167,6 +284,39 @@ impl PrinteableTask {
         }
     }
 
+    pub fn get_added_string(&self) -> String {
+        self.date_added.with_timezone(&Local).format("%Y-%m-%d").to_string()
+    }
+
+    pub fn get_id_string(&self) -> String {
+        self.id.to_string()
+    }
+
+    pub fn get_tags_string(&self) -> String {
+        self.tags.iter().map(|tag| format!("#{}", tag)).collect::<Vec<String>>().join(" ")
+    }
+
+    pub fn get_priority_string(&self) -> String {
+        match self.priority {
+            Some(priority) => priority.to_string(),
+            None => String::new(),
+        }
+    }
+
+    pub fn get_estimate_string(&self) -> String {
+        match self.estimate {
+            Some(estimate) => format!("~{}", estimate),
+            None => String::new(),
+        }
+    }
+
+    pub fn get_due_string(&self) -> String {
+        match self.due {
+            Some(due) => due.format("%Y-%m-%d").to_string(),
+            None => String::new(),
+        }
+    }
+
     pub fn get_description_string(&self) -> String {
         let mut description = String::new();
         for _ in 0..self.indent {
@@ -177,21 +327,27 @@ impl PrinteableTask {
     }
 }
 
-pub fn get_printeable_tasklist(tasks: &[Task], categories: &[Category], parent: Option<u32>, indent: u32) -> Vec<PrinteableTask> {
+/// The visible tasks below the task with the uid `parent`, depth first.
+pub fn get_printeable_tasklist(tasks: &[Task], categories: &[Category], parent: Option<&str>, indent: u32) -> Vec<PrinteableTask> {
+    printeable_tree(tasks, categories, parent, indent, &|task| get_category_by_id(categories, task.category).unwrap().visible)
+}
+
+/// The tasks below `parent` which pass `include`, depth first.
+fn printeable_tree(tasks: &[Task], categories: &[Category], parent: Option<&str>, indent: u32, include: &dyn Fn(&Task) -> bool) -> Vec<PrinteableTask> {
     let mut current_level_tasks: Vec<&Task> = tasks.iter().filter(|task| {
-        get_category_by_id(categories, task.category).unwrap().visible && task.parent == parent 
+        include(task) && task.parent.as_deref() == parent
     }).collect();
     current_level_tasks.sort_by(|a, b| a.order.cmp(&b.order));
     current_level_tasks.iter().map(|task| {
-        let mut children = get_printeable_tasklist(tasks, categories, Some(task.id), indent + 1);
-        children.insert(0, PrinteableTask::new(task, categories, indent));
+        let mut children = printeable_tree(tasks, categories, Some(&task.uid), indent + 1, include);
+        children.insert(0, PrinteableTask::new(task, tasks, categories, indent));
         children
     }).flatten().collect()
 }
 
 pub struct PrinteableCategory {
     pub id: u32,
-    category: Category,
+    pub category: Category,
     default: bool,
     num_tasks: usize,
     num_tasks_checked: usize,
@@ -246,15 +402,19 @@ impl PrinteableCategory {
 impl Database {
     pub fn new() -> Database {
         Database {
+            schema_version: SCHEMA_VERSION,
             tasks: Vec::new(),
             categories: vec![Category::new(0, "nocat".to_string(), Some('u'))],
             pomodoro_duration_minutes: 25,
             active_pomodoro_starttime: None,
             default_category_id: 0,
+            last_seen: None,
+            next_task_id: 1,
         }
     }
 
-    fn example_db() -> Database {
+    /// The database pti starts with if there is none yet.
+    pub fn example_db() -> Database {
         let mut database = Database::new();
         database.categories.push(Category::new(1, "archive".to_string(), Some('a')));
         database.categories[0].visible = false;
@@ -263,31 +423,56 @@ impl Database {
         database
     }
 
-    pub fn load_or_create(file: &mut File) -> Database {
-        match Database::from_json_file(file) {
-            Some(database) => database,
-            None => Database::example_db(),
-        }
+    /// Finished pomodoro segments which end after `since`, oldest first.
+    pub fn segments_since(&self, since: DateTime<Utc>) -> Vec<Segment> {
+        let mut segments = self.tasks
+            .iter()
+            .flat_map(|task| task.past_pomodoros.iter().map(move |pomodoro| Segment {
+                task_id: task.id,
+                category_id: task.category,
+                start_time: pomodoro.start_time,
+                end_time: pomodoro.end_time,
+            }))
+            .filter(|segment| segment.end_time > since)
+            .collect::<Vec<Segment>>();
+        segments.sort_by_key(|segment| segment.start_time);
+        segments
+    }
+
+    /// Segments of the running pomodoro, up to `now`.
+    pub fn active_segments(&self, now: DateTime<Utc>) -> Vec<Segment> {
+        self.tasks
+            .iter()
+            .filter_map(|task| task.active_pomodoro_jointime.map(|jointime| Segment {
+                task_id: task.id,
+                category_id: task.category,
+                start_time: jointime,
+                end_time: now,
+            }))
+            .collect()
     }
 
-    fn from_json_file(file: &mut File) -> Option<Database> {
-        let mut data = String::new();
-        file.read_to_string(&mut data).ok()?;
-        match data.len() {
-            0 => None,
-            _ => Some(serde_json::from_str(&data).expect("Could not parse json file")),
-        }
+    pub fn tasks_printeable(&self) -> Vec<PrinteableTask> {
+        get_printeable_tasklist(&self.tasks, &self.categories, None, 0)
     }
 
-    pub fn save(&self, file: &mut File) {
-        let serialized = serde_json::to_string_pretty(self).unwrap();
-        file.rewind().unwrap();
-        file.set_len(0).expect("Could not truncate file");
-        file.write_all(serialized.as_bytes()).expect("Could not write to file");
+    /// Tasks of all categories in tree order, limited to those matching `keep`
+    /// and their ancestors, so that the tree stays intact.
+    pub fn task_tree(&self, keep: impl Fn(&Task) -> bool) -> Vec<PrinteableTask> {
+        let mut kept = Vec::new();
+        for task in self.tasks.iter().filter(|task| keep(task)) {
+            let mut current = Some(task);
+            while let Some(task) = current.filter(|task| !kept.contains(&task.id)) {
+                kept.push(task.id);
+                current = task.parent.as_ref().and_then(|parent| self.tasks.iter().find(|task| task.uid == *parent));
+            }
+        }
+        printeable_tree(&self.tasks, &self.categories, None, 0, &|task| kept.contains(&task.id))
     }
 
-    pub fn tasks_printeable(&self) -> Vec<PrinteableTask> {
-        get_printeable_tasklist(&self.tasks, &self.categories, None, 0)
+    /// A single task, also when its category is hidden.
+    pub fn task_printeable(&self, task_id: u32) -> Option<PrinteableTask> {
+        self.tasks.iter().find(|task| task.id == task_id).map(|task| PrinteableTask::new(task, &self.tasks, &self.categories, 0))
     }
 
     pub fn categories_printeable(&self) -> Vec<PrinteableCategory> {
@@ -316,13 +501,164 @@ impl Database {
         task.category = category_id;
     }
 
+    pub fn delete_task(&mut self, task_id: u32) {
+        let index = self.tasks.iter().position(|task| task.id == task_id).expect("Task not found");
+        let task = self.tasks.remove(index);
+        // Subtasks move up one level instead of being deleted along
+        self.tasks.iter_mut().filter(|other| other.parent.as_ref() == Some(&task.uid)).for_each(|other| other.parent = task.parent.clone());
+        if task.pomodoro_active() && !self.tasks.iter().any(|task| task.pomodoro_active()) {
+            self.active_pomodoro_starttime = None;
+        }
+    }
+
+    pub fn categories(&self) -> &[Category] {
+        &self.categories
+    }
+
+    pub fn task_ids(&self) -> Vec<u32> {
+        self.tasks.iter().map(|task| task.id).collect()
+    }
+
+    pub fn task_done(&self, task_id: u32) -> bool {
+        self.tasks.iter().find(|task| task.id == task_id).expect("Task not found").done
+    }
+
+    pub fn set_task_done(&mut self, task_id: u32, done: bool) {
+        let task = self.tasks.iter_mut().find(|task| task.id == task_id).expect("Task not found");
+        task.done = done;
+    }
+
+    /// Keeps the creation date of a task brought over from elsewhere.
+    pub fn set_task_added(&mut self, task_id: u32, added: DateTime<Utc>) {
+        let task = self.tasks.iter_mut().find(|task| task.id == task_id).expect("Task not found");
+        task.date_added = added;
+    }
+
+    /// Records time worked on a task elsewhere.
+    pub fn add_past_pomodoro(&mut self, task_id: u32, start_time: DateTime<Utc>, end_time: DateTime<Utc>) {
+        let task = self.tasks.iter_mut().find(|task| task.id == task_id).expect("Task not found");
+        task.past_pomodoros.push(Pomodoro { start_time, end_time });
+    }
+
+    pub fn task_in_pomodoro(&self, task_id: u32) -> bool {
+        self.tasks.iter().find(|task| task.id == task_id).expect("Task not found").pomodoro_active()
+    }
+
+    pub fn has_task(&self, task_id: u32) -> bool {
+        self.tasks.iter().any(|task| task.id == task_id)
+    }
+
+    pub fn get_category_by_name(&self, name: &str) -> Option<&Category> {
+        self.categories.iter().find(|category| category.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn add_category(&mut self, name: String, hotkey: Option<char>) -> Result<u32, String> {
+        if self.get_category_by_name(&name).is_some() {
+            return Err(format!("Category {} already exists", name));
+        }
+        if let Some(category) = hotkey.and_then(|hotkey| self.get_category_by_hotkey(hotkey)) {
+            return Err(format!("Hotkey is already used by category {}", category.name));
+        }
+        let id = self.categories.iter().map(|category| category.id).max().map_or(0, |id| id + 1);
+        self.categories.push(Category::new(id, name, hotkey));
+        Ok(id)
+    }
+
+    pub fn set_category_color(&mut self, category_id: u32, color: Option<String>) {
+        let category = self.categories.iter_mut().find(|category| category.id == category_id).expect("Category not found");
+        category.color = color;
+    }
+
+    pub fn set_category_billing(&mut self, category_id: u32, billing: Option<Billing>) {
+        let category = self.categories.iter_mut().find(|category| category.id == category_id).expect("Category not found");
+        category.billing = billing;
+    }
+
+    pub fn pomodoro_duration_minutes(&self) -> u32 {
+        self.pomodoro_duration_minutes
+    }
+
+    pub fn set_pomodoro_duration(&mut self, minutes: u32) {
+        self.pomodoro_duration_minutes = minutes;
+    }
+
+    /// Moves all checked tasks into the category, returns how many were moved.
+    pub fn move_done_tasks(&mut self, category_id: u32) -> usize {
+        let done_tasks = self.tasks.iter_mut().filter(|task| task.done && task.category != category_id);
+        done_tasks.fold(0, |count, task| {
+            task.category = category_id;
+            count + 1
+        })
+    }
+
+    /// Time spent per category after `since`, categories without time are left out.
+    pub fn time_per_category(&self, segments: &[Segment], since: DateTime<Utc>) -> Vec<(Category, Duration)> {
+        let mut retval = self.categories
+            .iter()
+            .map(|category| {
+                let time = segments.iter().filter(|segment| segment.category_id == category.id).fold(Duration::zero(), |acc, segment| acc + segment.time_spent_since(since));
+                (category.clone(), time)
+            })
+            .filter(|(_, time)| *time > Duration::zero())
+            .collect::<Vec<(Category, Duration)>>();
+        retval.sort_by_key(|(_, time)| -*time);
+        retval
+    }
+
     pub fn get_category_by_hotkey(&self, hotkey: char) -> Option<&Category> {
         self.categories.iter().find(|category| category.hotkey == Some(hotkey))
     }
 
     pub fn add_task_from_string(&mut self, description: String) {
-        let highest_id = self.tasks.iter().map(|task| task.id).max().unwrap_or(0);
-        self.tasks.push(Task::new(highest_id+1, description, self.default_category_id));
+        // The counter may lag behind if the file was edited by hand
+        let id = self.next_task_id.max(self.tasks.iter().map(|task| task.id + 1).max().unwrap_or(1));
+        self.next_task_id = id + 1;
+        let mut task = Task::new(id, description, self.default_category_id);
+        task.order = self.tasks.iter().map(|task| task.order + 1).max().unwrap_or(0);
+        self.tasks.push(task);
+    }
+
+    /// Adds a task typed with quick-add syntax, returns its id.
+    pub fn add_parsed_task(&mut self, parsed: &QuickAdd, parent: Option<u32>) -> u32 {
+        let parent = parent.and_then(|parent| self.tasks.iter().find(|task| task.id == parent)).map(|parent| parent.uid.clone());
+        self.add_task_from_string(parsed.description.clone());
+        let task = self.tasks.last_mut().expect("Task was just added");
+        task.parent = parent;
+        if let Some(category) = parsed.category {
+            task.category = category;
+        }
+        task.tags = parsed.tags.clone();
+        task.priority = parsed.priority;
+        task.estimate = parsed.estimate;
+        task.due = parsed.due;
+        task.id
+    }
+
+    /// Changes a task to what another tool says about it. Without a category in
+    /// `parsed` the task keeps its own.
+    pub fn update_parsed_task(&mut self, task_id: u32, parsed: &QuickAdd, parent: Option<u32>) {
+        let parent = parent.and_then(|parent| self.tasks.iter().find(|task| task.id == parent)).map(|parent| parent.uid.clone());
+        let task = self.tasks.iter_mut().find(|task| task.id == task_id).expect("Task not found");
+        task.description = parsed.description.clone();
+        task.parent = parent;
+        if let Some(category) = parsed.category {
+            task.category = category;
+        }
+        task.tags = parsed.tags.clone();
+        task.priority = parsed.priority;
+        task.estimate = parsed.estimate;
+        task.due = parsed.due;
+    }
+
+    /// The number of the task with this uid.
+    pub fn task_with_uid(&self, uid: &str) -> Option<u32> {
+        self.tasks.iter().find(|task| task.uid == uid).map(|task| task.id)
+    }
+
+    /// Keeps the identity of a task brought over from elsewhere.
+    pub fn set_task_uid(&mut self, task_id: u32, uid: String) {
+        let task = self.tasks.iter_mut().find(|task| task.id == task_id).expect("Task not found");
+        task.uid = uid;
     }
 
     pub fn make_default_category(&mut self, category_id: u32) {
@@ -370,28 +706,50 @@ impl Database {
         }
     }
 
-    pub fn check_active_pomodoro_over(&mut self) -> Option<Vec<String>> {
-        let now = Utc::now();
+    /// When the running pomodoro is due to end.
+    pub fn pomodoro_end_time(&self) -> Option<DateTime<Utc>> {
+        self.active_pomodoro_starttime.map(|starttime| starttime + Duration::minutes(self.pomodoro_duration_minutes.into()))
+    }
 
-        match self.active_pomodoro_starttime {
-            Some(starttime) => {
-                let pomodoro_duration = Duration::minutes(self.pomodoro_duration_minutes.into());
-                let duration = now - starttime;
-                if duration > pomodoro_duration {
-                    let mut tasks: Vec<String> = Vec::new();
-                    for task in self.tasks.iter_mut() {
-                        if task.pomodoro_active() {
-                            task.leave_pomodoro(starttime + pomodoro_duration);
-                            tasks.push(task.description.clone());
-                        }
-                    }
-                    self.active_pomodoro_starttime = None;
-                    Some(tasks)
-                } else {
-                    None
+    /// Ids of the tasks in the running pomodoro.
+    pub fn pomodoro_task_ids(&self) -> Vec<u32> {
+        self.tasks.iter().filter(|task| task.pomodoro_active()).map(|task| task.id).collect()
+    }
+
+    /// Descriptions of the tasks in the running pomodoro.
+    pub fn pomodoro_tasks(&self) -> Vec<String> {
+        self.tasks.iter().filter(|task| task.pomodoro_active()).map(|task| task.description.clone()).collect()
+    }
+
+    /// Ends the running pomodoro at `end` and returns the descriptions of its tasks.
+    /// Tasks which only joined after `end` get no time.
+    pub fn end_pomodoro_at(&mut self, end: DateTime<Utc>) -> Vec<String> {
+        let mut tasks: Vec<String> = Vec::new();
+        for task in self.tasks.iter_mut() {
+            if let Some(jointime) = task.active_pomodoro_jointime {
+                match jointime < end {
+                    true => task.leave_pomodoro(end),
+                    false => task.active_pomodoro_jointime = None,
                 }
-            },
-            None => None,
+                tasks.push(task.description.clone());
+            }
+        }
+        self.active_pomodoro_starttime = None;
+        tasks
+    }
+
+    /// Stops the running pomodoro without recording any of its time.
+    pub fn discard_pomodoro(&mut self) {
+        for task in self.tasks.iter_mut() {
+            task.active_pomodoro_jointime = None;
+        }
+        self.active_pomodoro_starttime = None;
+    }
+
+    pub fn check_active_pomodoro_over(&mut self) -> Option<Vec<String>> {
+        match self.pomodoro_end_time() {
+            Some(end) if Utc::now() > end => Some(self.end_pomodoro_at(end)),
+            _ => None,
         }
     }
 
@@ -2,28 +2,33 @@ use std::fs::read;
 use std::io::Cursor;
 
 use notify_rust::{Notification, Timeout};
-use rodio::{Decoder, OutputStream, Sink, OutputStreamHandle};
+use rodio::{Decoder, OutputStream, Sink};
 use crate::constants::{ALARM_FILE, get_full_path};
 
-#[allow(dead_code)]
-pub struct NotificationManager {
-    alarm_sound: Vec<u8>,
-    stream: OutputStream,
-    stream_handle: OutputStreamHandle,
+/// The audio output, kept alive for as long as sounds should play.
+struct Audio {
+    _stream: OutputStream,
     sink: Sink,
 }
 
+/// Shows desktop notifications and plays the alarm. Both are best effort, on a
+/// machine without a sound card or notification daemon pti simply stays quiet.
+pub struct NotificationManager {
+    alarm_sound: Option<Vec<u8>>,
+    audio: Option<Audio>,
+}
+
 impl NotificationManager {
     pub fn new() -> Self {
         let alarm_path = get_full_path(ALARM_FILE);
-        let alarm_sound = read(alarm_path.as_path()).expect("Failed to read alarm file.");
-        let (stream, stream_handle) = OutputStream::try_default().unwrap();
-        let sink = Sink::try_new(&stream_handle).unwrap();
+        let alarm_sound = read(alarm_path.as_path()).ok();
+        let audio = OutputStream::try_default().ok().and_then(|(stream, stream_handle)| {
+            let sink = Sink::try_new(&stream_handle).ok()?;
+            Some(Audio { _stream: stream, sink })
+        });
         Self {
             alarm_sound,
-            stream,
-            stream_handle,
-            sink,
+            audio,
         }
     }
 
@@ -37,13 +42,13 @@ impl NotificationManager {
             .show();
 
         // Play a sound
-
-        // Load a sound from a file, using a path relative to Cargo.toml
-        let reader = Cursor::new(self.alarm_sound.clone());
-        // Decode that sound file into a source
-        let source = Decoder::new(reader).unwrap();
-        // Play the sound directly on the device
-        self.sink.append(source);
-        self.sink.play();
+        if let (Some(alarm_sound), Some(audio)) = (&self.alarm_sound, &self.audio) {
+            // Decode the sound file into a source, a broken file is skipped
+            if let Ok(source) = Decoder::new(Cursor::new(alarm_sound.clone())) {
+                // Play the sound directly on the device
+                audio.sink.append(source);
+                audio.sink.play();
+            }
+        }
     }
 }
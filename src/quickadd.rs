@@ -0,0 +1,185 @@
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+use crate::model::{Category, Priority};
+
+/// A task typed into the input with quick-add syntax, e.g.
+/// `Fix login bug @work #backend !high ~2 due:tomorrow`.
+#[derive(Clone)]
+#[derive(Default)]
+#[derive(Debug)]
+pub struct QuickAdd {
+    pub description: String,
+    pub category: Option<u32>,
+    pub tags: Vec<String>,
+    pub priority: Option<Priority>,
+    /// Estimate in pomodoros
+    pub estimate: Option<u32>,
+    pub due: Option<NaiveDate>,
+    /// Tokens which looked like syntax but could not be understood, they stay in the description.
+    pub warnings: Vec<String>,
+    /// The first `@name` for which there is no category, e.g. to create it on import.
+    pub unknown_category: Option<String>,
+}
+
+fn parse_priority(text: &str) -> Option<Priority> {
+    match text.to_lowercase().as_str() {
+        "high" | "h" | "1" => Some(Priority::High),
+        "medium" | "med" | "m" | "2" => Some(Priority::Medium),
+        "low" | "l" | "3" => Some(Priority::Low),
+        _ => None,
+    }
+}
+
+fn parse_weekday(text: &str) -> Option<Weekday> {
+    match text {
+        "mon" | "monday" => Some(Weekday::Mon),
+        "tue" | "tuesday" => Some(Weekday::Tue),
+        "wed" | "wednesday" => Some(Weekday::Wed),
+        "thu" | "thursday" => Some(Weekday::Thu),
+        "fri" | "friday" => Some(Weekday::Fri),
+        "sat" | "saturday" => Some(Weekday::Sat),
+        "sun" | "sunday" => Some(Weekday::Sun),
+        _ => None,
+    }
+}
+
+/// Understands `today`, `tomorrow`, weekday names for the next such day and ISO dates.
+pub fn parse_due(text: &str, today: NaiveDate) -> Option<NaiveDate> {
+    let text = text.to_lowercase();
+    match text.as_str() {
+        "today" => Some(today),
+        "tomorrow" => Some(today + Duration::days(1)),
+        _ => match parse_weekday(&text) {
+            Some(weekday) => {
+                let days_ahead = (7 + weekday.num_days_from_monday() - today.weekday().num_days_from_monday() - 1) % 7 + 1;
+                Some(today + Duration::days(days_ahead.into()))
+            }
+            None => NaiveDate::parse_from_str(&text, "%Y-%m-%d").ok(),
+        },
+    }
+}
+
+/// Splits the input into the description and the quick-add tokens. A backslash
+/// in front of a token keeps it literally, e.g. `\@home`.
+pub fn parse(input: &str, categories: &[Category], today: NaiveDate) -> QuickAdd {
+    let mut parsed = QuickAdd::default();
+    let mut words: Vec<String> = Vec::new();
+    for word in input.split_whitespace() {
+        if let Some(literal) = word.strip_prefix('\\') {
+            words.push(literal.to_string());
+            continue;
+        }
+        let mut chars = word.chars();
+        let marker = chars.next().expect("Words are never empty");
+        let rest = chars.as_str();
+        let understood = match marker {
+            '@' if !rest.is_empty() => match categories.iter().find(|category| category.name.eq_ignore_ascii_case(rest)) {
+                Some(category) => {
+                    parsed.category = Some(category.id);
+                    true
+                }
+                None => {
+                    parsed.warnings.push(format!("unknown category {}", word));
+                    parsed.unknown_category.get_or_insert_with(|| rest.to_string());
+                    false
+                }
+            },
+            '#' if !rest.is_empty() => {
+                parsed.tags.push(rest.to_string());
+                true
+            }
+            '!' if !rest.is_empty() => match parse_priority(rest) {
+                Some(priority) => {
+                    parsed.priority = Some(priority);
+                    true
+                }
+                None => {
+                    parsed.warnings.push(format!("unknown priority {}", word));
+                    false
+                }
+            },
+            '~' if !rest.is_empty() => match rest.parse::<u32>() {
+                Ok(estimate) => {
+                    parsed.estimate = Some(estimate);
+                    true
+                }
+                Err(_) => {
+                    parsed.warnings.push(format!("estimate {} is not a number of pomodoros", word));
+                    false
+                }
+            },
+            _ => match word.strip_prefix("due:") {
+                Some(due) => match parse_due(due, today) {
+                    Some(date) => {
+                        parsed.due = Some(date);
+                        true
+                    }
+                    None => {
+                        parsed.warnings.push(format!("unknown date {}", word));
+                        false
+                    }
+                },
+                None => false,
+            },
+        };
+        if !understood {
+            words.push(word.to_string());
+        }
+    }
+    parsed.description = words.join(" ");
+    parsed
+}
+
+/// A line of a pasted list or of a Markdown checklist, e.g. `  - [x] Call mom`.
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct ListItem<'a> {
+    /// Width of the indentation, a tab counts as four spaces.
+    pub indent: usize,
+    /// The line starts with `- `, `* ` or `+ `.
+    pub bullet: bool,
+    /// Whether the checkbox after the bullet is checked, if there is one.
+    pub checkbox: Option<bool>,
+    pub text: &'a str,
+}
+
+/// Splits a line into indentation, bullet, checkbox and the text of the task.
+pub fn list_item(line: &str) -> ListItem<'_> {
+    let content = line.trim_start();
+    let indent = line[..line.len() - content.len()].chars().map(|c| if c == '\t' { 4 } else { 1 }).sum::<usize>();
+    let Some(item) = ["- ", "* ", "+ "].iter().find_map(|bullet| content.strip_prefix(bullet)) else {
+        return ListItem { indent, bullet: false, checkbox: None, text: content };
+    };
+    let (checkbox, text) = match item.get(..4).map(|checkbox| (checkbox, &item[4..])) {
+        Some(("[ ] ", text)) => (Some(false), text),
+        Some(("[x] ", text)) | Some(("[X] ", text)) => (Some(true), text),
+        _ => (None, item),
+    };
+    ListItem { indent, bullet: true, checkbox, text }
+}
+
+impl QuickAdd {
+    /// One line describing the task that will be created, shown below the input.
+    pub fn preview(&self, categories: &[Category]) -> String {
+        let mut parts = vec![format!("\"{}\"", self.description)];
+        if let Some(category) = self.category.and_then(|id| categories.iter().find(|category| category.id == id)) {
+            parts.push(format!("in {}", category.name));
+        }
+        if !self.tags.is_empty() {
+            parts.push(format!("tags {}", self.tags.join(", ")));
+        }
+        if let Some(priority) = self.priority {
+            parts.push(format!("priority {}", priority));
+        }
+        if let Some(estimate) = self.estimate {
+            parts.push(format!("estimate {} pomodoro(s)", estimate));
+        }
+        if let Some(due) = self.due {
+            parts.push(format!("due {}", due.format("%a %Y-%m-%d")));
+        }
+        let mut preview = parts.join(", ");
+        if !self.warnings.is_empty() {
+            preview.push_str(&format!(" ({})", self.warnings.join(", ")));
+        }
+        preview
+    }
+}
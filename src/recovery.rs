@@ -0,0 +1,136 @@
+use crossterm::event::{KeyCode, KeyModifiers};
+use ratatui::{
+    backend::Backend,
+    style::{Modifier, Style},
+    widgets::{Block, Borders, Cell, Paragraph, Row, Table, TableState, Wrap},
+    layout::{Constraint, Direction, Layout},
+    Frame,
+};
+
+use crate::app::AppResult;
+use crate::backup;
+use crate::error::PtiError;
+use crate::event::Event;
+use crate::model::Database;
+use crate::storage::{Storage, StorageKind};
+use crate::tui::Tui;
+
+/// Characters shown around the error position of a long line.
+const CONTEXT_WIDTH: usize = 60;
+
+/// Shown instead of the app if the database cannot be loaded, to restore a backup or start fresh.
+pub struct Recovery {
+    error: PtiError,
+    /// The line the error points at and a marker below the position, if known.
+    location: Vec<String>,
+    backups: Vec<String>,
+    /// Index into the backups, one past the last means starting fresh.
+    selected: usize,
+    /// Why the last choice did not work.
+    message: Option<String>,
+}
+
+/// The line of the damaged file with a `^` below the column the parser stopped at.
+fn error_location(error: &PtiError) -> Vec<String> {
+    let (path, line, column) = match error {
+        PtiError::Parse { path, line, column, .. } if *line > 0 => (path, *line, *column),
+        _ => return Vec::new(),
+    };
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(_) => return Vec::new(),
+    };
+    let content = match text.lines().nth(line - 1) {
+        Some(content) => content.chars().collect::<Vec<char>>(),
+        None => return Vec::new(),
+    };
+    let start = column.saturating_sub(CONTEXT_WIDTH / 2).min(content.len());
+    let end = (start + CONTEXT_WIDTH).min(content.len());
+    vec![
+        format!("Line {}, column {}:", line, column),
+        content[start..end].iter().collect(),
+        format!("{}^", " ".repeat(column.saturating_sub(start + 1))),
+    ]
+}
+
+impl Recovery {
+    pub fn new(error: PtiError) -> Recovery {
+        let location = error_location(&error);
+        let (backups, message) = match backup::list() {
+            Ok(backups) => (backups, None),
+            Err(error) => (Vec::new(), Some(format!("Could not list the backups: {}", error))),
+        };
+        Recovery { error, location, backups, selected: 0, message }
+    }
+
+    /// The database the selected option stands for.
+    fn chosen(&self) -> AppResult<Database> {
+        match self.backups.get(self.selected) {
+            Some(name) => backup::read(name),
+            None => Ok(Database::example_db()),
+        }
+    }
+
+    pub fn render<B: Backend>(&self, frame: &mut Frame<'_, B>) {
+        let mut text = vec![self.error.to_string(), String::new()];
+        text.extend(self.location.iter().cloned());
+        let rects = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(text.len() as u16 + 4),
+                Constraint::Min(4),
+                Constraint::Length(2),
+                ].as_ref())
+            .margin(1)
+            .split(frame.size());
+
+        let p = Paragraph::new(text.join("\n"))
+            .block(Block::default().borders(Borders::ALL).title("The database could not be loaded"))
+            .wrap(Wrap { trim: false });
+        frame.render_widget(p, rects[0]);
+
+        let mut rows = self.backups.iter()
+            .map(|name| Row::new(vec![Cell::from(format!("Restore backup {}", name))]))
+            .collect::<Vec<Row>>();
+        rows.push(Row::new(vec![Cell::from("Start with a fresh database")]));
+        let t = Table::new(rows)
+            .block(Block::default().borders(Borders::ALL).title("Recover"))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+            .widths(&[Constraint::Percentage(100)]);
+        frame.render_stateful_widget(t, rects[1], &mut TableState::default().with_selected(Some(self.selected)));
+
+        let help = "up/down: Choose, enter: Recover (the damaged file is kept in the backups), q: Quit without changes";
+        let p = Paragraph::new(match &self.message {
+            Some(message) => format!("{}\n{}", message, help),
+            None => help.to_string(),
+        }).wrap(Wrap { trim: false });
+        frame.render_widget(p, rects[2]);
+    }
+}
+
+/// Runs the recovery screen until a database was recovered, `None` if the user quit.
+pub fn run<B: Backend>(tui: &mut Tui<B>, storage: &mut dyn Storage, kind: StorageKind, error: PtiError) -> AppResult<Option<Database>> {
+    let mut recovery = Recovery::new(error);
+    loop {
+        tui.draw_recovery(&recovery)?;
+        let key_event = match tui.events.next()? {
+            Event::Key(key_event) => key_event,
+            _ => continue,
+        };
+        match key_event.code {
+            KeyCode::Up => recovery.selected = recovery.selected.saturating_sub(1),
+            KeyCode::Down => recovery.selected = (recovery.selected + 1).min(recovery.backups.len()),
+            KeyCode::Enter => match recovery.chosen() {
+                Ok(database) => {
+                    backup::keep_damaged(kind)?;
+                    storage.save(&database)?;
+                    return Ok(Some(database));
+                }
+                Err(error) => recovery.message = Some(format!("Cannot use this backup: {}", error)),
+            },
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(None),
+            KeyCode::Char('c') if key_event.modifiers == KeyModifiers::CONTROL => return Ok(None),
+            _ => {}
+        }
+    }
+}
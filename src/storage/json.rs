@@ -0,0 +1,113 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use chrono::{DateTime, Utc};
+use fs2::FileExt;
+use crate::error::PtiError;
+use crate::model::{Database, Segment};
+use super::{Storage, database_from_value, write_atomic};
+
+pub struct JsonStorage {
+    path: PathBuf,
+    /// Held for the exclusive lock, `None` when opened read-only. The database file
+    /// itself is replaced on every save, so the lock lives on a separate file.
+    lock: Option<File>,
+    /// What was last loaded or saved, to answer queries.
+    snapshot: Option<Database>,
+}
+
+impl JsonStorage {
+    pub fn open(path: &Path, lock_path: &Path) -> Result<JsonStorage, PtiError> {
+        let lock = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(lock_path)
+            .map_err(|error| PtiError::io(lock_path, error))?;
+
+        lock.try_lock_exclusive()
+            .map_err(|_| PtiError::Locked(path.to_path_buf()))?;
+        // Older versions of pti lock the database file itself
+        if let Ok(database) = File::open(path) {
+            database.try_lock_exclusive()
+                .map_err(|_| PtiError::Locked(path.to_path_buf()))?;
+        }
+
+        Ok(JsonStorage { path: path.to_path_buf(), lock: Some(lock), snapshot: None })
+    }
+
+    /// Opens the database without taking the lock, for looking at it while another instance owns it.
+    pub fn open_read_only(path: &Path) -> JsonStorage {
+        JsonStorage { path: path.to_path_buf(), lock: None, snapshot: None }
+    }
+}
+
+impl Storage for JsonStorage {
+    fn load(&mut self) -> Result<Option<Database>, PtiError> {
+        let data = match fs::read_to_string(&self.path) {
+            Ok(data) => data,
+            Err(error) if error.kind() == ErrorKind::NotFound => String::new(),
+            Err(error) => return Err(PtiError::io(&self.path, error)),
+        };
+        if data.is_empty() {
+            self.snapshot = None;
+            return Ok(None);
+        }
+        let value = serde_json::from_str(&data).map_err(|error| PtiError::parse(&self.path, error))?;
+        let (database, upgraded) = database_from_value(&self.path, value, !self.read_only())?;
+        // Write the upgraded version right away, so that the migration only runs once
+        if upgraded && !self.read_only() {
+            self.save(&database)?;
+        }
+        self.snapshot = Some(database.clone());
+        Ok(Some(database))
+    }
+
+    fn save(&mut self, database: &Database) -> Result<(), PtiError> {
+        if self.read_only() {
+            return Err(PtiError::ReadOnly);
+        }
+        let serialized = serde_json::to_string_pretty(database).map_err(|error| PtiError::Storage(error.to_string()))?;
+        write_atomic(&self.path, serialized.as_bytes())?;
+        self.snapshot = Some(database.clone());
+        Ok(())
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn read_only(&self) -> bool {
+        self.lock.is_none()
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        fs::metadata(&self.path).and_then(|metadata| metadata.modified()).ok()
+    }
+
+    fn segments_since(&mut self, since: DateTime<Utc>) -> Result<Vec<Segment>, PtiError> {
+        Ok(self.snapshot.as_ref().map(|database| database.segments_since(since)).unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_lock_of_older_versions_is_respected() {
+        let dir = std::env::temp_dir().join(format!("pti-json-lock-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("database.json");
+        fs::write(&path, "").unwrap();
+        // As taken by pti before the lock moved to its own file
+        let old = File::open(&path).unwrap();
+        old.try_lock_exclusive().unwrap();
+        assert!(matches!(JsonStorage::open(&path, &dir.join("database.lock")), Err(PtiError::Locked(_))));
+        drop(old);
+        assert!(JsonStorage::open(&path, &dir.join("database.lock")).is_ok());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
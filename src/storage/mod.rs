@@ -0,0 +1,168 @@
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use serde::{Serialize, Deserialize};
+use crate::app::AppResult;
+use crate::backup;
+use crate::migration::{self, SCHEMA_VERSION};
+use crate::error::PtiError;
+use crate::constants::{TASK_FILE, SQLITE_FILE, LOCK_FILE, get_full_path};
+use crate::model::{Database, Segment};
+
+/// Storage in database.json, the whole file is rewritten on every save.
+pub mod json;
+
+/// Storage in an SQLite database, only changed rows are written.
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+/// Writes to a temporary file next to `path` and renames it over `path`, so
+/// that a crash halfway through leaves the old file intact.
+pub fn write_atomic(path: &Path, data: &[u8]) -> Result<(), PtiError> {
+    let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+    let mut file = File::create(&temp_path).map_err(|error| PtiError::io(&temp_path, error))?;
+    file.write_all(data).and_then(|_| file.sync_all()).map_err(|error| PtiError::io(&temp_path, error))?;
+    drop(file);
+    fs::rename(&temp_path, path).map_err(|error| PtiError::io(path, error))
+}
+
+/// Turns the json of a database read from `path` into a [`Database`]. Older
+/// schema versions are upgraded, the flag tells if that happened. Only the
+/// `writable` instance, which saves the upgrade, backs up the original.
+pub(crate) fn database_from_value(path: &Path, value: Value, writable: bool) -> Result<(Database, bool), PtiError> {
+    let version = migration::version_of(&value).map_err(|message| schema_error(path, message))?;
+    let upgraded = version < SCHEMA_VERSION;
+    if upgraded && writable {
+        backup::keep_before_migration(version, &value)?;
+    }
+    Ok((upgrade_value(path, value)?, upgraded))
+}
+
+fn schema_error(path: &Path, message: String) -> PtiError {
+    PtiError::Parse { path: path.to_path_buf(), line: 0, column: 0, message }
+}
+
+/// Turns the json of a database read from `path` into a [`Database`], upgrading
+/// older schema versions without keeping the original, e.g. for backups.
+pub(crate) fn upgrade_value(path: &Path, mut value: Value) -> Result<Database, PtiError> {
+    let version = migration::version_of(&value).map_err(|message| schema_error(path, message))?;
+    if version > SCHEMA_VERSION {
+        return Err(PtiError::NewerSchema { path: path.to_path_buf(), version });
+    }
+    migration::upgrade(&mut value).map_err(|message| schema_error(path, message))?;
+    serde_json::from_value(value).map_err(|error| PtiError::parse(path, error))
+}
+
+/// Where the database is persisted.
+pub trait Storage {
+    /// Reads the database, `None` if nothing has been saved yet.
+    fn load(&mut self) -> Result<Option<Database>, PtiError>;
+
+    /// Writes the database. Without a previous load or save everything stored before is replaced.
+    fn save(&mut self, database: &Database) -> Result<(), PtiError>;
+
+    /// The file the database lives in.
+    fn path(&self) -> &Path;
+
+    /// Whether another instance owns the database, so that saving is not possible.
+    fn read_only(&self) -> bool;
+
+    /// Whether this instance ends pomodoros and notifies about it. Not the case when
+    /// another instance owns the database or a daemon runs the timer.
+    fn runs_timer(&self) -> bool {
+        !self.read_only()
+    }
+
+    /// When the database was last written, by this or another instance.
+    fn modified(&self) -> Option<SystemTime>;
+
+    /// Finished pomodoro segments which end after `since`, oldest first.
+    fn segments_since(&mut self, since: DateTime<Utc>) -> Result<Vec<Segment>, PtiError>;
+}
+
+#[derive(Clone, Copy)]
+#[derive(Serialize)]
+#[derive(Deserialize)]
+#[derive(Eq)]
+#[derive(PartialEq)]
+#[derive(Default)]
+#[derive(Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageKind {
+    #[default]
+    Json,
+    Sqlite,
+}
+
+impl StorageKind {
+    pub fn parse(name: &str) -> Option<StorageKind> {
+        match name {
+            "json" => Some(StorageKind::Json),
+            "sqlite" => Some(StorageKind::Sqlite),
+            _ => None,
+        }
+    }
+}
+
+/// The file the storage of the given kind keeps the database in.
+pub fn database_path(kind: StorageKind) -> PathBuf {
+    match kind {
+        StorageKind::Json => get_full_path(TASK_FILE),
+        StorageKind::Sqlite => get_full_path(SQLITE_FILE),
+    }
+}
+
+/// Opens and locks the storage of the given kind in the storage directory.
+pub fn open(kind: StorageKind) -> Result<Box<dyn Storage>, PtiError> {
+    match kind {
+        StorageKind::Json => Ok(Box::new(json::JsonStorage::open(&database_path(kind), &get_full_path(LOCK_FILE))?)),
+        #[cfg(feature = "sqlite")]
+        StorageKind::Sqlite => Ok(Box::new(sqlite::SqliteStorage::open(&database_path(kind))?)),
+        #[cfg(not(feature = "sqlite"))]
+        StorageKind::Sqlite => Err(PtiError::Unsupported(format!("pti was built without SQLite support, cannot open {}. Reinstall with --features=sqlite.", database_path(kind).display()))),
+    }
+}
+
+/// Opens the storage for changing it. If the daemon owns the database, the
+/// changes go through the daemon instead.
+pub fn open_writable(kind: StorageKind) -> Result<Box<dyn Storage>, PtiError> {
+    match open(kind) {
+        #[cfg(unix)]
+        Err(PtiError::Locked(path)) => match crate::daemon::client::DaemonStorage::connect(kind) {
+            Ok(storage) => Ok(Box::new(storage)),
+            Err(_) => Err(PtiError::Locked(path)),
+        },
+        result => result,
+    }
+}
+
+/// Opens the storage of the given kind without locking it, saving is refused.
+pub fn open_read_only(kind: StorageKind) -> Result<Box<dyn Storage>, PtiError> {
+    match kind {
+        StorageKind::Json => Ok(Box::new(json::JsonStorage::open_read_only(&database_path(kind)))),
+        #[cfg(feature = "sqlite")]
+        StorageKind::Sqlite => Ok(Box::new(sqlite::SqliteStorage::open_read_only(&database_path(kind))?)),
+        #[cfg(not(feature = "sqlite"))]
+        StorageKind::Sqlite => open(kind),
+    }
+}
+
+/// Copies the database from one storage into the other.
+pub fn migrate(from: StorageKind, to: StorageKind) -> AppResult<()> {
+    if from == to {
+        return Err("Source and target storage are the same".into());
+    }
+    let mut source = open(from)?;
+    let database = source.load()?.ok_or("There is no database to migrate")?;
+    let mut target = open(to)?;
+    if target.load()?.is_some() {
+        return Err("The target storage already contains a database, remove it first".into());
+    }
+    target.save(&database)?;
+    Ok(())
+}
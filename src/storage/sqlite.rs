@@ -0,0 +1,275 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use chrono::{DateTime, SecondsFormat, Utc};
+use fs2::FileExt;
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use serde_json::Value;
+use crate::error::PtiError;
+use crate::model::{Database, Pomodoro, Segment, Task};
+use super::{Storage, database_from_value};
+
+/// Tasks and categories are stored as json in their rows, with the columns needed
+/// for queries next to it. Pomodoros get their own table so that reports can use an index.
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS settings (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+    CREATE TABLE IF NOT EXISTS categories (id INTEGER PRIMARY KEY, data TEXT NOT NULL);
+    CREATE TABLE IF NOT EXISTS tasks (id INTEGER PRIMARY KEY, category INTEGER NOT NULL, parent TEXT, data TEXT NOT NULL);
+    CREATE TABLE IF NOT EXISTS pomodoros (task_id INTEGER NOT NULL, start_time TEXT NOT NULL, end_time TEXT NOT NULL);
+    CREATE INDEX IF NOT EXISTS pomodoros_task_id ON pomodoros (task_id);
+    CREATE INDEX IF NOT EXISTS pomodoros_end_time ON pomodoros (end_time);
+";
+
+/// Version of the tables, kept in `PRAGMA user_version`. The json in the rows
+/// has its own schema version, see [`crate::migration`].
+const TABLES_VERSION: i64 = 1;
+
+/// Brings the tables written by an older pti up to [`TABLES_VERSION`], one step per version.
+fn upgrade_tables(connection: &Connection) -> Result<(), PtiError> {
+    let version: i64 = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if version < 1 {
+        // Parents are uids since schema version 4, the column was declared INTEGER.
+        // Tasks written before are saved again once the json is upgraded.
+        connection.execute_batch("
+            BEGIN;
+            ALTER TABLE tasks RENAME TO tasks_v0;
+            CREATE TABLE tasks (id INTEGER PRIMARY KEY, category INTEGER NOT NULL, parent TEXT, data TEXT NOT NULL);
+            INSERT INTO tasks (id, category, parent, data) SELECT id, category, CAST(parent AS TEXT), data FROM tasks_v0;
+            DROP TABLE tasks_v0;
+            COMMIT;
+        ")?;
+    }
+    if version < TABLES_VERSION {
+        connection.pragma_update(None, "user_version", TABLES_VERSION)?;
+    }
+    Ok(())
+}
+
+/// Fixed width, so that timestamps compare correctly as text.
+fn format_time(time: &DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Nanos, true)
+}
+
+/// Rows which cannot be read back.
+fn damaged(what: &str, error: impl Display) -> PtiError {
+    PtiError::Storage(format!("{} is damaged: {}", what, error))
+}
+
+fn parse_time(text: &str) -> Result<DateTime<Utc>, PtiError> {
+    Ok(DateTime::parse_from_rfc3339(text).map_err(|error| damaged("A pomodoro time", error))?.with_timezone(&Utc))
+}
+
+/// Everything of the database except for tasks and categories.
+fn settings_json(database: &Database) -> Result<String, PtiError> {
+    let mut value = serde_json::to_value(database).map_err(|error| PtiError::Storage(error.to_string()))?;
+    let object = value.as_object_mut().ok_or(PtiError::Storage("Database is not a json object".to_string()))?;
+    object.remove("tasks");
+    object.remove("categories");
+    Ok(value.to_string())
+}
+
+/// The task without its pomodoros, which live in their own table.
+fn task_json(task: &Task) -> Result<String, PtiError> {
+    let mut value = serde_json::to_value(task).map_err(|error| PtiError::Storage(error.to_string()))?;
+    value.as_object_mut().ok_or(PtiError::Storage("Task is not a json object".to_string()))?.remove("past_pomodoros");
+    Ok(value.to_string())
+}
+
+pub struct SqliteStorage {
+    path: PathBuf,
+    connection: Connection,
+    /// Held for the exclusive lock, `None` when opened read-only.
+    lock: Option<File>,
+    /// What was last loaded or saved, to find the rows that changed.
+    snapshot: Option<Database>,
+}
+
+impl SqliteStorage {
+    pub fn open(path: &Path) -> Result<SqliteStorage, PtiError> {
+        let lock = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(|error| PtiError::io(path, error))?;
+        lock.try_lock_exclusive()
+            .map_err(|_| PtiError::Locked(path.to_path_buf()))?;
+
+        let connection = Connection::open(path)?;
+        // A new file gets the current tables right away
+        let new: bool = connection.query_row("SELECT NOT EXISTS (SELECT 1 FROM sqlite_master WHERE name = 'tasks')", [], |row| row.get(0))?;
+        connection.execute_batch(SCHEMA)?;
+        if new {
+            connection.pragma_update(None, "user_version", TABLES_VERSION)?;
+        }
+        upgrade_tables(&connection)?;
+        Ok(SqliteStorage { path: path.to_path_buf(), connection, lock: Some(lock), snapshot: None })
+    }
+
+    /// Opens the database without taking the lock, for looking at it while another instance owns it.
+    pub fn open_read_only(path: &Path) -> Result<SqliteStorage, PtiError> {
+        let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        Ok(SqliteStorage { path: path.to_path_buf(), connection, lock: None, snapshot: None })
+    }
+
+    /// Reads all rows, and whether the database was upgraded from an older schema version.
+    fn read(&self) -> Result<Option<(Database, bool)>, PtiError> {
+        let settings: Option<String> = self.connection
+            .query_row("SELECT value FROM settings WHERE key = 'database'", [], |row| row.get(0))
+            .optional()?;
+        let settings = match settings {
+            Some(settings) => settings,
+            None => return Ok(None),
+        };
+        // The rows are put together into the json of the whole database, so that
+        // older schema versions go through the same migrations as json files
+        let mut value: Value = serde_json::from_str(&settings).map_err(|error| damaged("The settings row", error))?;
+        let object = value.as_object_mut().ok_or(damaged("The settings row", "not a json object"))?;
+
+        let mut categories = Vec::new();
+        let mut statement = self.connection.prepare("SELECT data FROM categories ORDER BY id")?;
+        for data in statement.query_map([], |row| row.get::<_, String>(0))? {
+            categories.push(serde_json::from_str::<Value>(&data?).map_err(|error| damaged("A category", error))?);
+        }
+        object.insert("categories".to_string(), Value::Array(categories));
+
+        let mut tasks = Vec::new();
+        let mut statement = self.connection.prepare("SELECT data FROM tasks ORDER BY id")?;
+        for data in statement.query_map([], |row| row.get::<_, String>(0))? {
+            let mut task: Value = serde_json::from_str(&data?).map_err(|error| damaged("A task", error))?;
+            task.as_object_mut().ok_or(damaged("A task", "not a json object"))?.insert("past_pomodoros".to_string(), Value::Array(Vec::new()));
+            tasks.push(task);
+        }
+        object.insert("tasks".to_string(), Value::Array(tasks));
+        let (mut database, upgraded) = database_from_value(&self.path, value, !self.read_only())?;
+
+        let mut pomodoros: HashMap<u32, Vec<Pomodoro>> = HashMap::new();
+        let mut statement = self.connection.prepare("SELECT task_id, start_time, end_time FROM pomodoros ORDER BY start_time")?;
+        let rows = statement.query_map([], |row| Ok((row.get::<_, u32>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?)))?;
+        for row in rows {
+            let (task_id, start_time, end_time) = row?;
+            pomodoros.entry(task_id).or_default().push(Pomodoro {
+                start_time: parse_time(&start_time)?,
+                end_time: parse_time(&end_time)?,
+            });
+        }
+        for task in database.tasks.iter_mut() {
+            task.past_pomodoros = pomodoros.remove(&task.id).unwrap_or_default();
+        }
+        Ok(Some((database, upgraded)))
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn load(&mut self) -> Result<Option<Database>, PtiError> {
+        let (database, upgraded) = match self.read()? {
+            Some(read) => read,
+            None => return Ok(None),
+        };
+        // Write the upgraded version right away, so that the migration only runs once
+        if upgraded && !self.read_only() {
+            self.save(&database)?;
+        }
+        self.snapshot = Some(database.clone());
+        Ok(Some(database))
+    }
+
+    fn save(&mut self, database: &Database) -> Result<(), PtiError> {
+        if self.read_only() {
+            return Err(PtiError::ReadOnly);
+        }
+        let previous = self.snapshot.take();
+        let transaction = self.connection.transaction()?;
+        // Without a snapshot nothing is known about the stored rows, so all of them are replaced
+        if previous.is_none() {
+            transaction.execute_batch("DELETE FROM categories; DELETE FROM tasks; DELETE FROM pomodoros;")?;
+        }
+        transaction.execute(
+            "INSERT OR REPLACE INTO settings (key, value) VALUES ('database', ?1)",
+            params![settings_json(database)?],
+        )?;
+
+        for category in database.categories.iter() {
+            let unchanged = previous.as_ref().is_some_and(|previous| previous.categories.iter().any(|other| other == category));
+            if !unchanged {
+                transaction.execute(
+                    "INSERT OR REPLACE INTO categories (id, data) VALUES (?1, ?2)",
+                    params![category.id, serde_json::to_string(category).map_err(|error| PtiError::Storage(error.to_string()))?],
+                )?;
+            }
+        }
+
+        for task in database.tasks.iter() {
+            let previous_task = previous.as_ref().and_then(|previous| previous.tasks.iter().find(|other| other.id == task.id));
+            if previous_task == Some(task) {
+                continue;
+            }
+            transaction.execute(
+                "INSERT OR REPLACE INTO tasks (id, category, parent, data) VALUES (?1, ?2, ?3, ?4)",
+                params![task.id, task.category, task.parent, task_json(task)?],
+            )?;
+            if previous_task.map(|previous_task| &previous_task.past_pomodoros) != Some(&task.past_pomodoros) {
+                transaction.execute("DELETE FROM pomodoros WHERE task_id = ?1", params![task.id])?;
+                for pomodoro in task.past_pomodoros.iter() {
+                    transaction.execute(
+                        "INSERT INTO pomodoros (task_id, start_time, end_time) VALUES (?1, ?2, ?3)",
+                        params![task.id, format_time(&pomodoro.start_time), format_time(&pomodoro.end_time)],
+                    )?;
+                }
+            }
+        }
+
+        // Rows of deleted tasks and categories
+        if let Some(previous) = previous.as_ref() {
+            for task in previous.tasks.iter().filter(|task| !database.tasks.iter().any(|other| other.id == task.id)) {
+                transaction.execute("DELETE FROM tasks WHERE id = ?1", params![task.id])?;
+                transaction.execute("DELETE FROM pomodoros WHERE task_id = ?1", params![task.id])?;
+            }
+            for category in previous.categories.iter().filter(|category| !database.categories.iter().any(|other| other.id == category.id)) {
+                transaction.execute("DELETE FROM categories WHERE id = ?1", params![category.id])?;
+            }
+        }
+
+        transaction.commit()?;
+        self.snapshot = Some(database.clone());
+        Ok(())
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn read_only(&self) -> bool {
+        self.lock.is_none()
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        fs::metadata(&self.path).and_then(|metadata| metadata.modified()).ok()
+    }
+
+    fn segments_since(&mut self, since: DateTime<Utc>) -> Result<Vec<Segment>, PtiError> {
+        let mut statement = self.connection.prepare(
+            "SELECT pomodoros.task_id, tasks.category, pomodoros.start_time, pomodoros.end_time
+             FROM pomodoros JOIN tasks ON tasks.id = pomodoros.task_id
+             WHERE pomodoros.end_time > ?1
+             ORDER BY pomodoros.start_time",
+        )?;
+        let rows = statement.query_map(params![format_time(&since)], |row| {
+            Ok((row.get::<_, u32>(0)?, row.get::<_, u32>(1)?, row.get::<_, String>(2)?, row.get::<_, String>(3)?))
+        })?;
+        let mut segments = Vec::new();
+        for row in rows {
+            let (task_id, category_id, start_time, end_time) = row?;
+            segments.push(Segment {
+                task_id,
+                category_id,
+                start_time: parse_time(&start_time)?,
+                end_time: parse_time(&end_time)?,
+            });
+        }
+        Ok(segments)
+    }
+}
@@ -0,0 +1,200 @@
+use std::path::PathBuf;
+use std::process::{Command, Output};
+use chrono::{DateTime, Duration, Local, Utc};
+use crate::app::AppResult;
+use crate::backup;
+use crate::constants::{TASK_FILE, get_full_path};
+use crate::merge::{self, Merged};
+use crate::model::Database;
+use crate::storage::{Storage, write_atomic};
+
+/// Only the database is shared, the lock, backups and settings stay on each machine.
+const GITIGNORE: &str = "/*\n!/.gitignore\n!/database.json\n";
+
+/// What `pti sync` did.
+pub enum Outcome {
+    /// Nothing new on either side.
+    UpToDate,
+    /// Only our side had new commits.
+    Pushed,
+    /// Only their side had new commits.
+    FastForwarded,
+    /// Both sides had new commits, the conflicts list the edits where ours was kept.
+    Merged(Vec<String>),
+}
+
+fn repository() -> PathBuf {
+    get_full_path("")
+}
+
+/// Whether the storage directory is a git repository, which turns on committing saves.
+pub fn enabled() -> bool {
+    get_full_path(".git").exists()
+}
+
+fn run_git(args: &[&str]) -> AppResult<Output> {
+    Command::new("git")
+        .arg("-C")
+        .arg(repository())
+        .args(args)
+        .output()
+        .map_err(|error| format!("Could not run git: {}", error).into())
+}
+
+/// Runs git in the storage directory and returns what it printed.
+fn git(args: &[&str]) -> AppResult<String> {
+    let output = run_git(args)?;
+    match output.status.success() {
+        true => Ok(String::from_utf8_lossy(&output.stdout).trim().to_string()),
+        false => Err(format!("git {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim()).into()),
+    }
+}
+
+/// Whether git exits successfully, for commands which answer with their status.
+fn git_succeeds(args: &[&str]) -> AppResult<bool> {
+    Ok(run_git(args)?.status.success())
+}
+
+fn commit(message: &str) -> AppResult<()> {
+    git(&["commit", "-q", "-m", message])?;
+    Ok(())
+}
+
+/// Commits the database if the storage directory is a git repository and it changed.
+pub fn commit_database() -> AppResult<()> {
+    if !enabled() || !get_full_path(TASK_FILE).exists() {
+        return Ok(());
+    }
+    git(&["add", TASK_FILE])?;
+    if git_succeeds(&["diff", "--cached", "--quiet"])? {
+        return Ok(());
+    }
+    commit(&format!("pti {}", Local::now().format("%Y-%m-%d %H:%M:%S")))
+}
+
+/// Seconds without saves after which the saved database is committed.
+const COMMIT_IDLE: i64 = 300;
+
+/// Commits the database once saving has paused for a while, instead of
+/// committing every single save.
+#[derive(Default)]
+pub struct PendingCommit {
+    /// The last save which was not committed yet.
+    saved: Option<DateTime<Utc>>,
+}
+
+impl PendingCommit {
+    pub fn saved(&mut self) {
+        self.saved = Some(Utc::now());
+    }
+
+    /// Commits if nothing was saved for [`COMMIT_IDLE`] seconds.
+    pub fn tick(&mut self, now: DateTime<Utc>) -> AppResult<()> {
+        match self.saved {
+            Some(saved) if now - saved >= Duration::seconds(COMMIT_IDLE) => self.flush(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Commits what was saved so far, e.g. when quitting.
+    pub fn flush(&mut self) -> AppResult<()> {
+        match self.saved.take() {
+            Some(_) => commit_database(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Makes the storage directory a git repository, optionally with `remote` as origin.
+pub fn init(remote: Option<&str>) -> AppResult<()> {
+    if !enabled() {
+        git(&["init", "-q"])?;
+    }
+    // Commits need an identity, a fresh machine may not have one configured
+    if !git_succeeds(&["config", "user.email"])? {
+        git(&["config", "user.name", "pti"])?;
+        git(&["config", "user.email", "pti@localhost"])?;
+    }
+    let gitignore = get_full_path(".gitignore");
+    if !gitignore.exists() {
+        write_atomic(&gitignore, GITIGNORE.as_bytes())?;
+    }
+    if let Some(remote) = remote {
+        git(&["remote", "add", "origin", remote])?;
+    }
+    git(&["add", ".gitignore"])?;
+    if get_full_path(TASK_FILE).exists() {
+        git(&["add", TASK_FILE])?;
+    }
+    if !git_succeeds(&["diff", "--cached", "--quiet"])? || !git_succeeds(&["rev-parse", "--verify", "-q", "HEAD"])? {
+        commit("Start syncing pti")?;
+    }
+    Ok(())
+}
+
+/// The database as committed in `revision`, upgraded to the current schema.
+fn database_at(revision: &str) -> AppResult<Database> {
+    merge::parse(&git(&["show", &format!("{}:{}", revision, TASK_FILE)])?)
+}
+
+/// Commits the database, pulls from `remote`, merges both versions task by task
+/// and pushes the result. `storage` has to be the locked JSON storage.
+pub fn sync(storage: &mut dyn Storage, remote: &str) -> AppResult<Outcome> {
+    if !enabled() {
+        return Err("The storage directory is no git repository yet, run `pti sync init <remote>` first".into());
+    }
+    commit_database()?;
+    let branch = git(&["symbolic-ref", "--short", "HEAD"])?;
+    let push = |outcome: Outcome| -> AppResult<Outcome> {
+        git(&["push", "-q", remote, &format!("HEAD:refs/heads/{}", branch)])?;
+        Ok(outcome)
+    };
+    if git(&["ls-remote", "--heads", remote, &branch])?.is_empty() {
+        return push(Outcome::Pushed);
+    }
+    git(&["fetch", "-q", remote, &branch])?;
+    if git_succeeds(&["merge-base", "--is-ancestor", "FETCH_HEAD", "HEAD"])? {
+        return match git(&["rev-parse", "HEAD"])? == git(&["rev-parse", "FETCH_HEAD"])? {
+            true => Ok(Outcome::UpToDate),
+            false => push(Outcome::Pushed),
+        };
+    }
+    if git_succeeds(&["merge-base", "--is-ancestor", "HEAD", "FETCH_HEAD"])? {
+        git(&["merge", "-q", "--ff-only", "FETCH_HEAD"])?;
+        if let Some(database) = storage.load()? {
+            backup::write(&database)?;
+        }
+        return Ok(Outcome::FastForwarded);
+    }
+    match merge_fetched(storage, remote, &branch) {
+        Ok(conflicts) => push(Outcome::Merged(conflicts)),
+        Err(error) => {
+            // Leave the repository as before, the next sync tries again
+            let _ = run_git(&["merge", "--abort"]);
+            Err(error)
+        }
+    }
+}
+
+/// Merges FETCH_HEAD into HEAD, the database instead of the text of it.
+fn merge_fetched(storage: &mut dyn Storage, remote: &str, branch: &str) -> AppResult<Vec<String>> {
+    // Without a common version, e.g. when two machines started syncing at once,
+    // nothing counts as deleted
+    let base = git(&["merge-base", "HEAD", "FETCH_HEAD"]).ok().and_then(|base| database_at(&base).ok());
+    let theirs = database_at("FETCH_HEAD")?;
+    // A machine which just started syncing takes the database as it is
+    let Merged { database, conflicts } = match storage.load()? {
+        Some(ours) => merge::merge(base.as_ref(), &ours, &theirs),
+        None => Merged { database: theirs, conflicts: Vec::new() },
+    };
+    git(&["merge", "-q", "--no-commit", "--no-ff", "--allow-unrelated-histories", "-s", "ours", "FETCH_HEAD"])?;
+    storage.save(&database)?;
+    backup::write(&database)?;
+    git(&["add", TASK_FILE])?;
+    let mut message = format!("Merge {}/{}", remote, branch);
+    if !conflicts.is_empty() {
+        message.push_str(&format!("\n\nConflicts, kept our side:\n{}", conflicts.join("\n")));
+    }
+    commit(&message)?;
+    Ok(conflicts)
+}
@@ -0,0 +1,202 @@
+use std::env;
+use std::fs::read_to_string;
+use ratatui::style::{Color, Modifier, Style};
+use serde::Deserialize;
+use crate::constants::get_full_path;
+use crate::model::Category;
+
+/// Parses colour names like `red` or `lightblue`, hex colours like `#ff8800`
+/// and indices into the 256 colour palette.
+pub fn parse_color(text: &str) -> Option<Color> {
+    let text = text.trim().to_lowercase();
+    if let Some(hex) = text.strip_prefix('#') {
+        if hex.len() != 6 {
+            return None;
+        }
+        let value = u32::from_str_radix(hex, 16).ok()?;
+        return Some(Color::Rgb((value >> 16) as u8, (value >> 8) as u8, value as u8));
+    }
+    if let Ok(index) = text.parse::<u8>() {
+        return Some(Color::Indexed(index));
+    }
+    let color = match text.replace(['_', '-', ' '], "").as_str() {
+        "reset" | "default" => Color::Reset,
+        "black" => Color::Black,
+        "red" => Color::Red,
+        "green" => Color::Green,
+        "yellow" => Color::Yellow,
+        "blue" => Color::Blue,
+        "magenta" => Color::Magenta,
+        "cyan" => Color::Cyan,
+        "gray" | "grey" => Color::Gray,
+        "darkgray" | "darkgrey" => Color::DarkGray,
+        "lightred" => Color::LightRed,
+        "lightgreen" => Color::LightGreen,
+        "lightyellow" => Color::LightYellow,
+        "lightblue" => Color::LightBlue,
+        "lightmagenta" => Color::LightMagenta,
+        "lightcyan" => Color::LightCyan,
+        "white" => Color::White,
+        _ => return None,
+    };
+    Some(color)
+}
+
+/// A theme file, every colour overrides the one of the base theme.
+#[derive(Deserialize)]
+#[derive(Default)]
+#[derive(Debug)]
+pub struct ThemeFile {
+    pub base: Option<String>,
+    pub header_fg: Option<String>,
+    pub header_bg: Option<String>,
+    pub gauge_fg: Option<String>,
+    pub gauge_bg: Option<String>,
+    pub help_fg: Option<String>,
+    pub help_bg: Option<String>,
+    pub status_fg: Option<String>,
+    pub highlight_fg: Option<String>,
+    pub highlight_bg: Option<String>,
+    pub marked_bg: Option<String>,
+}
+
+#[derive(Clone)]
+#[derive(Debug)]
+pub struct Theme {
+    /// Text of the table headers
+    pub header: Style,
+    /// Background of the table header rows
+    pub header_row: Style,
+    pub gauge: Style,
+    pub help: Style,
+    pub status: Style,
+    /// The selected row of a table
+    pub highlight: Style,
+    /// Rows marked for bulk operations
+    pub marked: Style,
+    /// Whether categories are shown in their own colour
+    pub category_colors: bool,
+}
+
+impl Theme {
+    pub fn dark() -> Theme {
+        Theme {
+            header: Style::default().fg(Color::Red),
+            header_row: Style::default().bg(Color::Blue),
+            gauge: Style::default().fg(Color::Green).bg(Color::Black),
+            help: Style::default().fg(Color::White).bg(Color::Black),
+            status: Style::default().fg(Color::Yellow),
+            highlight: Style::default().add_modifier(Modifier::BOLD),
+            marked: Style::default().bg(Color::DarkGray),
+            category_colors: true,
+        }
+    }
+
+    pub fn light() -> Theme {
+        Theme {
+            header: Style::default().fg(Color::White),
+            header_row: Style::default().bg(Color::Blue),
+            gauge: Style::default().fg(Color::Blue).bg(Color::Gray),
+            help: Style::default().fg(Color::Black).bg(Color::White),
+            status: Style::default().fg(Color::Magenta),
+            highlight: Style::default().fg(Color::Blue).add_modifier(Modifier::BOLD),
+            marked: Style::default().bg(Color::LightYellow),
+            category_colors: true,
+        }
+    }
+
+    /// No colours at all, only text attributes.
+    pub fn monochrome() -> Theme {
+        Theme {
+            header: Style::default().add_modifier(Modifier::BOLD),
+            header_row: Style::default().add_modifier(Modifier::UNDERLINED),
+            gauge: Style::default(),
+            help: Style::default(),
+            status: Style::default().add_modifier(Modifier::ITALIC),
+            highlight: Style::default().add_modifier(Modifier::REVERSED),
+            marked: Style::default().add_modifier(Modifier::UNDERLINED),
+            category_colors: false,
+        }
+    }
+
+    fn builtin(name: &str) -> Option<Theme> {
+        match name {
+            "dark" => Some(Theme::dark()),
+            "light" => Some(Theme::light()),
+            "monochrome" | "none" => Some(Theme::monochrome()),
+            _ => None,
+        }
+    }
+
+    fn from_file(file: &ThemeFile, warnings: &mut Vec<String>) -> Theme {
+        let mut theme = match &file.base {
+            Some(base) => Theme::builtin(base).unwrap_or_else(|| {
+                warnings.push(format!("Unknown base theme '{}'", base));
+                Theme::dark()
+            }),
+            None => Theme::dark(),
+        };
+        let mut color = |value: &Option<String>| -> Option<Color> {
+            let value = value.as_ref()?;
+            let color = parse_color(value);
+            if color.is_none() {
+                warnings.push(format!("Unknown colour '{}'", value));
+            }
+            color
+        };
+        if let Some(c) = color(&file.header_fg) { theme.header = theme.header.fg(c); }
+        if let Some(c) = color(&file.header_bg) { theme.header_row = theme.header_row.bg(c); }
+        if let Some(c) = color(&file.gauge_fg) { theme.gauge = theme.gauge.fg(c); }
+        if let Some(c) = color(&file.gauge_bg) { theme.gauge = theme.gauge.bg(c); }
+        if let Some(c) = color(&file.help_fg) { theme.help = theme.help.fg(c); }
+        if let Some(c) = color(&file.help_bg) { theme.help = theme.help.bg(c); }
+        if let Some(c) = color(&file.status_fg) { theme.status = theme.status.fg(c); }
+        if let Some(c) = color(&file.highlight_fg) { theme.highlight = theme.highlight.fg(c); }
+        if let Some(c) = color(&file.highlight_bg) { theme.highlight = theme.highlight.bg(c); }
+        if let Some(c) = color(&file.marked_bg) { theme.marked = theme.marked.bg(c); }
+        theme
+    }
+
+    /// Loads a builtin theme or a theme file from the storage directory.
+    /// A set `NO_COLOR` environment variable always wins.
+    pub fn load(name: &str) -> (Theme, Vec<String>) {
+        if env::var("NO_COLOR").map(|value| !value.is_empty()).unwrap_or(false) {
+            return (Theme::monochrome(), Vec::new());
+        }
+        if let Some(theme) = Theme::builtin(name) {
+            return (theme, Vec::new());
+        }
+        let path = get_full_path(name);
+        let mut warnings = Vec::new();
+        let theme = match read_to_string(path.as_path()) {
+            Ok(data) => match serde_json::from_str::<ThemeFile>(&data) {
+                Ok(file) => Theme::from_file(&file, &mut warnings),
+                Err(error) => {
+                    warnings.push(format!("Could not parse {}: {}", path.display(), error));
+                    Theme::dark()
+                }
+            },
+            Err(_) => {
+                warnings.push(format!("Theme '{}' is neither builtin nor a file in the storage directory", name));
+                Theme::dark()
+            }
+        };
+        (theme, warnings)
+    }
+
+    pub fn category_style(&self, category: &Category) -> Style {
+        match &category.color {
+            Some(color) if self.category_colors => match parse_color(color) {
+                Some(color) => Style::default().fg(color),
+                None => Style::default(),
+            },
+            _ => Style::default(),
+        }
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme::dark()
+    }
+}
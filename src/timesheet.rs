@@ -0,0 +1,229 @@
+use std::collections::BTreeMap;
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, TimeZone, Utc};
+use crate::command::format_duration;
+use crate::export::csv_field;
+use crate::config::{Rounding, RoundingMode};
+use crate::model::{Database, Segment, get_category_by_id};
+
+/// Time worked on one project on one day.
+pub struct Entry {
+    pub date: NaiveDate,
+    pub client: String,
+    /// The category name if the billing does not name a project.
+    pub project: String,
+    /// Per hour, as billed for the first category of the project.
+    pub rate: Option<f64>,
+    pub currency: String,
+    pub worked: Duration,
+    /// The worked time after rounding.
+    pub billed: Duration,
+}
+
+impl Entry {
+    pub fn billed_hours(&self) -> f64 {
+        self.billed.num_seconds() as f64 / 3600.0
+    }
+
+    pub fn amount(&self) -> Option<f64> {
+        self.rate.map(|rate| rate * self.billed_hours())
+    }
+}
+
+/// The first day of a month given as `2023-06`, and the first day of the next one.
+pub fn parse_month(month: &str) -> Option<(NaiveDate, NaiveDate)> {
+    let start = NaiveDate::parse_from_str(&format!("{}-01", month), "%Y-%m-%d").ok()?;
+    let end = match start.month() {
+        12 => NaiveDate::from_ymd_opt(start.year() + 1, 1, 1)?,
+        month => NaiveDate::from_ymd_opt(start.year(), month + 1, 1)?,
+    };
+    Some((start, end))
+}
+
+/// The start of a day in local time.
+pub fn day_start(date: NaiveDate) -> DateTime<Utc> {
+    let midnight = date.and_hms_opt(0, 0, 0).expect("Midnight is a valid time");
+    Local.from_local_datetime(&midnight).earliest().unwrap_or_else(Local::now).with_timezone(&Utc)
+}
+
+fn round(worked: Duration, rounding: Rounding) -> Duration {
+    if rounding.minutes == 0 {
+        return worked;
+    }
+    let step = i64::from(rounding.minutes) * 60;
+    let seconds = worked.num_seconds();
+    let steps = match rounding.mode {
+        RoundingMode::Up => (seconds + step - 1) / step,
+        RoundingMode::Nearest => (seconds + step / 2) / step,
+        RoundingMode::Down => seconds / step,
+    };
+    Duration::seconds(steps * step)
+}
+
+/// Groups the segments between `start` and `end` by local day, client and project,
+/// optionally only those billed to `client`. Categories billed to the same project
+/// are rounded together. Segments over midnight count on both days.
+pub fn entries(data: &Database, segments: &[Segment], start: NaiveDate, end: NaiveDate, client: Option<&str>, rounding: Rounding) -> Vec<Entry> {
+    let mut entries: BTreeMap<(NaiveDate, String, String), Entry> = BTreeMap::new();
+    let mut day = start;
+    while day < end {
+        let next = day.succ_opt().expect("Dates end far in the future");
+        let (from, to) = (day_start(day), day_start(next));
+        for segment in segments.iter().filter(|segment| segment.start_time < to && segment.end_time > from) {
+            let category = match get_category_by_id(data.categories(), segment.category_id) {
+                Some(category) => category,
+                None => continue,
+            };
+            let billing = category.billing.as_ref();
+            let billed_to = billing.map_or("", |billing| billing.client.as_str());
+            if client.is_some_and(|client| client != billed_to) {
+                continue;
+            }
+            let project = billing.and_then(|billing| billing.project.clone()).unwrap_or_else(|| category.name.clone());
+            let entry = entries.entry((day, billed_to.to_string(), project.clone())).or_insert_with(|| Entry {
+                date: day,
+                client: billed_to.to_string(),
+                project,
+                rate: billing.and_then(|billing| billing.rate),
+                currency: billing.and_then(|billing| billing.currency.clone()).unwrap_or_default(),
+                worked: Duration::zero(),
+                billed: Duration::zero(),
+            });
+            entry.worked += segment.end_time.min(to) - segment.start_time.max(from);
+        }
+        day = next;
+    }
+    entries.into_values()
+        .filter(|entry| entry.worked > Duration::zero())
+        .map(|entry| Entry { billed: round(entry.worked, rounding), ..entry })
+        .collect()
+}
+
+struct ProjectTotal {
+    client: String,
+    project: String,
+    rate: Option<f64>,
+    currency: String,
+    hours: f64,
+}
+
+/// One line per day and project.
+pub fn csv(entries: &[Entry]) -> String {
+    let mut csv = "date,client,project,worked_hours,billed_hours,rate,amount,currency\n".to_string();
+    for entry in entries {
+        let fields = [
+            entry.date.format("%Y-%m-%d").to_string(),
+            entry.client.clone(),
+            entry.project.clone(),
+            format!("{:.2}", entry.worked.num_seconds() as f64 / 3600.0),
+            format!("{:.2}", entry.billed_hours()),
+            entry.rate.map(|rate| format!("{:.2}", rate)).unwrap_or_default(),
+            entry.amount().map(|amount| format!("{:.2}", amount)).unwrap_or_default(),
+            entry.currency.clone(),
+        ];
+        csv.push_str(&fields.iter().map(|field| csv_field(field)).collect::<Vec<String>>().join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+/// The days worked, followed by the hours and amounts per project for an invoice.
+pub fn text(entries: &[Entry], title: &str) -> String {
+    let mut text = format!("{}\n\n{:<12}{:<24}{:>8}{:>8}\n", title, "Date", "Project", "Worked", "Billed");
+    for entry in entries {
+        text.push_str(&format!("{:<12}{:<24}{:>8}{:>8}\n", entry.date.format("%Y-%m-%d"), entry.project, format_duration(entry.worked), format_duration(entry.billed)));
+    }
+
+    // Projects in order of their first appearance
+    let mut projects: Vec<ProjectTotal> = Vec::new();
+    for entry in entries {
+        match projects.iter_mut().find(|total| total.client == entry.client && total.project == entry.project) {
+            Some(total) => total.hours += entry.billed_hours(),
+            None => projects.push(ProjectTotal {
+                client: entry.client.clone(),
+                project: entry.project.clone(),
+                rate: entry.rate,
+                currency: entry.currency.clone(),
+                hours: entry.billed_hours(),
+            }),
+        }
+    }
+    text.push_str(&format!("\n{:<24}{:<16}{:>8}{:>10}{:>12}\n", "Project", "Client", "Hours", "Rate", "Amount"));
+    // Hours and amount per currency
+    let mut totals: Vec<(String, f64, f64)> = Vec::new();
+    for ProjectTotal { client, project, rate, currency, hours } in projects.iter() {
+        let amount = rate.map(|rate| rate * hours);
+        text.push_str(&format!("{:<24}{:<16}{:>8.2}{:>10}{:>12} {}\n",
+            project,
+            client,
+            hours,
+            rate.map(|rate| format!("{:.2}", rate)).unwrap_or_default(),
+            amount.map(|amount| format!("{:.2}", amount)).unwrap_or_default(),
+            currency));
+        match totals.iter_mut().find(|(other, ..)| other == currency) {
+            Some(total) => {
+                total.1 += hours;
+                total.2 += amount.unwrap_or(0.0);
+            }
+            None => totals.push((currency.clone(), *hours, amount.unwrap_or(0.0))),
+        }
+    }
+    for (currency, hours, amount) in totals {
+        text.push_str(&format!("{:<40}{:>8.2}{:>10}{:>12.2} {}\n", "Total", hours, "", amount, currency));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::Billing;
+    use super::*;
+
+    fn june(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2023, 6, day).unwrap()
+    }
+
+    /// `minutes` worked from noon of the day on a category.
+    fn segment(category_id: u32, day: u32, minutes: i64) -> Segment {
+        let start_time = day_start(june(day)) + Duration::hours(12);
+        Segment { task_id: 1, category_id, start_time, end_time: start_time + Duration::minutes(minutes) }
+    }
+
+    fn rounded(minutes: u32, mode: RoundingMode, worked: Duration) -> Duration {
+        round(worked, Rounding { minutes, mode })
+    }
+
+    #[test]
+    fn rounding_modes() {
+        let worked = Duration::minutes(20);
+        assert_eq!(rounded(15, RoundingMode::Up, worked), Duration::minutes(30));
+        assert_eq!(rounded(15, RoundingMode::Nearest, worked), Duration::minutes(15));
+        assert_eq!(rounded(15, RoundingMode::Down, worked), Duration::minutes(15));
+        assert_eq!(rounded(15, RoundingMode::Up, Duration::minutes(30)), Duration::minutes(30));
+        assert_eq!(rounded(0, RoundingMode::Up, Duration::seconds(1234)), Duration::seconds(1234));
+    }
+
+    #[test]
+    fn nearest_rounds_half_a_step_up() {
+        let half = Duration::minutes(7) + Duration::seconds(30);
+        assert_eq!(rounded(15, RoundingMode::Nearest, half), Duration::minutes(15));
+        assert_eq!(rounded(15, RoundingMode::Nearest, half - Duration::seconds(1)), Duration::zero());
+    }
+
+    #[test]
+    fn categories_of_one_project_are_rounded_together() {
+        let mut data = Database::example_db();
+        let billing = Billing { client: "ACME".to_string(), project: Some("Report".to_string()), rate: Some(90.0), currency: Some("EUR".to_string()) };
+        let writing = data.add_category("writing".to_string(), None).unwrap();
+        let research = data.add_category("research".to_string(), None).unwrap();
+        data.set_category_billing(writing, Some(billing.clone()));
+        data.set_category_billing(research, Some(billing));
+        let segments = [segment(writing, 1, 10), segment(research, 1, 10)];
+        let rounding = Rounding { minutes: 15, mode: RoundingMode::Nearest };
+
+        let entries = entries(&data, &segments, june(1), june(2), Some("ACME"), rounding);
+        assert_eq!(entries.len(), 1);
+        assert_eq!((entries[0].client.as_str(), entries[0].project.as_str()), ("ACME", "Report"));
+        assert_eq!(entries[0].worked, Duration::minutes(20));
+        assert_eq!(entries[0].billed, Duration::minutes(15));
+    }
+}
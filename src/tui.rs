@@ -1,9 +1,12 @@
 use crate::app::{App, AppResult};
 use crate::event::EventHandler;
+use crate::recovery::Recovery;
 use crate::ui;
-use crossterm::event::{DisableMouseCapture, EnableMouseCapture};
+use crossterm::event::{DisableBracketedPaste, DisableMouseCapture, EnableBracketedPaste, EnableMouseCapture};
+use crossterm::cursor::Show;
 use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
 use std::io;
+use std::panic;
 use ratatui::backend::Backend;
 use ratatui::Terminal;
 
@@ -30,7 +33,7 @@ impl<B: Backend> Tui<B> {
     /// It enables the raw mode and sets terminal properties.
     pub fn init(&mut self) -> AppResult<()> {
         terminal::enable_raw_mode()?;
-        crossterm::execute!(io::stderr(), EnterAlternateScreen, EnableMouseCapture)?;
+        crossterm::execute!(io::stderr(), EnterAlternateScreen, EnableMouseCapture, EnableBracketedPaste)?;
         self.terminal.hide_cursor()?;
         self.terminal.clear()?;
         Ok(())
@@ -45,13 +48,35 @@ impl<B: Backend> Tui<B> {
         Ok(())
     }
 
+    /// Draws the recovery screen, which is shown instead of the app if the database cannot be loaded.
+    pub fn draw_recovery(&mut self, recovery: &Recovery) -> AppResult<()> {
+        self.terminal.draw(|frame| recovery.render(frame))?;
+        Ok(())
+    }
+
     /// Exits the terminal interface.
     ///
     /// It disables the raw mode and reverts back the terminal properties.
     pub fn exit(&mut self) -> AppResult<()> {
-        terminal::disable_raw_mode()?;
-        crossterm::execute!(io::stderr(), LeaveAlternateScreen, DisableMouseCapture)?;
+        reset_terminal()?;
         self.terminal.show_cursor()?;
         Ok(())
     }
 }
+
+/// Reverts the terminal properties set by [`Tui::init`].
+fn reset_terminal() -> AppResult<()> {
+    terminal::disable_raw_mode()?;
+    crossterm::execute!(io::stderr(), LeaveAlternateScreen, DisableMouseCapture, DisableBracketedPaste, Show)?;
+    Ok(())
+}
+
+/// Restores the terminal before a panic message is printed, otherwise it would
+/// end up garbled on the alternate screen and the shell stays in raw mode.
+pub fn install_panic_hook() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let _ = reset_terminal();
+        default_hook(info);
+    }));
+}
@@ -1,49 +1,71 @@
 use ratatui::{
     backend::Backend,
-    style::{Color, Style, Modifier},
-    widgets::{Block, Borders, Cell, Row, Table, Wrap, Paragraph, Gauge},
+    style::{Style, Modifier},
+    widgets::{Block, Borders, Cell, Clear, Row, Table, Wrap, Paragraph, Gauge},
     layout::{Layout, Constraint, Rect, Direction},
     Frame,
 };
 
-use crate::app::App;
+use crate::app::{App, Interruption};
+use chrono::{DateTime, Local, Utc};
+use crate::keymap::{Action, Keymap, Mode};
+use crate::config::Column;
 
 fn render_todo_table<B: Backend>(app: &mut App, frame: &mut Frame<'_, B>, rect: &Rect) {
     let tasklist = app.data.tasks_printeable();
-    let header_cells = ["", "Time", "Cat", "Task"]
+    let marked = app.marked();
+    let columns = &app.config.columns;
+    let theme = &app.theme;
+    let header_cells = columns
         .iter()
-        .map(|h| Cell::from(*h).style(Style::default().fg(Color::Red)));
+        .map(|c| Cell::from(c.column.title()).style(theme.header));
     let header = Row::new(header_cells)
-        .style(Style::default().bg(Color::Blue))
+        .style(theme.header_row)
         .height(1)
         .bottom_margin(1);
     let mut rows = tasklist.iter().map(|item| {
-        Row::new(vec![
-            Cell::from(item.get_checkbox_string()),
-            Cell::from(item.get_time_spent_string()),
-            Cell::from(item.get_category_string()),
-            Cell::from(item.get_description_string()),
-        ])
+        let style = match marked.contains(&item.id) {
+            true => theme.marked,
+            false => Style::default(),
+        };
+        Row::new(columns.iter().map(|c| match c.column {
+            Column::Checkbox => Cell::from(item.get_checkbox_string()),
+            Column::Time => Cell::from(item.get_time_spent_string()),
+            Column::Category => Cell::from(item.get_category_string()).style(theme.category_style(&item.category)),
+            Column::Task => Cell::from(item.get_description_string()),
+            Column::Added => Cell::from(item.get_added_string()),
+            Column::Id => Cell::from(item.get_id_string()),
+            Column::Tags => Cell::from(item.get_tags_string()),
+            Column::Priority => Cell::from(item.get_priority_string()),
+            Column::Estimate => Cell::from(item.get_estimate_string()),
+            Column::Due => Cell::from(item.get_due_string()),
+        }).collect::<Vec<Cell>>()).style(style)
     }).collect::<Vec<Row>>();
     if rows.len() == 0 {
-        rows.push(Row::new(vec![
-            Cell::from(""),
-            Cell::from(""),
-            Cell::from(""),
-            Cell::from("No tasks yet, press Enter and type one!"),
-        ]));
+        // Show the hint in the task column, or the first one if that is hidden
+        let hint_column = columns.iter().position(|c| c.column == Column::Task).unwrap_or(0);
+        rows.push(Row::new(columns.iter().enumerate().map(|(index, _)| match index == hint_column {
+            true => Cell::from("No tasks yet, press Enter and type one!"),
+            false => Cell::from(""),
+        }).collect::<Vec<Cell>>()));
     }
+    let widths = columns.iter().map(|c| match c.width {
+        Some(width) => Constraint::Length(width),
+        None => Constraint::Percentage(100),
+    }).collect::<Vec<Constraint>>();
 
+    let mut title = match marked.len() {
+        0 => "Todos".to_string(),
+        n => format!("Todos ({} marked)", n),
+    };
+    if app.storage.read_only() {
+        title.push_str(" [read-only]");
+    }
     let t = Table::new(rows)
         .header(header)
-        .block(Block::default().borders(Borders::ALL).title("Todos"))
-        .highlight_style(Style::default().add_modifier(Modifier::BOLD))
-        .widths(&[
-            Constraint::Length(3),
-            Constraint::Length(8),
-            Constraint::Length(10),
-            Constraint::Percentage(100),
-        ]);
+        .block(Block::default().borders(Borders::ALL).title(title))
+        .highlight_style(theme.highlight)
+        .widths(&widths);
     let selected_task_index = match app.selected_task {
         Some(selected) => tasklist.iter().position(|task| task.id == selected),
         None => None,
@@ -59,22 +81,25 @@ fn render_category_table<B: Backend>(app: &mut App, frame: &mut Frame<'_, B>, re
     let categorylist = app.data.categories_printeable();
     let header_cells = ["Show", "Hotkey", "Name"]
         .iter()
-        .map(|h| Cell::from(*h).style(Style::default().fg(Color::Red)));
+        .map(|h| Cell::from(*h).style(app.theme.header));
     let header = Row::new(header_cells)
-        .style(Style::default().bg(Color::Blue))
+        .style(app.theme.header_row)
         .height(1)
         .bottom_margin(1);
     let rows = categorylist.iter().map(|item| {
         Row::new(vec![
             Cell::from(item.get_visible_string()),
             Cell::from(item.get_hotkey_string()),
-            Cell::from(item.get_description_string()),
+            Cell::from(item.get_description_string()).style(app.theme.category_style(&item.category)),
         ])
     }).collect::<Vec<Row>>();
     let t = Table::new(rows)
         .header(header)
-        .block(Block::default().borders(Borders::ALL).title("Categories"))
-        .highlight_style(Style::default().add_modifier(Modifier::BOLD))
+        .block(Block::default().borders(Borders::ALL).title(match app.storage.read_only() {
+            true => "Categories [read-only]",
+            false => "Categories",
+        }))
+        .highlight_style(app.theme.highlight)
         .widths(&[
             Constraint::Length(4),
             Constraint::Length(6),
@@ -101,10 +126,15 @@ fn render_input<B: Backend>(app: &mut App, frame: &mut Frame<'_, B>, rect: &Rect
     frame.render_widget(app.textarea.widget(), *rect);
 }
 
-fn render_help_text<B: Backend>(frame: &mut Frame<'_, B>, rect: &Rect) {
-    let p = Paragraph::new("Ctrl+c to quit, Up/Down to select a task, PgUp/Down to move a task, Esc to switch to view mode, c to switch to category view, x to check task, ctrl+<hotkey> to toggle category visibility, d to set default category, n to test notifications")
+fn render_help_text<B: Backend>(app: &mut App, frame: &mut Frame<'_, B>, rect: &Rect) {
+    let text = app.keymap.actions_for(Mode::Category)
+        .iter()
+        .map(|(action, chords)| format!("{}: {}", chords.join("/"), action.description()))
+        .collect::<Vec<String>>()
+        .join(", ");
+    let p = Paragraph::new(format!("{}, ctrl+<hotkey>: Toggle visibility of a category", text))
         .block(Block::default().title("Help").borders(Borders::ALL))
-        .style(Style::default().fg(Color::White).bg(Color::Black))
+        .style(app.theme.help)
         .wrap(Wrap { trim: false });
     frame.render_widget(p, *rect);
 }
@@ -113,13 +143,13 @@ fn render_progress_gauge<B: Backend>(app: &mut App, frame: &mut Frame<'_, B>, re
     let gauge = match app.data.get_remaining_pomodoro_time() {
         Some((duration_left, fraction_completed)) => {
             Gauge::default()
-                .gauge_style(Style::default().fg(Color::Green).bg(Color::Black))
+                .gauge_style(app.theme.gauge)
                 .label(format!("{:02}:{:02} left", duration_left.num_minutes(), duration_left.num_seconds() % 60))
                 .ratio(fraction_completed)
         },
         None => {
             Gauge::default()
-                .gauge_style(Style::default().fg(Color::Green).bg(Color::Black))
+                .gauge_style(app.theme.gauge)
                 .label("No pomodoro active, start one with 'p'")
                 .percent(0)
         }
@@ -129,20 +159,54 @@ fn render_progress_gauge<B: Backend>(app: &mut App, frame: &mut Frame<'_, B>, re
 }
 
 
+fn render_status_line<B: Backend>(app: &mut App, frame: &mut Frame<'_, B>, rect: &Rect) {
+    match &app.command_line {
+        Some(input) => {
+            let p = Paragraph::new(format!(":{}", input));
+            frame.render_widget(p, *rect);
+            frame.set_cursor(rect.x + 1 + input.chars().count() as u16, rect.y);
+        }
+        None => {
+            let message = app.status_message.clone().unwrap_or_default();
+            let p = Paragraph::new(message)
+                .style(app.theme.status);
+            frame.render_widget(p, *rect);
+        }
+    }
+}
+
 fn render_todo_view<B: Backend>(app: &mut App, frame: &mut Frame<'_, B>) {
+    let status_height = match app.status_message.is_some() || app.command_line.is_some() {
+        true => 1,
+        false => 0,
+    };
+    let preview = match app.mode() {
+        Mode::TextInput => app.quickadd_preview(),
+        _ => None,
+    };
+    let preview_height = match preview {
+        Some(_) => 1,
+        None => 0,
+    };
     let rects = Layout::default()
         .direction(Direction::Vertical)
         .constraints([
             Constraint::Length(1),
             Constraint::Min(5),
             Constraint::Length(3),
+            Constraint::Length(preview_height),
+            Constraint::Length(status_height),
             ].as_ref())
         .margin(1)
         .split(frame.size());
     
     render_progress_gauge(app, frame, &rects[0]);
     render_todo_table(app, frame, &rects[1]);
-    render_input(app, frame, &rects[2])
+    render_input(app, frame, &rects[2]);
+    if let Some(preview) = preview {
+        frame.render_widget(Paragraph::new(format!("-> {}", preview)), rects[3]);
+    }
+    render_status_line(app, frame, &rects[4]);
 }
 
 fn render_category_view<B: Backend>(app: &mut App, frame: &mut Frame<'_, B>) {
@@ -156,7 +220,124 @@ fn render_category_view<B: Backend>(app: &mut App, frame: &mut Frame<'_, B>) {
         .split(frame.size());
     
     render_category_table(app, frame, &rects[0]);
-    render_help_text(frame, &rects[1]);
+    render_help_text(app, frame, &rects[1]);
+}
+
+/// A rectangle of the given percentage of the area, centered in it.
+fn centered_rect(percent_x: u16, percent_y: u16, area: Rect) -> Rect {
+    let vertical = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Percentage((100 - percent_y) / 2),
+            Constraint::Percentage(percent_y),
+            Constraint::Percentage((100 - percent_y) / 2),
+            ].as_ref())
+        .split(area);
+    Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([
+            Constraint::Percentage((100 - percent_x) / 2),
+            Constraint::Percentage(percent_x),
+            Constraint::Percentage((100 - percent_x) / 2),
+            ].as_ref())
+        .split(vertical[1])[1]
+}
+
+fn render_popup<B: Backend>(title: &str, lines: &[String], frame: &mut Frame<'_, B>) {
+    let p = Paragraph::new(lines.join("\n"))
+        .block(Block::default().borders(Borders::ALL).title(format!("{} (any key to close)", title)));
+    let area = centered_rect(70, 70, frame.size());
+    frame.render_widget(Clear, area);
+    frame.render_widget(p, area);
+}
+
+/// The keys bound to an answer of a prompt, e.g. `m` for keeping my side of a conflict.
+fn prompt_keys(keymap: &Keymap, mode: Mode, action: Action) -> String {
+    keymap.actions_for(mode).into_iter().find(|(bound, _)| *bound == action).map(|(_, chords)| chords.join("/")).unwrap_or_default()
+}
+
+/// Asks which side to keep when the database was changed outside of pti while there were unsaved changes.
+fn render_conflict<B: Backend>(lines: &[String], keymap: &Keymap, frame: &mut Frame<'_, B>) {
+    let mut text = vec![
+        "The database was changed outside of pti while there were unsaved changes here.".to_string(),
+        "All other changes are kept either way, these were made on both sides:".to_string(),
+        String::new(),
+    ];
+    text.extend(lines.iter().cloned());
+    let p = Paragraph::new(text.join("\n"))
+        .block(Block::default().borders(Borders::ALL).title(format!("Conflict ({}: keep mine, {}: take theirs, quit: decide later)",
+            prompt_keys(keymap, Mode::Conflict, Action::KeepMine),
+            prompt_keys(keymap, Mode::Conflict, Action::TakeTheirs))))
+        .wrap(Wrap { trim: false });
+    let area = centered_rect(80, 70, frame.size());
+    frame.render_widget(Clear, area);
+    frame.render_widget(p, area);
+}
+
+/// Asks what to do with a pomodoro which ran while pti was closed or the computer was suspended.
+fn render_interruption<B: Backend>(interruption: &Interruption, end: Option<DateTime<Utc>>, keymap: &Keymap, frame: &mut Frame<'_, B>) {
+    let time = |time: DateTime<Utc>| time.with_timezone(&Local).format("%H:%M").to_string();
+    let mut text = vec![match interruption.closed {
+        true => format!("pti was closed at {} while a pomodoro was running on:", time(interruption.since)),
+        false => format!("The computer was asleep from {} to {} while a pomodoro was running on:", time(interruption.since), time(interruption.until)),
+    }];
+    text.extend(interruption.tasks.iter().map(|task| format!("  {}", task)));
+    text.push(String::new());
+    if let Some(end) = end.filter(|end| *end <= interruption.until) {
+        text.push(format!("The pomodoro ended at {}.", time(end)));
+    }
+    text.push(format!("{}: keep the time, {}: only count it until {}, {}: discard the pomodoro, quit: decide later",
+        prompt_keys(keymap, Mode::Interruption, Action::KeepInterruptedTime),
+        prompt_keys(keymap, Mode::Interruption, Action::TruncateInterruptedTime),
+        time(interruption.since),
+        prompt_keys(keymap, Mode::Interruption, Action::DiscardInterruptedPomodoro)));
+    let p = Paragraph::new(text.join("\n"))
+        .block(Block::default().borders(Borders::ALL).title("Pomodoro interrupted"))
+        .wrap(Wrap { trim: false });
+    let area = centered_rect(70, 50, frame.size());
+    frame.render_widget(Clear, area);
+    frame.render_widget(p, area);
+}
+
+/// Lists the bindings of the current mode, straight from the keymap the handler dispatches on.
+fn render_help_overlay<B: Backend>(app: &mut App, frame: &mut Frame<'_, B>) {
+    let mode = app.mode();
+    let mut entries = app.keymap.actions_for(mode)
+        .into_iter()
+        .map(|(action, chords)| (chords.join(", "), action.description().to_string()))
+        .collect::<Vec<(String, String)>>();
+    // Keys which are not part of the keymap
+    for category in app.data.categories().iter() {
+        if let Some(hotkey) = category.hotkey {
+            if mode == Mode::Todo {
+                entries.push((hotkey.to_string(), format!("Assign category {}", category.name)));
+            }
+            entries.push((format!("ctrl+{}", hotkey), format!("Toggle visibility of category {}", category.name)));
+        }
+    }
+    if mode == Mode::TextInput {
+        entries.push(("other keys".to_string(), "Type the task".to_string()));
+    }
+
+    let title = match mode {
+        Mode::Global | Mode::Todo => "Help: todo list",
+        Mode::TextInput => "Help: typing a task",
+        Mode::Category => "Help: categories",
+        Mode::Conflict => "Help: conflict",
+        Mode::Interruption => "Help: interrupted pomodoro",
+    };
+    let rows = entries.into_iter().map(|(keys, description)| {
+        Row::new(vec![Cell::from(keys), Cell::from(description)])
+    }).collect::<Vec<Row>>();
+    let t = Table::new(rows)
+        .block(Block::default().borders(Borders::ALL).title(format!("{} (any key to close)", title)))
+        .widths(&[
+            Constraint::Length(24),
+            Constraint::Percentage(100),
+        ]);
+    let area = centered_rect(70, 70, frame.size());
+    frame.render_widget(Clear, area);
+    frame.render_widget(t, area);
 }
 
 pub fn render<B: Backend>(app: &mut App, frame: &mut Frame<'_, B>) {
@@ -164,4 +345,16 @@ pub fn render<B: Backend>(app: &mut App, frame: &mut Frame<'_, B>) {
         Some(_) => render_category_view(app, frame),
         None => render_todo_view(app, frame),
     }
+    if app.show_help {
+        render_help_overlay(app, frame);
+    }
+    if let Some((title, lines)) = &app.popup {
+        render_popup(title, lines, frame);
+    }
+    if let Some(conflict) = &app.conflict {
+        render_conflict(&conflict.lines, &app.keymap, frame);
+    }
+    if let Some(interruption) = &app.interruption {
+        render_interruption(interruption, app.data.pomodoro_end_time(), &app.keymap, frame);
+    }
 }
@@ -0,0 +1,55 @@
+use std::path::Path;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+
+/// How often the database is checked for changes where file system events are not available.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Tells when the database file might have been changed by someone else.
+///
+/// Uses file system events if possible and falls back to checking every second.
+/// It is only a hint, whether the file really changed is decided by its modification time.
+pub struct FileWatcher {
+    /// Kept alive for as long as events should arrive, `None` when polling.
+    watcher: Option<RecommendedWatcher>,
+    receiver: mpsc::Receiver<()>,
+    last_poll: Instant,
+}
+
+impl FileWatcher {
+    pub fn new(path: &Path) -> FileWatcher {
+        let (sender, receiver) = mpsc::channel();
+        let watcher = Self::watch(path, sender).ok();
+        FileWatcher { watcher, receiver, last_poll: Instant::now() }
+    }
+
+    /// The directory is watched instead of the file, as saves replace the file with a new one.
+    fn watch(path: &Path, sender: mpsc::Sender<()>) -> notify::Result<RecommendedWatcher> {
+        let file_name = path.file_name().map(|name| name.to_os_string());
+        let mut watcher = notify::recommended_watcher(move |result: notify::Result<notify::Event>| {
+            if let Ok(event) = result {
+                if event.paths.iter().any(|path| path.file_name().map(|name| name.to_os_string()) == file_name) {
+                    let _ = sender.send(());
+                }
+            }
+        })?;
+        let directory = path.parent().unwrap_or(Path::new("."));
+        watcher.watch(directory, RecursiveMode::NonRecursive)?;
+        Ok(watcher)
+    }
+
+    /// Whether the file should be checked for changes.
+    pub fn changed(&mut self) -> bool {
+        match self.watcher {
+            Some(_) => self.receiver.try_iter().count() > 0,
+            None => {
+                if self.last_poll.elapsed() < POLL_INTERVAL {
+                    return false;
+                }
+                self.last_poll = Instant::now();
+                true
+            }
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use serde_json::Value;
+use pti::backup;
+use pti::migration::{self, SCHEMA_VERSION};
+use pti::model::Database;
+use pti::storage::{Storage, StorageKind};
+use pti::storage::json::JsonStorage;
+
+/// `tests/fixtures/v<N>.json` holds a database as written by schema version N.
+fn fixture(version: u32) -> Value {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(format!("v{}.json", version));
+    let data = fs::read_to_string(&path).unwrap_or_else(|_| panic!("Missing fixture {}, add one when bumping the schema version", path.display()));
+    serde_json::from_str(&data).expect("Fixture is not valid json")
+}
+
+/// The storage directory is process-wide, so the tests using it take turns.
+static STORAGE_DIR: Mutex<()> = Mutex::new(());
+
+#[test]
+fn every_version_loads() {
+    for version in 0..=SCHEMA_VERSION {
+        let mut value = fixture(version);
+        assert_eq!(migration::upgrade(&mut value), Ok(version));
+        assert_eq!(value["schema_version"], SCHEMA_VERSION);
+        let database: Database = serde_json::from_value(value).unwrap_or_else(|error| panic!("Version {} does not load: {}", version, error));
+        let descriptions = database.tasks_printeable().iter().map(|task| task.description.clone()).collect::<Vec<String>>();
+        assert_eq!(descriptions, vec!["Write the report", "Collect the numbers"]);
+    }
+}
+
+#[test]
+fn upgrade_from_v0_fills_new_fields() {
+    let mut value = fixture(0);
+    migration::upgrade(&mut value).unwrap();
+    for task in value["tasks"].as_array().unwrap() {
+        assert_eq!(task["tags"], Value::Array(Vec::new()));
+        assert_eq!(task["priority"], Value::Null);
+        assert_eq!(task["estimate"], Value::Null);
+        assert_eq!(task["due"], Value::Null);
+    }
+    for category in value["categories"].as_array().unwrap() {
+        assert_eq!(category["color"], Value::Null);
+        assert_eq!(category["billing"], Value::Null);
+    }
+    assert_eq!(value["last_seen"], Value::Null);
+    assert_eq!(value["tasks"][1]["parent"], value["tasks"][0]["uid"]);
+    assert_eq!(value["next_task_id"], 2);
+    assert_eq!(value["tasks"][0]["past_pomodoros"][0]["end_time"], "2023-06-01T09:25:00Z");
+}
+
+#[test]
+fn upgrades_agree_on_uids() {
+    // Copies of one database upgraded on different machines have to merge
+    let mut old = fixture(0);
+    let mut newer = fixture(3);
+    migration::upgrade(&mut old).unwrap();
+    migration::upgrade(&mut newer).unwrap();
+    for (task, other) in old["tasks"].as_array().unwrap().iter().zip(newer["tasks"].as_array().unwrap()) {
+        assert_eq!(task["uid"], other["uid"]);
+    }
+}
+
+#[test]
+fn current_version_is_untouched() {
+    let mut value = fixture(SCHEMA_VERSION);
+    let original = value.clone();
+    migration::upgrade(&mut value).unwrap();
+    assert_eq!(value, original);
+}
+
+#[test]
+fn newer_version_is_refused() {
+    let mut value = fixture(SCHEMA_VERSION);
+    value["schema_version"] = Value::from(SCHEMA_VERSION + 1);
+    assert!(migration::upgrade(&mut value).is_err());
+}
+
+#[test]
+fn json_storage_upgrades_and_backs_up() {
+    let _lock = STORAGE_DIR.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let dir = std::env::temp_dir().join(format!("pti-migration-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    std::env::set_var("PTI_STORAGE_DIR", &dir);
+    let path = dir.join("database.json");
+    fs::write(&path, fixture(0).to_string()).unwrap();
+
+    let mut storage = JsonStorage::open(&path, &dir.join("database.lock")).unwrap();
+    let database = storage.load().unwrap().expect("Database is empty");
+    let written: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(written, serde_json::to_value(&database).unwrap());
+    assert_eq!(written["schema_version"], SCHEMA_VERSION);
+
+    let backups = fs::read_dir(dir.join("backups")).unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .collect::<Vec<String>>();
+    assert_eq!(backups.len(), 1);
+    assert!(backups[0].starts_with("before-migration-v0-"));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn read_only_storage_upgrades_without_a_backup() {
+    let _lock = STORAGE_DIR.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let dir = std::env::temp_dir().join(format!("pti-migration-read-only-test-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    std::env::set_var("PTI_STORAGE_DIR", &dir);
+    let path = dir.join("database.json");
+    fs::write(&path, fixture(0).to_string()).unwrap();
+
+    // The instance owning the database migrates it and keeps the original
+    let mut storage = JsonStorage::open_read_only(&path);
+    assert!(storage.load().unwrap().is_some());
+    assert!(!dir.join("backups").exists());
+    assert_eq!(fs::read_to_string(&path).unwrap(), fixture(0).to_string());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn backups_of_older_versions_are_upgraded_on_restore() {
+    let _lock = STORAGE_DIR.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let dir = std::env::temp_dir().join(format!("pti-migration-backup-test-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("backups")).unwrap();
+    std::env::set_var("PTI_STORAGE_DIR", &dir);
+    let name = "recent-2023-06-01T09-30-00.000.json";
+    fs::write(dir.join("backups").join(name), fixture(0).to_string()).unwrap();
+
+    backup::read(name).unwrap();
+    backup::restore(name, StorageKind::Json).unwrap();
+    let written: Value = serde_json::from_str(&fs::read_to_string(dir.join("database.json")).unwrap()).unwrap();
+    assert_eq!(written["schema_version"], SCHEMA_VERSION);
+    let restored: Database = serde_json::from_value(written).unwrap();
+    let descriptions = restored.tasks_printeable().iter().map(|task| task.description.clone()).collect::<Vec<String>>();
+    assert_eq!(descriptions, vec!["Write the report", "Collect the numbers"]);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn sqlite_tables_of_older_versions_store_parent_uids() {
+    use pti::storage::sqlite::SqliteStorage;
+    let path = std::env::temp_dir().join(format!("pti-migration-test-{}.sqlite", std::process::id()));
+    let _ = fs::remove_file(&path);
+    // The tasks table as written before it had a version
+    rusqlite::Connection::open(&path).unwrap()
+        .execute_batch("CREATE TABLE tasks (id INTEGER PRIMARY KEY, category INTEGER NOT NULL, parent INTEGER, data TEXT NOT NULL);")
+        .unwrap();
+
+    let mut value = fixture(0);
+    migration::upgrade(&mut value).unwrap();
+    let database: Database = serde_json::from_value(value).unwrap();
+    SqliteStorage::open(&path).unwrap().save(&database).unwrap();
+
+    let connection = rusqlite::Connection::open(&path).unwrap();
+    let version: i64 = connection.query_row("PRAGMA user_version", [], |row| row.get(0)).unwrap();
+    assert_eq!(version, 1);
+    let column: String = connection.query_row("SELECT type FROM pragma_table_info('tasks') WHERE name = 'parent'", [], |row| row.get(0)).unwrap();
+    assert_eq!(column, "TEXT");
+    let parent: String = connection.query_row("SELECT parent FROM tasks WHERE parent IS NOT NULL", [], |row| row.get(0)).unwrap();
+    // "Collect the numbers" is below "Write the report"
+    assert_eq!(parent, database.tasks_printeable()[0].uid);
+    drop(connection);
+    let _ = fs::remove_file(&path);
+}
@@ -0,0 +1,210 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use pti::model::Database;
+use pti::quickadd;
+use pti::storage::Storage;
+use pti::storage::json::JsonStorage;
+use pti::sync::{self, Outcome};
+
+/// The storage directory is process-wide, so the machines take turns.
+static STORAGE_DIR: Mutex<()> = Mutex::new(());
+
+/// Two machines syncing through a bare repository, all in a temporary directory.
+struct Setup {
+    root: PathBuf,
+    remote: String,
+}
+
+/// A storage directory of its own, as on another computer.
+struct Machine {
+    dir: PathBuf,
+}
+
+fn git(dir: &Path, args: &[&str]) -> String {
+    let output = Command::new("git").arg("-C").arg(dir).args(args).output().expect("git is installed");
+    assert!(output.status.success(), "git {:?} failed: {}", args, String::from_utf8_lossy(&output.stderr));
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+impl Setup {
+    fn new(name: &str) -> Setup {
+        let root = env::temp_dir().join(format!("pti-sync-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        git(&root, &["init", "-q", "--bare", "remote.git"]);
+        let remote = root.join("remote.git").to_string_lossy().to_string();
+        Setup { root, remote }
+    }
+
+    /// A machine which starts syncing with `database`, or with nothing if `None`.
+    fn machine(&self, name: &str, database: Option<&Database>) -> Machine {
+        let machine = Machine { dir: self.root.join(name) };
+        fs::create_dir_all(&machine.dir).unwrap();
+        if let Some(database) = database {
+            machine.save(database);
+        }
+        machine.enter();
+        sync::init(Some(&self.remote)).unwrap();
+        machine
+    }
+
+    /// Machine `a` with one task, and `b` which started from what `a` pushed.
+    fn two_machines(&self) -> (Machine, Machine) {
+        let mut database = Database::example_db();
+        add_task(&mut database, "Write the report");
+        let a = self.machine("a", Some(&database));
+        assert!(matches!(a.sync(&self.remote), Outcome::Pushed));
+        let b = self.machine("b", None);
+        assert!(matches!(b.sync(&self.remote), Outcome::Merged(conflicts) if conflicts.is_empty()));
+        // The merge commit of b, so that both start from the same commit
+        assert!(matches!(a.sync(&self.remote), Outcome::FastForwarded));
+        (a, b)
+    }
+}
+
+impl Drop for Setup {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+impl Machine {
+    fn enter(&self) {
+        env::set_var("PTI_STORAGE_DIR", &self.dir);
+    }
+
+    fn storage(&self) -> JsonStorage {
+        JsonStorage::open(&self.dir.join("database.json"), &self.dir.join("database.lock")).unwrap()
+    }
+
+    fn load(&self) -> Database {
+        self.storage().load().unwrap().expect("Database is empty")
+    }
+
+    fn save(&self, database: &Database) {
+        self.storage().save(database).unwrap();
+    }
+
+    fn change(&self, change: impl FnOnce(&mut Database)) {
+        let mut database = self.load();
+        change(&mut database);
+        self.save(&database);
+    }
+
+    fn sync(&self, remote: &str) -> Outcome {
+        self.enter();
+        sync::sync(&mut self.storage(), remote).unwrap()
+    }
+
+    fn last_commit_message(&self) -> String {
+        git(&self.dir, &["log", "-1", "--format=%B"])
+    }
+}
+
+fn add_task(database: &mut Database, text: &str) -> u32 {
+    let parsed = quickadd::parse(text, database.categories(), NaiveDate::from_ymd_opt(2023, 6, 1).unwrap());
+    database.add_parsed_task(&parsed, None)
+}
+
+fn descriptions(database: &Database) -> Vec<String> {
+    let mut descriptions = database.tasks_printeable().iter().map(|task| task.description.clone()).collect::<Vec<String>>();
+    descriptions.sort();
+    descriptions
+}
+
+fn at(hour: u32, minute: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2023, 6, 1, hour, minute, 0).unwrap()
+}
+
+#[test]
+fn fast_forwards_when_only_one_side_changed() {
+    let _lock = STORAGE_DIR.lock().unwrap_or_else(|error| error.into_inner());
+    let setup = Setup::new("fast-forward");
+    let (a, b) = setup.two_machines();
+    a.change(|database| {
+        add_task(database, "Collect the numbers");
+    });
+    assert!(matches!(a.sync(&setup.remote), Outcome::Pushed));
+    assert!(matches!(b.sync(&setup.remote), Outcome::FastForwarded));
+    assert_eq!(descriptions(&b.load()), vec!["Collect the numbers", "Write the report"]);
+    assert!(matches!(b.sync(&setup.remote), Outcome::UpToDate));
+}
+
+#[test]
+fn tasks_added_on_both_sides_under_the_same_number_are_kept() {
+    let _lock = STORAGE_DIR.lock().unwrap_or_else(|error| error.into_inner());
+    let setup = Setup::new("adds");
+    let (a, b) = setup.two_machines();
+    let mut numbers = Vec::new();
+    a.change(|database| numbers.push(add_task(database, "Added on a")));
+    b.change(|database| numbers.push(add_task(database, "Added on b")));
+    assert_eq!(numbers[0], numbers[1]);
+
+    assert!(matches!(a.sync(&setup.remote), Outcome::Pushed));
+    assert!(matches!(b.sync(&setup.remote), Outcome::Merged(conflicts) if conflicts.is_empty()));
+    assert!(matches!(a.sync(&setup.remote), Outcome::FastForwarded));
+    let merged = a.load();
+    assert_eq!(descriptions(&merged), vec!["Added on a", "Added on b", "Write the report"]);
+    let mut ids = merged.task_ids();
+    ids.sort();
+    ids.dedup();
+    assert_eq!(ids.len(), 3);
+    assert_eq!(merged, b.load());
+}
+
+#[test]
+fn a_task_edited_on_one_side_and_deleted_on_the_other_is_kept() {
+    let _lock = STORAGE_DIR.lock().unwrap_or_else(|error| error.into_inner());
+    let setup = Setup::new("edit-delete");
+    let (a, b) = setup.two_machines();
+    let task = a.load().task_ids()[0];
+    a.change(|database| database.set_task_done(task, true));
+    b.change(|database| database.delete_task(task));
+
+    a.sync(&setup.remote);
+    match b.sync(&setup.remote) {
+        Outcome::Merged(conflicts) => assert_eq!(conflicts, vec!["task \"Write the report\": deleted on our side but changed on theirs, kept it"]),
+        _ => panic!("Both sides changed, expected a merge"),
+    }
+    assert!(b.load().task_done(task));
+}
+
+#[test]
+fn a_pomodoro_recorded_on_both_sides_counts_once() {
+    let _lock = STORAGE_DIR.lock().unwrap_or_else(|error| error.into_inner());
+    let setup = Setup::new("pomodoro");
+    let (a, b) = setup.two_machines();
+    let task = a.load().task_ids()[0];
+    a.change(|database| {
+        database.add_past_pomodoro(task, at(9, 0), at(9, 25));
+        database.add_past_pomodoro(task, at(10, 0), at(10, 25));
+    });
+    b.change(|database| database.add_past_pomodoro(task, at(9, 0), at(9, 25)));
+
+    a.sync(&setup.remote);
+    b.sync(&setup.remote);
+    let segments = b.load().segments_since(at(0, 0));
+    assert_eq!(segments.iter().map(|segment| segment.start_time).collect::<Vec<_>>(), vec![at(9, 0), at(10, 0)]);
+}
+
+#[test]
+fn a_field_changed_on_both_sides_is_reported_in_the_merge_commit() {
+    let _lock = STORAGE_DIR.lock().unwrap_or_else(|error| error.into_inner());
+    let setup = Setup::new("conflict");
+    let (a, b) = setup.two_machines();
+    a.change(|database| database.set_pomodoro_duration(30));
+    b.change(|database| database.set_pomodoro_duration(45));
+
+    a.sync(&setup.remote);
+    assert!(matches!(b.sync(&setup.remote), Outcome::Merged(conflicts) if conflicts.len() == 1));
+    assert_eq!(b.load().pomodoro_duration_minutes(), 45);
+    let message = b.last_commit_message();
+    assert!(message.contains("Conflicts, kept our side:"), "{}", message);
+    assert!(message.contains("settings: pomodoro duration changed on both sides"), "{}", message);
+    assert!(matches!(a.sync(&setup.remote), Outcome::FastForwarded));
+    assert_eq!(a.load().pomodoro_duration_minutes(), 45);
+}